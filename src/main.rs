@@ -1,35 +1,51 @@
-use std::sync::mpsc::{self, Receiver, Sender};
+// The codebase favours explicit `return` statements
+#![allow(clippy::needless_return)]
 
 mod simulation;
 mod utils;
 
-fn main() {
-    let (main_tx, main_rx): (
-        Sender<simulation::event::Event>,
-        Receiver<simulation::event::Event>,
-    ) = mpsc::channel();
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-    let mut router = simulation::router::new_router();
-    let router_tx = router.tx.clone();
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("compare") {
+        std::process::exit(simulation::compare::run(&args[2..]));
+    }
 
-    router.listeners.insert(
-        simulation::event::SIMULATION_FINISHED.to_string(),
-        vec![main_tx.clone()],
-    );
+    // Shared by every run of the process, since the handler can only be set once
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })
+    .expect("Error setting Ctrl-C handler");
 
-    let (metrics_collector_tx, metrics_collector_rx): (
-        Sender<simulation::event::Event>,
-        Receiver<simulation::event::Event>,
-    ) = mpsc::channel();
+    if args.get(1).map(String::as_str) == Some("replicate") {
+        std::process::exit(simulation::replication::run(&args[2..], running));
+    }
+    if args.get(1).map(String::as_str) == Some("sweep") {
+        std::process::exit(simulation::sweep::run(&args[2..], running));
+    }
+    if args.get(1).map(String::as_str) == Some("optimize") {
+        std::process::exit(simulation::optimizer::run(&args[2..], running));
+    }
+    if args.get(1).map(String::as_str) == Some("validate") {
+        std::process::exit(simulation::validation::run(&args[2..], running));
+    }
+    if args.get(1).map(String::as_str) == Some("benchmark") {
+        std::process::exit(simulation::benchmark::run(&args[2..], running));
+    }
 
-    simulation::event::ALL_EVENTS.iter().for_each(|event| {
-        let _ = router
-            .listeners
-            .insert(event.to_string(), vec![metrics_collector_tx.clone()]);
+    let (metrics_collector, metadata) = simulation::run_simulation(simulation::RunOptions {
+        parameters: simulation::config::default_parameters(),
+        seed: simulation::SEED.unwrap_or_else(rand::random),
+        duration: simulation::SIMULATION_DURATION,
+        running,
+        replication: false,
     });
-
-    simulation::spawn_router_thread(router);
-    simulation::spawn_metrics_collector_thread(router_tx.clone(), metrics_collector_rx);
-    simulation::spawn_bathroom_thread(router_tx.clone());
-    simulation::randomly_generate_person_threads(router_tx.clone(), main_rx);
+    simulation::report_run(&metrics_collector, metadata);
+    if !metrics_collector.invariant_violations.is_empty() {
+        std::process::exit(1);
+    }
 }
@@ -1,22 +1,44 @@
 pub mod bathroom;
+pub mod benchmark;
+pub mod bridge;
+pub mod channel;
+pub mod compare;
+pub mod config;
+pub mod consistency;
+pub mod controller;
 pub mod event;
+pub mod histogram;
+pub mod html_report;
+pub mod invariants;
+pub mod lock_free;
+pub mod locking;
 pub mod metrics_collector;
+pub mod optimizer;
 pub mod person;
+pub mod queueing;
+pub mod replication;
+pub mod report;
 pub mod router;
+pub mod sweep;
+pub mod timeseries;
+pub mod tracer;
+pub mod validation;
+pub mod warmup;
+pub mod watchdog;
 
 use rand::prelude::*;
-use std::fs::File;
+use std::collections::HashMap;
 use std::io::Write;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::sync::Arc;
+use std::sync::mpsc::TryRecvError;
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 use crate::simulation::event::new_event;
 
-use self::event::Event;
+use self::channel::{ChannelConfig, ChannelRegistry, Receiver, Sender};
 use self::person::Gender;
 
 const ENABLE_LOGGING: bool = false;
@@ -34,11 +56,217 @@ pub const MAX_PERSON_BATHROOM_SECONDS: u64 = MIN_PERSON_BATHROOM_SECONDS * 5;
 // Parameters for new persons to be generated and join either the female/male queue
 pub const PERSON_GENERATION_INTERVAL: Duration = Duration::from_secs(10);
 pub const PERSON_GENERATION_RATE: f64 = 0.3;
+// Probability of a new person being female
+pub const FEMALE_ARRIVAL_SHARE: f64 = 0.5;
+
+// Seed of the random arrivals (when, which gender and for how long people use the bathroom), a
+// random one is picked and recorded in the report when None. Threads are still scheduled by the
+// OS, so runs with the same seed see the same arrivals but may not unfold exactly alike
+pub const SEED: Option<u64> = None;
+
+// Simulated time after which people stop arriving and the simulation stops, None to run until
+// Ctrl-C is pressed. It is counted in rounds of PERSON_GENERATION_INTERVAL (see
+// `RunOptions::duration`)
+pub const SIMULATION_DURATION: Option<Duration> = None;
+
+// `cargo run -- replicate` runs this many independent replications (with seeds derived from SEED)
+// for this long in simulated time each, this many at a time (as many as there are CPUs when None)
+pub const REPLICATIONS: usize = 10;
+pub const REPLICATION_DURATION: Duration = Duration::from_secs(4 * 60 * 60);
+pub const PARALLEL_REPLICATIONS: Option<usize> = None;
+
+// `cargo run -- sweep` runs this many replications (with the same seeds, derived from SEED) of every
+// configuration drawn from the ranges below, for this long in simulated time each. Configurations
+// are either every combination of the ranges' values (`sweep::Sampling::Grid`) or a Latin
+// hypercube sample of them (`sweep::Sampling::LatinHypercube`). Thresholds are in simulated seconds
+pub const SWEEP_SAMPLING: sweep::Sampling = sweep::Sampling::Grid;
+pub const SWEEP_REPLICATIONS: usize = 3;
+pub const SWEEP_DURATION: Duration = Duration::from_secs(2 * 60 * 60);
+pub const SWEEP_BATHROOM_SIZE: sweep::Range = sweep::Range {
+    min: 4.0,
+    max: 12.0,
+    steps: 3,
+};
+pub const SWEEP_MAX_USE_TIME_THRESHOLD: sweep::Range = sweep::Range {
+    min: 60.0,
+    max: 600.0,
+    steps: 4,
+};
+pub const SWEEP_PERSON_GENERATION_RATE: sweep::Range = sweep::Range {
+    min: 0.1,
+    max: 0.5,
+    steps: 3,
+};
+
+// `cargo run -- optimize` searches for the max use time threshold (in simulated seconds) and max
+// uses per turn minimizing OPTIMIZER_OBJECTIVE, within the ranges below. Every point it evaluates
+// is run OPTIMIZER_REPLICATIONS times (with the same seeds, derived from SEED) for
+// OPTIMIZER_DURATION of simulated time, and scored by the objective's mean over them. Points are
+// either every combination of the ranges' values (`optimizer::Search::Grid`), drawn at random
+// (`optimizer::Search::Random`), or evolved (`optimizer::Search::Evolutionary`), mutating the
+// survivors of each generation by up to OPTIMIZER_MUTATION of each range
+pub const OPTIMIZER_SEARCH: optimizer::Search = optimizer::Search::Evolutionary {
+    generations: 5,
+    population: 8,
+};
+pub const OPTIMIZER_OBJECTIVE: optimizer::Objective = optimizer::p95_wait_with_fairness_penalty;
+// Simulated seconds of objective per simulated second of difference between genders' mean waits
+pub const OPTIMIZER_FAIRNESS_WEIGHT: f64 = 1.0;
+pub const OPTIMIZER_REPLICATIONS: usize = 3;
+pub const OPTIMIZER_DURATION: Duration = Duration::from_secs(2 * 60 * 60);
+pub const OPTIMIZER_MUTATION: f64 = 0.25;
+pub const OPTIMIZER_MAX_USE_TIME_THRESHOLD: sweep::Range = sweep::Range {
+    min: 60.0,
+    max: 1200.0,
+    steps: 5,
+};
+pub const OPTIMIZER_MAX_USES_PER_TURN: sweep::Range = sweep::Range {
+    min: 1.0,
+    max: 24.0,
+    steps: 4,
+};
+
+// `cargo run -- validate` compares the simulation of a bathroom of VALIDATION_BATHROOM_SIZE cabins
+// where only women arrive, so it never switches genders, with queueing theory's expectations (see
+// `queueing.rs`), over VALIDATION_REPLICATIONS replications of VALIDATION_DURATION of simulated
+// time. Simulated values are consistent with the expectations within VALIDATION_TOLERANCE (relative)
+// or their 95% confidence interval
+pub const VALIDATION_BATHROOM_SIZE: usize = 7;
+pub const VALIDATION_REPLICATIONS: usize = 5;
+pub const VALIDATION_DURATION: Duration = Duration::from_secs(4 * 60 * 60);
+pub const VALIDATION_TOLERANCE: f64 = 0.1;
+
+// `cargo run -- benchmark` runs each of BENCHMARK_IMPLEMENTATIONS in turn over BENCHMARK_REPLICATIONS
+// replications of BENCHMARK_DURATION of simulated time, each replication's seed being shared by
+// every implementation so they face the same arrivals (see `benchmark.rs`). People arrive at
+// BENCHMARK_PERSON_GENERATION_RATE instead of PERSON_GENERATION_RATE, for a heavier load
+pub const BENCHMARK_IMPLEMENTATIONS: &[config::Implementation] = &[
+    config::Implementation::MessagePassing,
+    config::Implementation::LockBased,
+    config::Implementation::LockFree,
+];
+pub const BENCHMARK_REPLICATIONS: usize = 3;
+pub const BENCHMARK_DURATION: Duration = Duration::from_secs(60 * 60);
+pub const BENCHMARK_PERSON_GENERATION_RATE: f64 = 0.6;
+
+// Start of each run left out of the statistics, since the bathroom starts empty: for a simulated
+// time (`warmup::WarmUp::SimulatedTime`), until a number of people were served
+// (`warmup::WarmUp::ServedPeople`), or until the queue lengths stabilise (`warmup::WarmUp::Automatic`).
+// Nothing is left out when None
+pub const WARM_UP: Option<warmup::WarmUp> = None;
+// The automatic warm-up ends once the average queue length over the last WARM_UP_DETECTOR_WINDOWS
+// windows of WARM_UP_DETECTOR_WINDOW (in simulated time) varies by at most
+// WARM_UP_DETECTOR_TOLERANCE of their mean
+pub const WARM_UP_DETECTOR_WINDOW: Duration = Duration::from_secs(10 * 60);
+pub const WARM_UP_DETECTOR_WINDOWS: usize = 3;
+pub const WARM_UP_DETECTOR_TOLERANCE: f64 = 0.2;
 
 // Bathroom constants
 pub const BATHROOM_SIZE: usize = 12;
 // Time the bathroom may be occupied by a single gender before switching
-pub const MAX_USE_TIME_THRESHOLD: Duration = Duration::from_secs(MAX_PERSON_BATHROOM_SECONDS * 1);
+pub const MAX_USE_TIME_THRESHOLD: Duration = Duration::from_secs(MAX_PERSON_BATHROOM_SECONDS);
+// Times the bathroom may be used by a single gender before switching, as many as there are cabins
+// when None
+pub const MAX_USES_PER_TURN: Option<u32> = None;
+// How people and the bathroom synchronize: through events exchanged with a bathroom actor, by
+// locking the bathroom's state themselves (see `locking.rs`), or through atomics (see
+// `lock_free.rs`). All of them emit the same events, so their reports compare
+pub const IMPLEMENTATION: config::Implementation = config::Implementation::MessagePassing;
+
+// People who waited longer than this (in simulated time) to enter the bathroom are counted as
+// starved in the fairness metrics
+pub const STARVATION_THRESHOLD: Duration = Duration::from_secs(30 * 60);
+
+// Relative difference allowed between both sides of Little's law (L = λW), which the report checks
+// for the queue and for the whole system along with the conservation of people (see
+// `consistency.rs`)
+pub const LITTLES_LAW_TOLERANCE: f64 = 0.05;
+
+// Where report files are written, and how they are named (a chrono format string, filled in with
+// the local time at which the simulation stopped). Each format of `REPORT_FORMATS` is written to a
+// file of its own extension, e.g. `2024-01-31_12-00-00-000.json`
+pub const REPORTS_DIRECTORY: &str = "./statistics_reports";
+pub const REPORT_FILENAME_PATTERN: &str = "%Y-%m-%d_%H-%M-%S-%3f";
+pub const REPORT_FORMATS: &[report::ReportFormat] = &[
+    report::ReportFormat::Json,
+    report::ReportFormat::Markdown,
+    report::ReportFormat::Html,
+];
+
+// Relative increase of a statistic over the baseline beyond which `cargo run -- compare` flags it
+// as a regression, unless overridden with `--tolerance`
+pub const COMPARE_TOLERANCE: f64 = 0.05;
+
+// Width (in simulated time) of the buckets of the time series exported as CSV alongside the
+// statistics report
+pub const TIME_SERIES_BUCKET: Duration = Duration::from_secs(60);
+
+// Channel bounds (a capacity of None means unbounded) and what happens to an event sent to a full
+// channel. Beware that blocking channels may deadlock actors that send to each other, and that
+// dropped events are lost to their receivers for good
+pub const ROUTER_CHANNEL: ChannelConfig = channel::UNBOUNDED;
+pub const BATHROOM_CHANNEL: ChannelConfig = channel::UNBOUNDED;
+pub const PERSON_CHANNEL: ChannelConfig = channel::UNBOUNDED;
+pub const METRICS_COLLECTOR_CHANNEL: ChannelConfig = channel::UNBOUNDED;
+
+// How statistics keep their measures: `Exact` keeps every single one, while `Streaming` only
+// counts them in a histogram of bounded size, approximating percentiles (see `histogram.rs`)
+pub const STATISTIC_MODE: metrics_collector::StatisticMode =
+    metrics_collector::StatisticMode::Exact;
+// Whether raw measures (in arrival and ascending order) are written to the statistics report
+pub const REPORT_RAW_MEASURES: bool = true;
+// Percentiles reported for every statistic, and how they are interpolated between measures
+pub const REPORTED_PERCENTILES: [f64; 8] = [10.0, 25.0, 50.0, 75.0, 90.0, 95.0, 99.0, 99.9];
+pub const PERCENTILE_INTERPOLATION: metrics_collector::Interpolation =
+    metrics_collector::Interpolation::Linear;
+
+// Whether every event is traced and exported as a Chrome trace-event JSON file alongside the
+// statistics report, to be opened in a trace viewer (e.g. chrome://tracing or Perfetto)
+pub const ENABLE_TRACING: bool = false;
+
+// Address where external processes may connect to publish arrivals and subscribe to events (see
+// `bridge.rs` for the protocol), e.g. `Some(bridge::BridgeAddress::Unix("/tmp/bathroom.sock"))` or
+// `Some(bridge::BridgeAddress::Tcp("127.0.0.1:7878"))`. The bridge is disabled when None
+pub const BRIDGE_ADDRESS: Option<bridge::BridgeAddress> = None;
+
+// Controller that retunes the bathroom's policy while the simulation runs (see `controller.rs`),
+// every CONTROLLER_INTERVAL of simulated time. When more than CONTROLLER_QUEUE_THRESHOLD people are
+// waiting, it shortens the max use time threshold by CONTROLLER_ADJUSTMENT (down to
+// CONTROLLER_MIN_MAX_USE_TIME_THRESHOLD), and when a gender's mean wait (smoothed by
+// CONTROLLER_SMOOTHING) is over CONTROLLER_WAIT_RATIO times the other's, it lowers the max uses per
+// turn by CONTROLLER_ADJUSTMENT. Otherwise, both relax back towards their configured values
+pub const ENABLE_CONTROLLER: bool = false;
+pub const CONTROLLER_INTERVAL: Duration = Duration::from_secs(5 * 60);
+pub const CONTROLLER_QUEUE_THRESHOLD: usize = 10;
+pub const CONTROLLER_WAIT_RATIO: f64 = 2.0;
+pub const CONTROLLER_ADJUSTMENT: f64 = 0.75;
+pub const CONTROLLER_MIN_MAX_USE_TIME_THRESHOLD: Duration = Duration::from_secs(60);
+pub const CONTROLLER_SMOOTHING: f64 = 0.2;
+
+// Invariant monitor that checks every event against the safety properties of the bathroom (see
+// `invariants.rs`): no mixed genders nor more occupants than cabins, nobody in two places at once,
+// and nobody entering without having queued. It runs in replications too, and a single run exits
+// with a non-zero status when it found a violation
+pub const ENABLE_INVARIANT_MONITOR: bool = true;
+
+// Watchdog that checks the liveness of the simulation every WATCHDOG_CHECK_INTERVAL (in simulated
+// time, see `watchdog.rs`): nobody waits longer than WATCHDOG_MAX_WAIT, the bathroom switches within
+// WATCHDOG_SWITCH_DELAY once the other gender waits and every cabin is free, and actors, which send
+// a heartbeat every WATCHDOG_HEARTBEAT_INTERVAL, don't go silent for longer than
+// WATCHDOG_HEARTBEAT_TIMEOUT
+pub const ENABLE_WATCHDOG: bool = true;
+pub const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+pub const WATCHDOG_MAX_WAIT: Duration = Duration::from_secs(60 * 60);
+pub const WATCHDOG_SWITCH_DELAY: Duration = Duration::from_secs(60);
+pub const WATCHDOG_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+pub const WATCHDOG_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// Monitor that periodically asks the bathroom for its queue lengths, how often (in simulated time)
+// it does so, and how long (in real time) it waits for each reply. It logs what it learns, so it
+// only prints with ENABLE_LOGGING
+pub const ENABLE_MONITOR: bool = false;
+pub const MONITOR_QUERY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+pub const MONITOR_REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
 
 pub fn timestamp() -> chrono::format::DelayedFormat<chrono::format::StrftimeItems<'static>> {
     return chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f");
@@ -54,26 +282,42 @@ pub fn wait(d: Duration) {
     thread::sleep(d.div_f64(TIME_SCALE));
 }
 
-pub fn spawn_person_thread(router_tx: Sender<Event>, gender: Gender) -> JoinHandle<()> {
-    let (tx_person, rx_person): (Sender<Event>, Receiver<Event>) = mpsc::channel();
+// How long a new person will stay in the bathroom
+pub fn random_bathroom_time<R: Rng>(rand: &mut R) -> Duration {
+    return Duration::from_secs(
+        rand.gen_range(MIN_PERSON_BATHROOM_SECONDS..MAX_PERSON_BATHROOM_SECONDS),
+    );
+}
+
+// Brings a new person, who will stay in the bathroom for the given time, into the simulation
+pub type PersonSpawner = Arc<dyn Fn(Gender, Duration) + Send + Sync>;
+
+pub fn spawn_person_thread(
+    router_tx: Sender,
+    channels: &ChannelRegistry,
+    gender: Gender,
+    bathroom_time: Duration,
+) -> JoinHandle<()> {
+    let (tx_person, rx_person) = channel::new_channel(channels, "person", PERSON_CHANNEL);
     let mut person = person::new_person(gender);
     log(format!(
         "Person {} of gender {} spawned!",
         person.id, person.gender
     ));
     router_tx
-        .send(event::new_creation_event(
-            event::NEW_PERSON.to_string(),
-            person.id,
-            None,
-            tx_person.clone(),
-            Some(person.clone()),
-        ))
+        .send(
+            event::new_creation_event(
+                event::NEW_PERSON.to_string(),
+                person.id,
+                None,
+                tx_person.clone(),
+                Some(person.clone()),
+            )
+            .with_actor("person"),
+        )
         .unwrap();
 
     let person_t = thread::spawn(move || loop {
-        let mut rand = rand::thread_rng();
-
         match &rx_person.try_recv() {
             Ok(msg) => match msg.name.as_str() {
                 event::PERSON_JOINED_THE_QUEUE => {
@@ -82,83 +326,98 @@ pub fn spawn_person_thread(router_tx: Sender<Event>, gender: Gender) -> JoinHand
                 event::PERSON_ENTERED_THE_BATHROOM => {
                     person.entered_bathroom_at =
                         msg.person_snapshot.as_ref().unwrap().entered_bathroom_at;
-                    wait(Duration::new(
-                        rand.gen_range(MIN_PERSON_BATHROOM_SECONDS..MAX_PERSON_BATHROOM_SECONDS),
-                        0,
-                    ));
-                    let _ = router_tx
-                        .send(event::new_event(
+                    wait(bathroom_time);
+                    let _ = router_tx.send(
+                        event::new_event(
                             event::PERSON_FINISHED_USING_BATHROOM.to_string(),
                             person.id,
                             None,
                             Some(person.clone()),
                             None,
-                        ))
-                        .unwrap();
-                }
-                event::PERSON_LEFT_THE_BATHROOM => {
-                    person.left_bathroom_at =
-                        msg.person_snapshot.as_ref().unwrap().left_bathroom_at;
-                    break;
+                        )
+                        .caused_by(msg),
+                    );
                 }
-                &_ => todo!(),
+                event::PERSON_LEFT_THE_BATHROOM => break,
+                // e.g. a request addressed to the person by mistake
+                &_ => log(format!(
+                    "Person {} ignored unexpected event {}",
+                    person.id, msg.name
+                )),
             },
-            Err(_) => wait(RX_POLLING_WAIT),
+            // The router is gone, the simulation is over
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => wait(RX_POLLING_WAIT),
         };
     });
 
     return person_t;
 }
 
-pub fn spawn_bathroom_thread(router_tx: Sender<Event>) {
-    let _ = thread::spawn(move || {
-        log("Bathroom spawned!".to_string());
-        let mut bathroom = bathroom::new_bathroom(Gender::Female);
-        let (tx_bathroom, rx_bathroom): (Sender<Event>, Receiver<Event>) = mpsc::channel();
+// A quiet bathroom does not print its state whenever it changes. The bathroom is registered with
+// the router before returning, so that it doesn't miss people spawned right after
+pub fn spawn_bathroom_thread(
+    router_tx: Sender,
+    channels: ChannelRegistry,
+    verbose: bool,
+    parameters: config::Parameters,
+) {
+    let mut bathroom = bathroom::new_bathroom(Gender::Female, &parameters);
+    bathroom.verbose = verbose;
+    let (tx_bathroom, rx_bathroom) = channel::new_channel(&channels, "bathroom", BATHROOM_CHANNEL);
 
-        router_tx
-            .send(event::new_creation_event(
+    router_tx
+        .send(
+            event::new_creation_event(
                 event::NEW_BATHROOM.to_string(),
                 bathroom.id,
                 None,
-                tx_bathroom.clone(),
+                tx_bathroom,
                 None,
-            ))
-            .unwrap();
+            )
+            .with_actor("bathroom"),
+        )
+        .unwrap();
 
+    let _ = thread::spawn(move || {
+        log("Bathroom spawned!".to_string());
         let mut previous_bathroom_state: bathroom::Bathroom;
+        // Id of the PERSON_JOINED_THE_QUEUE event of each queued person, the parent of the event
+        // emitted when they enter the bathroom
+        let mut joined_queue_event_ids: HashMap<Uuid, Uuid> = HashMap::new();
+        let mut heartbeat = watchdog::new_heartbeat("bathroom", bathroom.id);
 
         loop {
+            heartbeat.beat(&router_tx);
             previous_bathroom_state = bathroom.clone();
 
-            if bathroom.should_switch_genders() {
+            if let Some(switch_rule) = bathroom.should_switch_genders() {
                 bathroom.switch_genders();
-                router_tx
-                    .send(event::new_event(
-                        event::BATHROOM_SWITCHED_GENDERS.to_string(),
-                        bathroom.id,
-                        None,
-                        None,
-                        Some(previous_bathroom_state.clone()),
-                    ))
-                    .unwrap();
+                let mut switched_genders_event = event::new_event(
+                    event::BATHROOM_SWITCHED_GENDERS.to_string(),
+                    bathroom.id,
+                    None,
+                    None,
+                    Some(previous_bathroom_state.clone()),
+                );
+                switched_genders_event.switch_rule = Some(switch_rule);
+                let _ = router_tx.send(switched_genders_event);
                 bathroom.display();
             }
 
-            match bathroom.allocate_cabin(bathroom.allowed_gender) {
-                Some(person) => {
-                    log(format!("Person {} entered the bathroom", person.id));
-                    let _ = router_tx
-                        .send(event::new_event(
-                            event::PERSON_ENTERED_THE_BATHROOM.to_string(),
-                            bathroom.id,
-                            Some(person.id),
-                            Some(person),
-                            Some(bathroom.clone()),
-                        ))
-                        .unwrap();
-                }
-                None => (),
+            if let Some(person) = bathroom.allocate_cabin(bathroom.allowed_gender) {
+                log(format!("Person {} entered the bathroom", person.id));
+                let joined_queue_event_id = joined_queue_event_ids.remove(&person.id).unwrap();
+                let _ = router_tx.send(
+                    event::new_event(
+                        event::PERSON_ENTERED_THE_BATHROOM.to_string(),
+                        bathroom.id,
+                        Some(person.id),
+                        Some(person),
+                        Some(bathroom.clone()),
+                    )
+                    .with_parent(joined_queue_event_id),
+                );
             }
 
             match &rx_bathroom.try_recv() {
@@ -171,13 +430,16 @@ pub fn spawn_bathroom_thread(router_tx: Sender<Event>) {
                             "Person {} joined the {} queue",
                             person_snapshot.id, person_snapshot.gender
                         ));
-                        let _ = router_tx.send(event::new_event(
+                        let joined_queue_event = event::new_event(
                             event::PERSON_JOINED_THE_QUEUE.to_string(),
                             bathroom.id,
                             Some(msg.producer_id),
                             Some(person_snapshot),
                             Some(bathroom.clone()),
-                        ));
+                        )
+                        .caused_by(msg);
+                        joined_queue_event_ids.insert(msg.producer_id, joined_queue_event.id);
+                        let _ = router_tx.send(joined_queue_event);
                     }
                     event::PERSON_FINISHED_USING_BATHROOM => {
                         let mut person_snapshot = msg.person_snapshot.to_owned().unwrap();
@@ -187,17 +449,43 @@ pub fn spawn_bathroom_thread(router_tx: Sender<Event>) {
                         ));
                         bathroom.free_cabin(person_snapshot.id);
                         person_snapshot.left_bathroom_at = Some(Instant::now());
-                        let _ = router_tx.send(event::new_event(
-                            event::PERSON_LEFT_THE_BATHROOM.to_string(),
+                        let _ = router_tx.send(
+                            event::new_event(
+                                event::PERSON_LEFT_THE_BATHROOM.to_string(),
+                                bathroom.id,
+                                Some(msg.producer_id),
+                                Some(person_snapshot),
+                                Some(bathroom.clone()),
+                            )
+                            .caused_by(msg),
+                        );
+                    }
+                    event::BATHROOM_STATE_REQUESTED => {
+                        let _ = router_tx.send(event::new_reply_event(
+                            event::BATHROOM_STATE_REPLIED.to_string(),
                             bathroom.id,
-                            Some(msg.producer_id),
-                            Some(person_snapshot),
+                            msg,
+                            None,
                             Some(bathroom.clone()),
                         ));
                     }
-                    &_ => todo!(),
+                    event::BATHROOM_POLICY_UPDATED => {
+                        if let Some(policy) = msg.policy {
+                            bathroom.log(format!(
+                                "Policy updated: max use time threshold {:?}, max uses per turn {}",
+                                policy.max_use_time_threshold, policy.max_uses_per_turn
+                            ));
+                            bathroom.policy = policy;
+                        }
+                    }
+                    &_ => log(format!(
+                        "Bathroom {} ignored unexpected event {}",
+                        bathroom.id, msg.name
+                    )),
                 },
-                Err(_) => wait(RX_POLLING_WAIT),
+                // The router is gone, the simulation is over
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => wait(RX_POLLING_WAIT),
             };
         }
     });
@@ -208,11 +496,17 @@ pub fn spawn_router_thread(mut router: router::Router) -> JoinHandle<()> {
         log("Router spawned!".to_string());
 
         let bathroom_interesting_events =
-            vec![event::NEW_PERSON, event::PERSON_FINISHED_USING_BATHROOM];
+            [event::NEW_PERSON, event::PERSON_FINISHED_USING_BATHROOM];
 
         loop {
             match router.rx.try_recv() {
-                Ok(ref msg) => {
+                Ok(mut msg) => {
+                    let received_at = Instant::now();
+                    msg.routed_at = Some(received_at);
+                    // The event just taken counts towards the depth the router was facing
+                    let inbox_depth = router.rx.len() + 1;
+                    let mut fan_out: u64 = 0;
+
                     match msg.name.as_str() {
                         event::NEW_BATHROOM => {
                             log(format!(
@@ -220,11 +514,23 @@ pub fn spawn_router_thread(mut router: router::Router) -> JoinHandle<()> {
                                 msg.producer_id
                             ));
                             bathroom_interesting_events.iter().for_each(|event| {
-                                let listeners =
-                                    &mut router.listeners.get_mut(&event.to_string()).unwrap();
-                                let _ =
-                                    listeners.push(msg.producer_sender.as_ref().unwrap().clone());
-                            })
+                                let listeners = &mut router.listeners.get_mut(*event).unwrap();
+                                listeners.push(msg.producer_sender.as_ref().unwrap().clone());
+                            });
+                            let _ = router.outbox.insert(
+                                msg.producer_id,
+                                msg.producer_sender.as_ref().unwrap().clone(),
+                            );
+                        }
+                        event::NEW_MONITOR => {
+                            log(format!(
+                                "Registering monitor {} in the router",
+                                msg.producer_id
+                            ));
+                            let _ = router.outbox.insert(
+                                msg.producer_id,
+                                msg.producer_sender.as_ref().unwrap().clone(),
+                            );
                         }
                         event::NEW_PERSON => {
                             log(format!(
@@ -239,18 +545,36 @@ pub fn spawn_router_thread(mut router: router::Router) -> JoinHandle<()> {
                         &_ => (),
                     }
 
-                    if msg.destination_id.is_some() {
-                        let rx = router.outbox.get(&msg.destination_id.unwrap()).unwrap();
-                        rx.send(msg.clone()).unwrap();
+                    // Actors that already stopped (e.g. the metrics collector while the
+                    // simulation shuts down) are skipped, and events for actors that never
+                    // registered are dropped
+                    if let Some(destination_id) = msg.destination_id {
+                        match router.outbox.get(&destination_id) {
+                            Some(rx) => fan_out += rx.send(msg.clone()).is_ok() as u64,
+                            None => log(format!(
+                                "Dropping {} for unknown actor {}",
+                                msg.name, destination_id
+                            )),
+                        }
                     }
 
-                    match router.listeners.get(&msg.name) {
-                        Some(interested_parties) => {
-                            interested_parties
-                                .iter()
-                                .for_each(|tx| tx.send(msg.clone()).unwrap());
-                        }
-                        None => (),
+                    if let Some(interested_parties) = router.listeners.get(&msg.name) {
+                        fan_out += interested_parties
+                            .iter()
+                            .filter(|tx| tx.send(msg.clone()).is_ok())
+                            .count() as u64;
+                    }
+
+                    router.statistics.lock().unwrap().add_routed_event(
+                        &msg.name,
+                        fan_out,
+                        received_at.duration_since(msg.sent_at),
+                        inbox_depth,
+                    );
+
+                    if msg.name == event::SIMULATION_FINISHED {
+                        // Dropping the router disconnects every actor's inbox, so they stop too
+                        break;
                     }
                 }
                 Err(_) => wait(RX_POLLING_WAIT),
@@ -259,150 +583,782 @@ pub fn spawn_router_thread(mut router: router::Router) -> JoinHandle<()> {
     })
 }
 
-pub fn spawn_metrics_collector_thread(
-    router_tx: Sender<Event>,
-    metrics_collector_rx: Receiver<Event>,
+// Retunes the bathroom's policy from live metrics every CONTROLLER_INTERVAL (in simulated time). A
+// quiet controller does not print the policies it sets
+pub fn spawn_controller_thread(
+    router_tx: Sender,
+    controller_rx: Receiver,
+    parameters: config::Parameters,
+    verbose: bool,
 ) {
-    let mut metrics_collector = metrics_collector::new_metrics_collector();
+    let _ = thread::spawn(move || {
+        let controller_id = Uuid::new_v4();
+        let mut controller = controller::new_controller(bathroom::new_policy(&parameters));
+        // The controller learns which bathroom to retune from its creation event
+        let mut bathroom_id = None;
+        let mut decided_at = Instant::now();
+        let mut heartbeat = watchdog::new_heartbeat("controller", controller_id);
 
-    thread::spawn(move || loop {
-        match &metrics_collector_rx.try_recv() {
-            Ok(msg) => match msg.name.as_str() {
-                event::BATHROOM_SWITCHED_GENDERS => {
-                    let bathroom_snapshot = msg.bathroom_snapshot.as_ref().unwrap();
-                    let time_since_last_gender_change: u64;
-
-                    match bathroom_snapshot.first_user_entered_at {
-                        Some(instant) => {
-                            time_since_last_gender_change =
-                                instant.elapsed().mul_f64(TIME_SCALE).as_secs()
-                        }
-                        None => continue,
-                    };
-
-                    metrics_collector.gender_switches += 1;
-                    match bathroom_snapshot.allowed_gender {
-                        Gender::Male => {
-                            metrics_collector
-                                .time_bathroom_was_male
-                                .add_measure(time_since_last_gender_change);
-                            metrics_collector.male_queue_size.add_measure(
-                                bathroom_snapshot.male_queue.len().try_into().unwrap(),
-                            );
-                        }
-                        Gender::Female => {
-                            metrics_collector
-                                .time_bathroom_was_female
-                                .add_measure(time_since_last_gender_change);
-                            metrics_collector.female_queue_size.add_measure(
-                                bathroom_snapshot.male_queue.len().try_into().unwrap(),
-                            );
-                        }
+        loop {
+            heartbeat.beat(&router_tx);
+            match controller_rx.try_recv() {
+                Ok(msg) => {
+                    if msg.name == event::NEW_BATHROOM {
+                        bathroom_id = Some(msg.producer_id);
                     }
-                }
-                event::PERSON_ENTERED_THE_BATHROOM => {
-                    let person_snapshot = msg.person_snapshot.as_ref().unwrap();
-                    let personal_total_wait_time = person_snapshot
-                        .entered_bathroom_at
-                        .unwrap()
-                        .duration_since(person_snapshot.joined_queue_at.unwrap())
-                        .mul_f64(TIME_SCALE)
-                        .as_secs();
-
-                    match person_snapshot.gender {
-                        Gender::Male => metrics_collector
-                            .male_personal_total_wait_time
-                            .add_measure(personal_total_wait_time),
-                        Gender::Female => metrics_collector
-                            .female_personal_total_wait_time
-                            .add_measure(personal_total_wait_time),
+                    if let Some(bathroom_snapshot) = &msg.bathroom_snapshot {
+                        controller.record_bathroom_state(bathroom_snapshot);
                     }
-                }
-                event::PERSON_LEFT_THE_BATHROOM => {
-                    let person_snapshot = msg.person_snapshot.as_ref().unwrap();
-                    let personal_total_time_spent = person_snapshot
-                        .left_bathroom_at
-                        .unwrap()
-                        .duration_since(person_snapshot.joined_queue_at.unwrap())
-                        .mul_f64(TIME_SCALE)
-                        .as_secs();
-
-                    match person_snapshot.gender {
-                        Gender::Male => metrics_collector
-                            .male_personal_total_time_spent
-                            .add_measure(personal_total_time_spent),
-                        Gender::Female => metrics_collector
-                            .female_personal_total_time_spent
-                            .add_measure(personal_total_time_spent),
+                    if msg.name == event::PERSON_ENTERED_THE_BATHROOM {
+                        let person_snapshot = msg.person_snapshot.as_ref().unwrap();
+                        let wait = person_snapshot
+                            .entered_bathroom_at
+                            .unwrap()
+                            .duration_since(person_snapshot.joined_queue_at.unwrap())
+                            .mul_f64(TIME_SCALE)
+                            .as_secs_f64();
+                        controller.record_wait(person_snapshot.gender, wait);
                     }
                 }
-                event::SIMULATION_STOPPED => {
-                    metrics_collector.update_statistics();
-                    let statistics_report = serde_json::to_string(&metrics_collector).unwrap();
-                    let filename = format!(
-                        "./statistics_reports/{}.json",
-                        chrono::offset::Local::now().format("%Y-%m-%d_%H-%M-%S-%3f")
+                // The router is gone, the simulation is over
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => wait(RX_POLLING_WAIT),
+            };
+
+            if decided_at.elapsed().mul_f64(TIME_SCALE) < CONTROLLER_INTERVAL {
+                continue;
+            }
+            decided_at = Instant::now();
+
+            let bathroom_id = match bathroom_id {
+                Some(bathroom_id) => bathroom_id,
+                None => continue,
+            };
+            if let Some(policy) = controller.next_policy() {
+                if verbose {
+                    println!(
+                        "[{}] 🎛️ Controller: max use time threshold {:?}, max uses per turn {} ({} people waiting, mean waits {:.0}s male / {:.0}s female)",
+                        timestamp(),
+                        policy.max_use_time_threshold,
+                        policy.max_uses_per_turn,
+                        controller.male_queue_length + controller.female_queue_length,
+                        controller.male_mean_wait,
+                        controller.female_mean_wait
                     );
-                    match File::create(&filename) {
-                        Ok(mut file) => {
-                            file.write(&statistics_report.as_bytes()).unwrap();
-                            println!("📄 Statistics report JSON file created successfully! File located at {}", filename);
-                        }
-                        Err(_) => println!("💩 Could not create statistics report file!"),
-                    };
+                }
+                let mut policy_updated_event = event::new_event(
+                    event::BATHROOM_POLICY_UPDATED.to_string(),
+                    controller_id,
+                    Some(bathroom_id),
+                    None,
+                    None,
+                )
+                .with_actor("controller");
+                policy_updated_event.policy = Some(policy);
+                let _ = router_tx.send(policy_updated_event);
+            }
+        }
+    });
+}
 
-                    router_tx
-                        .send(new_event(
-                            event::SIMULATION_FINISHED.to_string(),
-                            Uuid::new_v4(),
-                            None,
-                            None,
+// Checks every event against the safety properties of the bathroom, emitting an INVARIANT_VIOLATED
+// event for each violation found. Returns every violation once the simulation stopped. A quiet
+// invariant monitor does not print the violations it finds
+pub fn spawn_invariant_monitor_thread(
+    router_tx: Sender,
+    invariant_monitor_rx: Receiver,
+    parameters: config::Parameters,
+    verbose: bool,
+) -> JoinHandle<Vec<String>> {
+    thread::spawn(move || {
+        let invariant_monitor_id = Uuid::new_v4();
+        let mut invariant_monitor = invariants::new_invariant_monitor(parameters.bathroom_size);
+        let mut heartbeat = watchdog::new_heartbeat("invariant monitor", invariant_monitor_id);
+
+        loop {
+            heartbeat.beat(&router_tx);
+            match invariant_monitor_rx.try_recv() {
+                Ok(msg) => {
+                    for violation in invariant_monitor.check(&msg) {
+                        if verbose {
+                            println!("[{}] 🚨 Invariant violated: {}", timestamp(), violation);
+                        }
+                        let mut invariant_violated_event = event::new_event(
+                            event::INVARIANT_VIOLATED.to_string(),
+                            invariant_monitor_id,
                             None,
-                        ))
-                        .unwrap();
-                    break;
+                            msg.person_snapshot.clone(),
+                            msg.bathroom_snapshot.clone(),
+                        )
+                        .caused_by(&msg)
+                        .with_actor("invariant monitor");
+                        invariant_violated_event.violation = Some(violation);
+                        let _ = router_tx.send(invariant_violated_event);
+                    }
+
+                    // Events routed after the simulation stopped aren't checked
+                    if msg.name == event::SIMULATION_STOPPED {
+                        break;
+                    }
                 }
-                &_ => (),
-            },
-            Err(_) => wait(RX_POLLING_WAIT),
+                // The router is gone, the simulation is over
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => wait(RX_POLLING_WAIT),
+            };
+        }
+
+        return invariant_monitor.violations;
+    })
+}
+
+// Checks the liveness of the simulation every WATCHDOG_CHECK_INTERVAL (in simulated time), emitting
+// a LIVENESS_CHECK_FAILED event carrying the latest state of the bathroom for each failure found.
+// Returns every failure once the simulation stopped. A quiet watchdog does not print the failures
+// it finds, nor the diagnostic dump of the bathroom that follows them
+pub fn spawn_watchdog_thread(
+    router_tx: Sender,
+    watchdog_rx: Receiver,
+    verbose: bool,
+) -> JoinHandle<Vec<String>> {
+    thread::spawn(move || {
+        let watchdog_id = Uuid::new_v4();
+        let mut watchdog = watchdog::new_watchdog();
+        let mut checked_at = Instant::now();
+
+        loop {
+            match watchdog_rx.try_recv() {
+                Ok(msg) => {
+                    watchdog.record(&msg);
+
+                    // Actors may stop beating once the simulation stopped
+                    if msg.name == event::SIMULATION_STOPPED {
+                        break;
+                    }
+                }
+                // The router is gone, the simulation is over
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => wait(RX_POLLING_WAIT),
+            };
+
+            if checked_at.elapsed().mul_f64(TIME_SCALE) < WATCHDOG_CHECK_INTERVAL {
+                continue;
+            }
+            checked_at = Instant::now();
+
+            let failures = watchdog.check(checked_at);
+            if failures.is_empty() {
+                continue;
+            }
+            if verbose {
+                failures
+                    .iter()
+                    .for_each(|failure| println!("[{}] 🐕 Watchdog: {}", timestamp(), failure));
+                println!("{}", watchdog.dump(checked_at));
+            }
+            for failure in failures {
+                let mut liveness_check_failed_event = event::new_event(
+                    event::LIVENESS_CHECK_FAILED.to_string(),
+                    watchdog_id,
+                    None,
+                    None,
+                    watchdog.bathroom.clone(),
+                )
+                .with_actor("watchdog");
+                liveness_check_failed_event.violation = Some(failure);
+                let _ = router_tx.send(liveness_check_failed_event);
+            }
+        }
+
+        return watchdog.failures;
+    })
+}
+
+pub fn spawn_monitor_thread(router_tx: Sender, monitor_rx: Receiver, channels: ChannelRegistry) {
+    let _ = thread::spawn(move || {
+        let monitor_id = Uuid::new_v4();
+        let (tx_replies, rx_replies) =
+            channel::new_channel(&channels, "monitor", channel::UNBOUNDED);
+
+        router_tx
+            .send(
+                event::new_creation_event(
+                    event::NEW_MONITOR.to_string(),
+                    monitor_id,
+                    None,
+                    tx_replies,
+                    None,
+                )
+                .with_actor("monitor"),
+            )
+            .unwrap();
+
+        // The monitor learns which bathroom to query from its creation event
+        let bathroom_id = loop {
+            match monitor_rx.recv() {
+                Ok(msg) if msg.name == event::NEW_BATHROOM => break msg.producer_id,
+                Ok(_) => (),
+                Err(_) => return,
+            }
+        };
+
+        loop {
+            wait(MONITOR_QUERY_INTERVAL);
+
+            let request = event::new_request_event(
+                event::BATHROOM_STATE_REQUESTED.to_string(),
+                monitor_id,
+                bathroom_id,
+            );
+
+            match router::request(&router_tx, &rx_replies, request, MONITOR_REQUEST_TIMEOUT) {
+                Ok(reply) => match reply.bathroom_snapshot.as_ref() {
+                    Some(bathroom_snapshot) => log(format!(
+                        "🔎 Monitor: {} people in the female queue, {} people in the male queue",
+                        bathroom_snapshot.female_queue.len(),
+                        bathroom_snapshot.male_queue.len()
+                    )),
+                    None => log(format!(
+                        "🔎 Monitor: bathroom {} replied without its state",
+                        bathroom_id
+                    )),
+                },
+                Err(router::RequestError::Timeout) => log(format!(
+                    "🔎 Monitor: bathroom {} did not reply within {:?}",
+                    bathroom_id, MONITOR_REQUEST_TIMEOUT
+                )),
+                Err(router::RequestError::Disconnected) => break,
+            }
         }
     });
 }
 
-pub fn randomly_generate_person_threads(router_tx: Sender<Event>, main_rx: Receiver<Event>) {
-    let mut rand = rand::thread_rng();
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
+pub fn spawn_tracer_thread(tracer_rx: Receiver) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut tracer = tracer::new_tracer();
+
+        while let Ok(msg) = tracer_rx.recv() {
+            tracer.record(&msg);
+
+            if msg.name == event::SIMULATION_STOPPED {
+                break;
+            }
+        }
+
+        match report::create_report_file(&report::new_report_path(), "trace.json") {
+            Ok((filename, mut file)) => {
+                file.write_all(tracer.to_chrome_trace().to_string().as_bytes())
+                    .unwrap();
+                println!(
+                    "🧵 Trace file created successfully! File located at {}",
+                    filename
+                );
+            }
+            Err(_) => println!("💩 Could not create trace file!"),
+        };
+    })
+}
+
+pub fn spawn_metrics_collector_thread(
+    router_tx: Sender,
+    metrics_collector_rx: Receiver,
+    channels: ChannelRegistry,
+    router_statistics: Arc<Mutex<router::RouterStatistics>>,
+    parameters: config::Parameters,
+    seed: u64,
+) -> JoinHandle<(metrics_collector::MetricsCollector, report::Metadata)> {
+    let mut metrics_collector = metrics_collector::new_metrics_collector();
+    let started_at = chrono::offset::Local::now();
+    let mut heartbeat = watchdog::new_heartbeat("metrics collector", Uuid::new_v4());
+
+    thread::spawn(move || loop {
+        heartbeat.beat(&router_tx);
+        match &metrics_collector_rx.try_recv() {
+            Ok(msg) => {
+                metrics_collector.update_warm_up(msg.sent_at);
+                match msg.name.as_str() {
+                    event::PERSON_JOINED_THE_QUEUE => {
+                        let bathroom_snapshot = msg.bathroom_snapshot.as_ref().unwrap();
+                        metrics_collector.record_arrival(msg.sent_at);
+                        metrics_collector.record_bathroom_state(
+                            bathroom_snapshot,
+                            bathroom_snapshot.allowed_gender,
+                            msg.sent_at,
+                        );
+                    }
+                    event::BATHROOM_POLICY_UPDATED => metrics_collector.policy_updates += 1,
+                    event::BATHROOM_SWITCHED_GENDERS => {
+                        let bathroom_snapshot = msg.bathroom_snapshot.as_ref().unwrap();
+                        metrics_collector.record_bathroom_state(
+                            bathroom_snapshot,
+                            bathroom_snapshot.allowed_gender.opposite(),
+                            msg.sent_at,
+                        );
+                        *metrics_collector
+                            .switches_by_rule
+                            .entry(format!("{:?}", msg.switch_rule.unwrap()))
+                            .or_insert(0) += 1;
+
+                        let time_since_last_gender_change: f64 = match bathroom_snapshot
+                            .first_user_entered_at
+                        {
+                            Some(instant) => instant.elapsed().mul_f64(TIME_SCALE).as_secs_f64(),
+                            None => continue,
+                        };
+
+                        metrics_collector.gender_switches += 1;
+                        match bathroom_snapshot.allowed_gender {
+                            Gender::Male => {
+                                metrics_collector
+                                    .time_bathroom_was_male
+                                    .add_measure(time_since_last_gender_change);
+                                metrics_collector
+                                    .male_queue_size
+                                    .add_measure(bathroom_snapshot.male_queue.len() as f64);
+                            }
+                            Gender::Female => {
+                                metrics_collector
+                                    .time_bathroom_was_female
+                                    .add_measure(time_since_last_gender_change);
+                                metrics_collector
+                                    .female_queue_size
+                                    .add_measure(bathroom_snapshot.female_queue.len() as f64);
+                            }
+                        }
+                    }
+                    event::PERSON_ENTERED_THE_BATHROOM => {
+                        let bathroom_snapshot = msg.bathroom_snapshot.as_ref().unwrap();
+                        metrics_collector.record_bathroom_state(
+                            bathroom_snapshot,
+                            bathroom_snapshot.allowed_gender,
+                            msg.sent_at,
+                        );
+
+                        let person_snapshot = msg.person_snapshot.as_ref().unwrap();
+                        let personal_total_wait_time = person_snapshot
+                            .entered_bathroom_at
+                            .unwrap()
+                            .duration_since(person_snapshot.joined_queue_at.unwrap())
+                            .mul_f64(TIME_SCALE)
+                            .as_secs_f64();
+
+                        metrics_collector
+                            .record_wait(person_snapshot.gender, personal_total_wait_time);
+                    }
+                    event::PERSON_LEFT_THE_BATHROOM => {
+                        let bathroom_snapshot = msg.bathroom_snapshot.as_ref().unwrap();
+                        metrics_collector.record_departure(msg.sent_at);
+                        metrics_collector.record_bathroom_state(
+                            bathroom_snapshot,
+                            bathroom_snapshot.allowed_gender,
+                            msg.sent_at,
+                        );
+
+                        let person_snapshot = msg.person_snapshot.as_ref().unwrap();
+                        let personal_total_time_spent = person_snapshot
+                            .left_bathroom_at
+                            .unwrap()
+                            .duration_since(person_snapshot.joined_queue_at.unwrap())
+                            .mul_f64(TIME_SCALE)
+                            .as_secs_f64();
+
+                        match person_snapshot.gender {
+                            Gender::Male => metrics_collector
+                                .male_personal_total_time_spent
+                                .add_measure(personal_total_time_spent),
+                            Gender::Female => metrics_collector
+                                .female_personal_total_time_spent
+                                .add_measure(personal_total_time_spent),
+                        }
+                    }
+                    event::SIMULATION_STOPPED => {
+                        metrics_collector.finish(msg.sent_at);
+                        metrics_collector.channels = channel::channel_reports(&channels);
+                        metrics_collector.router = router_statistics.lock().unwrap().clone();
+                        metrics_collector.update_statistics();
+                        let metadata = report::new_metadata(
+                            &parameters,
+                            seed,
+                            started_at,
+                            metrics_collector.simulated_duration,
+                        );
 
-    ctrlc::set_handler(move || {
-        r.store(false, Ordering::SeqCst);
+                        router_tx
+                            .send(new_event(
+                                event::SIMULATION_FINISHED.to_string(),
+                                Uuid::new_v4(),
+                                None,
+                                None,
+                                None,
+                            ))
+                            .unwrap();
+                        break (metrics_collector, metadata);
+                    }
+                    &_ => (),
+                }
+            }
+            Err(_) => wait(RX_POLLING_WAIT),
+        }
     })
-    .expect("Error setting Ctrl-C handler");
+}
 
-    while running.load(Ordering::SeqCst) {
-        if rand.gen_bool(PERSON_GENERATION_RATE) {
-            let g = rand.gen::<Gender>();
-            let _person_t = spawn_person_thread(router_tx.clone(), g);
+pub fn randomly_generate_person_threads(
+    router_tx: Sender,
+    main_rx: Receiver,
+    spawn_person: &PersonSpawner,
+    options: &RunOptions,
+) {
+    let mut rand = StdRng::seed_from_u64(options.seed);
+    let started_at = Instant::now();
+    // See `RunOptions::duration`
+    let rounds = options.duration.map(|duration| {
+        (duration.as_secs_f64() / PERSON_GENERATION_INTERVAL.as_secs_f64()).ceil() as u64
+    });
+    let mut round: u64 = 0;
+
+    while options.running.load(Ordering::SeqCst) && rounds.is_none_or(|rounds| round < rounds) {
+        round += 1;
+        if rand.gen_bool(options.parameters.person_generation_rate) {
+            let g = if rand.gen_bool(options.parameters.female_arrival_share) {
+                Gender::Female
+            } else {
+                Gender::Male
+            };
+            let bathroom_time = random_bathroom_time(&mut rand);
+            spawn_person(g, bathroom_time);
         }
-        wait(PERSON_GENERATION_INTERVAL);
+        // Rounds are due at fixed times since the start, so the time spent spawning people
+        // doesn't add up over the run
+        let next_round_at =
+            started_at + PERSON_GENERATION_INTERVAL.mul_f64(round as f64 / TIME_SCALE);
+        thread::sleep(next_round_at.saturating_duration_since(Instant::now()));
     }
 
-    println!("\n🛑 Stopping simulation...");
+    if !options.replication {
+        println!("\n🛑 Stopping simulation...");
+    }
     router_tx
-        .send(new_event(
-            event::SIMULATION_STOPPED.to_string(),
-            Uuid::new_v4(),
-            None,
-            None,
-            None,
-        ))
+        .send(
+            new_event(
+                event::SIMULATION_STOPPED.to_string(),
+                Uuid::new_v4(),
+                None,
+                None,
+                None,
+            )
+            .with_actor("simulation"),
+        )
         .unwrap();
 
     match main_rx.recv() {
         Ok(msg) => match msg.name.as_str() {
+            event::SIMULATION_FINISHED if options.replication => (),
             event::SIMULATION_FINISHED => println!("🦀 Simulation finished gracefully..."),
-            &_ => todo!(),
+            &_ => println!("Unexpected event {} on simulation shutdown!", msg.name),
         },
         Err(_) => println!("Error on simulation shutdown!"),
     }
 }
+
+// Spawns the bathroom of the run's implementation, returning how people are brought to it
+pub fn spawn_bathroom(
+    router_tx: Sender,
+    channels: ChannelRegistry,
+    options: &RunOptions,
+) -> PersonSpawner {
+    return match options.parameters.implementation {
+        config::Implementation::MessagePassing => {
+            spawn_bathroom_thread(
+                router_tx.clone(),
+                channels.clone(),
+                !options.replication,
+                options.parameters,
+            );
+            Arc::new(move |gender, bathroom_time| {
+                let _person_t =
+                    spawn_person_thread(router_tx.clone(), &channels, gender, bathroom_time);
+            })
+        }
+        config::Implementation::LockBased => {
+            let shared = locking::new_shared_bathroom(&options.parameters, !options.replication);
+            locking::spawn_bathroom_thread(router_tx.clone(), channels, shared.clone());
+            Arc::new(move |gender, bathroom_time| {
+                let _person_t = locking::spawn_person_thread(
+                    router_tx.clone(),
+                    shared.clone(),
+                    gender,
+                    bathroom_time,
+                );
+            })
+        }
+        config::Implementation::LockFree => {
+            let shared =
+                lock_free::new_lock_free_bathroom(&options.parameters, !options.replication);
+            locking::spawn_bathroom_thread(router_tx.clone(), channels, shared.clone());
+            Arc::new(move |gender, bathroom_time| {
+                let _person_t = lock_free::spawn_person_thread(
+                    router_tx.clone(),
+                    shared.clone(),
+                    gender,
+                    bathroom_time,
+                );
+            })
+        }
+    };
+}
+
+// How a simulation run is carried out
+pub struct RunOptions {
+    pub parameters: config::Parameters,
+    pub seed: u64,
+    // Simulated time after which people stop arriving and the simulation stops, None to run until
+    // `running` is cleared. It is counted in rounds of PERSON_GENERATION_INTERVAL (rounded up)
+    // rather than on the clock, so that runs with the same seed and duration draw the very same
+    // arrivals, whatever the implementation or the load of the machine. Rounds are due at fixed
+    // times since the start, so a run only lasts longer than its duration when the generator
+    // falls behind, e.g. on an overloaded machine, and then by as long as it fell behind
+    pub duration: Option<Duration>,
+    // Cleared to stop the simulation, e.g. on Ctrl-C
+    pub running: Arc<AtomicBool>,
+    // Whether the run is one of several replications sharing the process: the bathroom keeps quiet,
+    // and the bridge (whose address can't be shared), tracer and monitor are left out
+    pub replication: bool,
+}
+
+// Runs a whole simulation, returning its metrics once it has stopped
+pub fn run_simulation(
+    options: RunOptions,
+) -> (metrics_collector::MetricsCollector, report::Metadata) {
+    let channels = channel::new_channel_registry();
+    let (main_tx, main_rx) = channel::new_channel(&channels, "main", channel::UNBOUNDED);
+
+    let mut router = router::new_router(&channels);
+    let router_tx = router.tx.clone();
+    let router_statistics = router.statistics.clone();
+
+    router.listeners.insert(
+        event::SIMULATION_FINISHED.to_string(),
+        vec![main_tx.clone()],
+    );
+
+    let (metrics_collector_tx, metrics_collector_rx) =
+        channel::new_channel(&channels, "metrics_collector", METRICS_COLLECTOR_CHANNEL);
+
+    event::ALL_EVENTS.iter().for_each(|event| {
+        let _ = router
+            .listeners
+            .insert(event.to_string(), vec![metrics_collector_tx.clone()]);
+    });
+
+    let tracer_t = if ENABLE_TRACING && !options.replication {
+        let (tracer_tx, tracer_rx) = channel::new_channel(&channels, "tracer", channel::UNBOUNDED);
+        event::ALL_EVENTS.iter().for_each(|event| {
+            router
+                .listeners
+                .get_mut(*event)
+                .unwrap()
+                .push(tracer_tx.clone())
+        });
+        Some(spawn_tracer_thread(tracer_rx))
+    } else {
+        None
+    };
+
+    // The bridge is only spawned after the bathroom, which the arrivals it publishes go to
+    let bridge = BRIDGE_ADDRESS
+        .filter(|_| !options.replication)
+        .map(|address| {
+            let (bridge_tx, bridge_rx) =
+                channel::new_channel(&channels, "bridge", channel::UNBOUNDED);
+            event::ALL_EVENTS.iter().for_each(|event| {
+                router
+                    .listeners
+                    .get_mut(*event)
+                    .unwrap()
+                    .push(bridge_tx.clone())
+            });
+            (address, bridge_rx)
+        });
+
+    // The controller is part of the bathroom's policy, so it runs in replications too
+    if ENABLE_CONTROLLER {
+        let (controller_tx, controller_rx) =
+            channel::new_channel(&channels, "controller", channel::UNBOUNDED);
+        [
+            event::NEW_BATHROOM,
+            event::PERSON_JOINED_THE_QUEUE,
+            event::PERSON_ENTERED_THE_BATHROOM,
+            event::PERSON_LEFT_THE_BATHROOM,
+            event::BATHROOM_SWITCHED_GENDERS,
+        ]
+        .iter()
+        .for_each(|event| {
+            router
+                .listeners
+                .get_mut(*event)
+                .unwrap()
+                .push(controller_tx.clone())
+        });
+        spawn_controller_thread(
+            router_tx.clone(),
+            controller_rx,
+            options.parameters,
+            !options.replication,
+        );
+    }
+
+    let invariant_monitor_t = if ENABLE_INVARIANT_MONITOR {
+        let (invariant_monitor_tx, invariant_monitor_rx) =
+            channel::new_channel(&channels, "invariant_monitor", channel::UNBOUNDED);
+        event::ALL_EVENTS
+            .iter()
+            .filter(|event| **event != event::INVARIANT_VIOLATED)
+            .for_each(|event| {
+                router
+                    .listeners
+                    .get_mut(*event)
+                    .unwrap()
+                    .push(invariant_monitor_tx.clone())
+            });
+        Some(spawn_invariant_monitor_thread(
+            router_tx.clone(),
+            invariant_monitor_rx,
+            options.parameters,
+            !options.replication,
+        ))
+    } else {
+        None
+    };
+
+    let watchdog_t = if ENABLE_WATCHDOG {
+        let (watchdog_tx, watchdog_rx) =
+            channel::new_channel(&channels, "watchdog", channel::UNBOUNDED);
+        event::ALL_EVENTS
+            .iter()
+            .filter(|event| **event != event::LIVENESS_CHECK_FAILED)
+            .for_each(|event| {
+                router
+                    .listeners
+                    .get_mut(*event)
+                    .unwrap()
+                    .push(watchdog_tx.clone())
+            });
+        Some(spawn_watchdog_thread(
+            router_tx.clone(),
+            watchdog_rx,
+            !options.replication,
+        ))
+    } else {
+        None
+    };
+
+    if ENABLE_MONITOR && !options.replication {
+        let (monitor_tx, monitor_rx) =
+            channel::new_channel(&channels, "monitor", channel::UNBOUNDED);
+        router
+            .listeners
+            .get_mut(event::NEW_BATHROOM)
+            .unwrap()
+            .push(monitor_tx);
+        spawn_monitor_thread(router_tx.clone(), monitor_rx, channels.clone());
+    }
+
+    spawn_router_thread(router);
+    let metrics_collector_t = spawn_metrics_collector_thread(
+        router_tx.clone(),
+        metrics_collector_rx,
+        channels.clone(),
+        router_statistics,
+        options.parameters,
+        options.seed,
+    );
+    let spawn_person = spawn_bathroom(router_tx.clone(), channels, &options);
+    if let Some((address, bridge_rx)) = bridge {
+        bridge::spawn_bridge_threads(address, bridge_rx, spawn_person.clone());
+    }
+    randomly_generate_person_threads(router_tx, main_rx, &spawn_person, &options);
+
+    let mut run = metrics_collector_t.join().unwrap();
+    if let Some(tracer_t) = tracer_t {
+        tracer_t.join().unwrap();
+    }
+    if let Some(invariant_monitor_t) = invariant_monitor_t {
+        run.0.invariant_violations = invariant_monitor_t.join().unwrap();
+    }
+    if let Some(watchdog_t) = watchdog_t {
+        run.0.liveness_failures = watchdog_t.join().unwrap();
+    }
+
+    return run;
+}
+
+// Prints the delivery metrics of a single run and writes its report files
+pub fn report_run(
+    metrics_collector: &metrics_collector::MetricsCollector,
+    metadata: report::Metadata,
+) {
+    metrics_collector.channels.iter().for_each(|report| {
+        println!(
+            "📨 Channel {} ({} channels): sent {}, dropped {}, blocked sends {}, peak depth {}, depth at shutdown {}",
+            report.name,
+            report.channels,
+            report.sent,
+            report.dropped,
+            report.blocked_sends,
+            report.peak_depth,
+            report.depth_at_shutdown
+        )
+    });
+    println!(
+        "🔀 Router routed {} events with {} deliveries, mean delivery latency {:.1}µs (max {}µs), peak inbox depth {}",
+        metrics_collector.router.total.routed,
+        metrics_collector.router.total.deliveries,
+        metrics_collector.router.total.mean_delivery_latency_micros,
+        metrics_collector.router.total.max_delivery_latency_micros,
+        metrics_collector.router.peak_inbox_depth
+    );
+    match metrics_collector.invariant_violations.len() {
+        0 if ENABLE_INVARIANT_MONITOR => println!("🛡️ No invariant was violated"),
+        0 => (),
+        _ => metrics_collector
+            .invariant_violations
+            .iter()
+            .for_each(|violation| println!("🚨 Invariant violation, {}", violation)),
+    };
+    match metrics_collector.liveness_failures.len() {
+        0 if ENABLE_WATCHDOG => println!("🐕 No liveness check failed"),
+        0 => (),
+        _ => metrics_collector
+            .liveness_failures
+            .iter()
+            .for_each(|failure| println!("🐕 Liveness failure, {}", failure)),
+    };
+    match metrics_collector.consistency.violations.len() {
+        0 => println!(
+            "⚖️ All {} consistency checks passed",
+            metrics_collector.consistency.checks.len()
+        ),
+        _ => metrics_collector
+            .consistency
+            .violations
+            .iter()
+            .for_each(|violation| println!("💩 Consistency violation, {}", violation)),
+    };
+
+    let report_path = report::new_report_path();
+    report::write_reports(
+        &report::new_report(metadata, metrics_collector),
+        &report_path,
+    );
+
+    match report::create_report_file(&report_path, "timeseries.csv") {
+        Ok((filename, mut file)) => {
+            file.write_all(metrics_collector.time_series.to_csv().as_bytes())
+                .unwrap();
+            println!(
+                "📈 Time series CSV file created successfully! File located at {}",
+                filename
+            );
+        }
+        Err(_) => println!("💩 Could not create time series file!"),
+    };
+}
@@ -1,16 +1,56 @@
 use core::fmt;
-use std::time::Instant;
+use serde::Serialize;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+// Rules under which the bathroom switches genders, which all require every cabin to be empty
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum SwitchRule {
+    // The other gender is waiting and the current gender used the bathroom as many times as it
+    // may per turn
+    UseCountLimitReached,
+    // The other gender is waiting and the current gender used the bathroom for longer than the
+    // max use time threshold
+    UseTimeLimitReached,
+    // The other gender is waiting and nobody of the current gender is
+    CurrentGenderQueueEmpty,
+    // The other gender is waiting while no usage limit has been reached
+    OtherGenderWaiting,
+    // Nobody is waiting at all
+    BathroomIdle,
+}
+
+// Limits of a gender's turn in the bathroom, which may be updated while the simulation runs (see
+// `controller.rs`)
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Policy {
+    // Time (simulated) a gender may use the bathroom for before switching
+    pub max_use_time_threshold: Duration,
+    // Times a gender may use the bathroom before switching
+    pub max_uses_per_turn: u32,
+}
+
+pub fn new_policy(parameters: &super::config::Parameters) -> Policy {
+    return Policy {
+        max_use_time_threshold: parameters.max_use_time_threshold,
+        max_uses_per_turn: parameters
+            .max_uses_per_turn
+            .unwrap_or(parameters.bathroom_size as u32),
+    };
+}
+
 #[derive(Clone, Debug)]
 pub struct Bathroom {
     pub id: Uuid,
-    pub cabins: [Option<super::person::Person>; super::BATHROOM_SIZE],
+    pub cabins: Vec<Option<super::person::Person>>,
+    pub policy: Policy,
     pub allowed_gender: super::person::Gender,
     pub use_count: u32,
     pub first_user_entered_at: Option<Instant>,
     pub male_queue: Vec<super::person::Person>,
     pub female_queue: Vec<super::person::Person>,
+    // Whether the bathroom prints its state whenever it changes
+    pub verbose: bool,
 }
 
 impl fmt::Display for Bathroom {
@@ -20,18 +60,14 @@ impl fmt::Display for Bathroom {
             if cabin.is_some() {
                 match cabin.as_ref().unwrap().gender {
                     super::person::Gender::Male => {
-                        acc + &" |".to_string()
-                            + &crate::utils::color::blue("👦".to_string())
-                            + &"|".to_string()
+                        acc + " |" + &crate::utils::color::blue("👦".to_string()) + "|"
                     }
                     super::person::Gender::Female => {
-                        acc + &" |".to_string()
-                            + &crate::utils::color::magenta("👧".to_string())
-                            + &"|".to_string()
+                        acc + " |" + &crate::utils::color::magenta("👧".to_string()) + "|"
                     }
                 }
             } else {
-                acc + &" |🚽|".to_string()
+                acc + " |🚽|"
             }
         }) + " ]";
 
@@ -60,11 +96,15 @@ impl fmt::Display for Bathroom {
 
 impl Bathroom {
     pub fn log(&self, msg: String) {
-        println!("[{}] {}", super::timestamp(), msg);
+        if self.verbose {
+            println!("[{}] {}", super::timestamp(), msg);
+        }
     }
 
     pub fn display(&self) {
-        println!("[{}] {}", super::timestamp(), self);
+        if self.verbose {
+            println!("[{}] {}", super::timestamp(), self);
+        }
     }
 
     pub fn enqueue(&mut self, mut person_to_enqueue: super::person::Person) {
@@ -87,20 +127,16 @@ impl Bathroom {
             super::person::Gender::Female => self.female_queue.first(),
         };
 
-        if first_in_queue.is_none() {
-            return None;
-        }
-
-        let mut person = first_in_queue.unwrap().to_owned();
+        let mut person = first_in_queue?.to_owned();
 
         if person.gender != self.allowed_gender
-            || self.use_count == super::BATHROOM_SIZE as u32
+            || self.use_count >= self.policy.max_uses_per_turn
             || self
                 .first_user_entered_at
                 .unwrap_or(Instant::now())
                 .elapsed()
                 .mul_f64(super::TIME_SCALE)
-                >= super::MAX_USE_TIME_THRESHOLD
+                >= self.policy.max_use_time_threshold
         {
             return None;
         }
@@ -151,7 +187,8 @@ impl Bathroom {
         self.display();
     }
 
-    pub fn should_switch_genders(&mut self) -> bool {
+    // Which rule, if any, calls for letting the other gender in
+    pub fn should_switch_genders(&mut self) -> Option<SwitchRule> {
         let other_gender_queue = match self.allowed_gender {
             super::person::Gender::Male => &self.female_queue,
             super::person::Gender::Female => &self.male_queue,
@@ -175,8 +212,36 @@ impl Bathroom {
 
         let current_gender_queue_empty = current_gender_queue.is_empty();
 
-        return (all_cabins_empty && !other_gender_queue_empty)
-            || (all_cabins_empty && current_gender_queue_empty);
+        if !all_cabins_empty {
+            return None;
+        }
+
+        if other_gender_queue_empty {
+            return match current_gender_queue_empty {
+                true => Some(SwitchRule::BathroomIdle),
+                false => None,
+            };
+        }
+
+        if current_gender_queue_empty {
+            return Some(SwitchRule::CurrentGenderQueueEmpty);
+        }
+
+        if self.use_count >= self.policy.max_uses_per_turn {
+            return Some(SwitchRule::UseCountLimitReached);
+        }
+
+        if self
+            .first_user_entered_at
+            .unwrap_or(Instant::now())
+            .elapsed()
+            .mul_f64(super::TIME_SCALE)
+            >= self.policy.max_use_time_threshold
+        {
+            return Some(SwitchRule::UseTimeLimitReached);
+        }
+
+        return Some(SwitchRule::OtherGenderWaiting);
     }
 
     pub fn switch_genders(&mut self) {
@@ -192,17 +257,18 @@ impl Bathroom {
     }
 }
 
-pub fn new_bathroom(g: super::person::Gender) -> Bathroom {
-    const NO_PERSON: Option<super::person::Person> = None;
+pub fn new_bathroom(g: super::person::Gender, parameters: &super::config::Parameters) -> Bathroom {
     const NO_INSTANT: Option<Instant> = None;
 
     return Bathroom {
         id: Uuid::new_v4(),
-        cabins: [NO_PERSON; super::BATHROOM_SIZE],
+        cabins: vec![None; parameters.bathroom_size],
+        policy: new_policy(parameters),
         allowed_gender: g,
         use_count: 0,
         first_user_entered_at: NO_INSTANT,
         male_queue: vec![],
         female_queue: vec![],
+        verbose: true,
     };
 }
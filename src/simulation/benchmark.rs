@@ -0,0 +1,286 @@
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::config::{self, Implementation, Parameters};
+use super::metrics_collector::{self, MetricsCollector};
+use super::replication::{self, RunArgs};
+use super::report;
+
+// How long the threads of a run may take to exit once it stopped (people still in a cabin finish
+// first), after which its CPU time isn't measured
+const THREADS_EXIT_TIMEOUT: Duration = Duration::from_secs(30);
+const THREADS_POLLING_WAIT: Duration = Duration::from_millis(10);
+
+// Percentiles of the wait (from joining the queue to entering the bathroom) of both genders
+const WAIT_PERCENTILES: [f64; 3] = [50.0, 95.0, 99.0];
+
+// What is measured of a run
+struct Measures {
+    arrivals: u64,
+    departures: u64,
+    throughput_per_hour: f64,
+    wait_percentiles: Vec<f64>,
+    // None when it couldn't be measured
+    cpu_seconds: Option<f64>,
+    invariant_violations: usize,
+}
+
+// Clock ticks per second of the CPU times in /proc
+#[cfg(unix)]
+fn clock_ticks_per_second() -> Option<f64> {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    return if ticks > 0 { Some(ticks as f64) } else { None };
+}
+
+#[cfg(not(unix))]
+fn clock_ticks_per_second() -> Option<f64> {
+    return None;
+}
+
+// CPU time (user and system) the whole process used so far, in seconds, including that of its
+// threads which already exited. None where /proc isn't available
+fn process_cpu_seconds() -> Option<f64> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // The command name, in parentheses, may contain spaces, the fields after it don't. utime and
+    // stime are the 14th and 15th fields, the state being the 3rd
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let user: f64 = fields.get(11)?.parse().ok()?;
+    let system: f64 = fields.get(12)?.parse().ok()?;
+
+    return Some((user + system) / clock_ticks_per_second()?);
+}
+
+// How many threads the process has, None where /proc isn't available
+fn process_threads() -> Option<usize> {
+    return Some(std::fs::read_dir("/proc/self/task").ok()?.count());
+}
+
+// Waits for the process to be back to `threads` threads, i.e. for every thread a run spawned (the
+// router, the actors, people, the watchdog...) to have exited, for at most THREADS_EXIT_TIMEOUT.
+// Returns whether it is
+fn wait_for_threads(threads: usize) -> bool {
+    let started_at = Instant::now();
+    loop {
+        match process_threads() {
+            Some(current) if current <= threads => return true,
+            Some(_) if started_at.elapsed() < THREADS_EXIT_TIMEOUT => {
+                std::thread::sleep(THREADS_POLLING_WAIT)
+            }
+            _ => return false,
+        }
+    }
+}
+
+// CPU time of a run, "n/a" if it couldn't be measured
+fn format_cpu_seconds(cpu_seconds: Option<f64>) -> String {
+    return match cpu_seconds {
+        Some(cpu_seconds) => format!("{:.2}s", cpu_seconds),
+        None => "n/a".to_string(),
+    };
+}
+
+fn measure(metrics_collector: &MetricsCollector, cpu_seconds: Option<f64>) -> Measures {
+    let mut wait = metrics_collector::new_statistic();
+    wait.merge(&metrics_collector.male_personal_total_wait_time);
+    wait.merge(&metrics_collector.female_personal_total_wait_time);
+    wait.update_ordered_measures();
+
+    return Measures {
+        arrivals: metrics_collector.arrivals,
+        departures: metrics_collector.departures,
+        throughput_per_hour: metrics_collector.throughput_per_hour,
+        wait_percentiles: WAIT_PERCENTILES
+            .iter()
+            .map(|p| {
+                wait.percentile(*p, super::PERCENTILE_INTERPOLATION)
+                    .unwrap_or(0.0)
+            })
+            .collect(),
+        cpu_seconds,
+        invariant_violations: metrics_collector.invariant_violations.len(),
+    };
+}
+
+fn benchmark_parameters(implementation: Implementation) -> Parameters {
+    return Parameters {
+        person_generation_rate: super::BENCHMARK_PERSON_GENERATION_RATE,
+        implementation,
+        ..config::default_parameters()
+    };
+}
+
+// Entry point of `cargo run -- benchmark [--replications <n>] [--duration <simulated minutes>]
+// [--seed <base seed>]`, returns the process exit code: 0 when no implementation violated an
+// invariant of the bathroom, 1 otherwise, 2 on usage errors
+pub fn run(args: &[String], running: Arc<AtomicBool>) -> i32 {
+    let RunArgs {
+        replications,
+        duration,
+        base_seed,
+        ..
+    } = match replication::parse_run_args(
+        args,
+        // Runs go one at a time, see below
+        RunArgs {
+            parallelism: None,
+            ..replication::new_run_args(super::BENCHMARK_REPLICATIONS, super::BENCHMARK_DURATION)
+        },
+        |_, _| false,
+    ) {
+        Some(run_args) => run_args,
+        None => {
+            println!("Usage: cargo run -- benchmark [--replications <n>] [--duration <simulated minutes>] [--seed <base seed>]");
+            return 2;
+        }
+    };
+
+    let implementations = super::BENCHMARK_IMPLEMENTATIONS;
+    println!(
+        "⏱️ Benchmarking {:?} over {} replications of {:?} of simulated time, base seed {}",
+        implementations, replications, duration, base_seed
+    );
+
+    // Implementations run one at a time, and every thread of a run must have exited before the
+    // next one starts, so that the CPU time the process used meanwhile is that run's alone. Each
+    // replication runs every implementation on the same seed, so they face the same arrivals.
+    // Besides the threads the process had before any run, only the pool's single worker remains
+    // between runs
+    let threads_between_runs = process_threads().map(|threads| threads + 1);
+    let seeds = replication::derive_seeds(base_seed, replications);
+    let runs: Vec<(Implementation, u64, Measures)> = replication::run_in_pool(
+        seeds.len() * implementations.len(),
+        1,
+        &running.clone(),
+        move |run| {
+            let seed = seeds[run / implementations.len()];
+            let implementation = implementations[run % implementations.len()];
+
+            // An earlier run whose threads didn't all exit would still be using CPU time
+            let cpu_seconds_before = threads_between_runs
+                .filter(|threads| wait_for_threads(*threads))
+                .and_then(|_| process_cpu_seconds());
+            let (metrics_collector, _) = super::run_simulation(super::RunOptions {
+                parameters: benchmark_parameters(implementation),
+                seed,
+                duration: Some(duration),
+                running: running.clone(),
+                replication: true,
+            });
+            let cpu_seconds = match (cpu_seconds_before, threads_between_runs) {
+                (Some(before), Some(threads)) if wait_for_threads(threads) => {
+                    process_cpu_seconds().map(|after| after - before)
+                }
+                _ => None,
+            };
+
+            let measures = measure(&metrics_collector, cpu_seconds);
+            println!(
+                "⏱️ {:?} (seed {}) finished: {} arrivals, {} departures, {:.1} people/h, p95 wait {:.1}s, {} of CPU time, {} invariant violations",
+                implementation,
+                seed,
+                measures.arrivals,
+                measures.departures,
+                measures.throughput_per_hour,
+                measures.wait_percentiles[1],
+                format_cpu_seconds(measures.cpu_seconds),
+                measures.invariant_violations
+            );
+            return (implementation, seed, measures);
+        },
+    );
+    if runs.is_empty() {
+        println!("💩 No run finished");
+        return 1;
+    }
+
+    println!(
+        "{:<16} {:>22} {:>22} {:>22} {:>22} {:>22}",
+        "", "throughput (people/h)", "p50 wait (s)", "p95 wait (s)", "p99 wait (s)", "CPU time (s)"
+    );
+    for implementation in implementations {
+        let implementation_runs: Vec<&Measures> = runs
+            .iter()
+            .filter(|(run_implementation, _, _)| run_implementation == implementation)
+            .map(|(_, _, measures)| measures)
+            .collect();
+        if implementation_runs.is_empty() {
+            continue;
+        }
+
+        let aggregate = |value: fn(&Measures) -> Option<f64>| {
+            let values: Option<Vec<f64>> = implementation_runs
+                .iter()
+                .map(|measures| value(measures))
+                .collect();
+            let metric = match values {
+                Some(values) => replication::aggregate(&values),
+                // A run whose value couldn't be measured leaves the others meaningless
+                None => return "n/a".to_string(),
+            };
+            // A single run has no confidence interval
+            if metric.replications < 2 {
+                return format!("{:.2}", metric.mean);
+            }
+            return format!("{:.2} ± {:.2}", metric.mean, metric.ci95_half_width);
+        };
+        println!(
+            "{:<16} {:>22} {:>22} {:>22} {:>22} {:>22}",
+            format!("{:?}", implementation),
+            aggregate(|measures| Some(measures.throughput_per_hour)),
+            aggregate(|measures| Some(measures.wait_percentiles[0])),
+            aggregate(|measures| Some(measures.wait_percentiles[1])),
+            aggregate(|measures| Some(measures.wait_percentiles[2])),
+            aggregate(|measures| measures.cpu_seconds)
+        );
+    }
+
+    match report::create_report_file(&report::new_report_path(), "benchmark.csv").and_then(
+        |(filename, mut file)| {
+            writeln!(
+                file,
+                "implementation,seed,arrivals,departures,throughput_per_hour,wait_p50,wait_p95,wait_p99,cpu_seconds,invariant_violations"
+            )?;
+            for (implementation, seed, measures) in &runs {
+                writeln!(
+                    file,
+                    "{:?},{},{},{},{},{},{},{},{},{}",
+                    implementation,
+                    seed,
+                    measures.arrivals,
+                    measures.departures,
+                    measures.throughput_per_hour,
+                    measures.wait_percentiles[0],
+                    measures.wait_percentiles[1],
+                    measures.wait_percentiles[2],
+                    measures
+                        .cpu_seconds
+                        .map_or(String::new(), |cpu_seconds| cpu_seconds.to_string()),
+                    measures.invariant_violations
+                )?;
+            }
+            return Ok(filename);
+        },
+    ) {
+        Ok(filename) => println!(
+            "📄 Benchmark CSV file created successfully! File located at {}",
+            filename
+        ),
+        Err(e) => println!("💩 Could not create benchmark report file: {}", e),
+    };
+
+    let violating_runs = runs
+        .iter()
+        .filter(|(_, _, measures)| measures.invariant_violations > 0)
+        .count();
+    if violating_runs > 0 {
+        println!(
+            "🚨 {} runs violated invariants of the bathroom",
+            violating_runs
+        );
+        return 1;
+    }
+
+    return 0;
+}
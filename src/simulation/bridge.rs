@@ -0,0 +1,465 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use super::channel::Receiver;
+use super::event::{self, Event};
+use super::person::Gender;
+
+// Every frame starts with the protocol version (1 byte) and the length of its JSON payload
+// (4 bytes, big-endian). Peers speaking another version are answered with an error and dropped
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const MAX_FRAME_LENGTH: u32 = 1024 * 1024;
+// Real time a client may take to accept an event frame before it is dropped, so that a client that
+// stopped reading doesn't hold up the others
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[allow(dead_code)] // Variants are picked through `BRIDGE_ADDRESS` in `simulation.rs`
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum BridgeAddress {
+    #[cfg(unix)]
+    Unix(&'static str),
+    Tcp(&'static str),
+}
+
+// Messages sent by external processes
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    // Starts (or replaces) the subscription to the given events, all of them if empty
+    Subscribe { topics: Vec<String> },
+    // A new person arrives at the bathroom
+    PublishArrival { gender: Gender },
+}
+
+// Messages sent to external processes
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Welcome {
+        protocol_version: u8,
+        topics: Vec<String>,
+    },
+    // Boxed since events are far larger than the other messages
+    Event {
+        event: Box<WireEvent>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WirePerson {
+    pub id: Uuid,
+    pub gender: Gender,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WireBathroom {
+    pub id: Uuid,
+    pub allowed_gender: Gender,
+    pub use_count: u32,
+    pub occupied_cabins: usize,
+    pub cabins: usize,
+    pub female_queue_length: usize,
+    pub male_queue_length: usize,
+}
+
+// Serializable view of an `Event`: instants are process-local, so timestamps are sent as real
+// microseconds since the bridge started, and senders are left out
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WireEvent {
+    pub id: Uuid,
+    pub name: String,
+    pub producer_id: Uuid,
+    pub destination_id: Option<Uuid>,
+    pub trace_id: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
+    pub correlation_id: Option<Uuid>,
+    pub sent_at_micros: u64,
+    pub person: Option<WirePerson>,
+    pub bathroom: Option<WireBathroom>,
+    // Set on INVARIANT_VIOLATED and LIVENESS_CHECK_FAILED events
+    pub violation: Option<String>,
+}
+
+pub fn to_wire_event(msg: &Event, started_at: Instant) -> WireEvent {
+    return WireEvent {
+        id: msg.id,
+        name: msg.name.clone(),
+        producer_id: msg.producer_id,
+        destination_id: msg.destination_id,
+        trace_id: msg.trace_id,
+        parent_id: msg.parent_id,
+        correlation_id: msg.correlation_id,
+        sent_at_micros: msg
+            .sent_at
+            .saturating_duration_since(started_at)
+            .as_micros() as u64,
+        person: msg.person_snapshot.as_ref().map(|person| WirePerson {
+            id: person.id,
+            gender: person.gender,
+        }),
+        bathroom: msg.bathroom_snapshot.as_ref().map(|bathroom| WireBathroom {
+            id: bathroom.id,
+            allowed_gender: bathroom.allowed_gender,
+            use_count: bathroom.use_count,
+            occupied_cabins: bathroom
+                .cabins
+                .iter()
+                .filter(|cabin| cabin.is_some())
+                .count(),
+            cabins: bathroom.cabins.len(),
+            female_queue_length: bathroom.female_queue.len(),
+            male_queue_length: bathroom.male_queue.len(),
+        }),
+        violation: msg.violation.clone(),
+    };
+}
+
+pub fn encode_frame<T: Serialize>(message: &T) -> io::Result<Vec<u8>> {
+    let payload = serde_json::to_vec(message)?;
+    let mut frame = Vec::with_capacity(payload.len() + 5);
+    frame.push(PROTOCOL_VERSION);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    return Ok(frame);
+}
+
+fn write_encoded_frame<W: Write>(writer: &mut W, frame: &[u8]) -> io::Result<()> {
+    writer.write_all(frame)?;
+    return writer.flush();
+}
+
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, message: &T) -> io::Result<()> {
+    return write_encoded_frame(writer, &encode_frame(message)?);
+}
+
+pub fn read_frame<R: Read, T: for<'de> Deserialize<'de>>(reader: &mut R) -> io::Result<T> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+
+    if header[0] != PROTOCOL_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "unsupported protocol version {} (expected {})",
+                header[0], PROTOCOL_VERSION
+            ),
+        ));
+    }
+
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+    if length > MAX_FRAME_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds the maximum length", length),
+        ));
+    }
+
+    let mut payload = vec![0u8; length as usize];
+    reader.read_exact(&mut payload)?;
+    return serde_json::from_slice(&payload)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+}
+
+pub enum Connection {
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Connection {
+    fn try_clone(&self) -> io::Result<Connection> {
+        return match self {
+            #[cfg(unix)]
+            Connection::Unix(stream) => Ok(Connection::Unix(stream.try_clone()?)),
+            Connection::Tcp(stream) => Ok(Connection::Tcp(stream.try_clone()?)),
+        };
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        return match self {
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_write_timeout(timeout),
+            Connection::Tcp(stream) => stream.set_write_timeout(timeout),
+        };
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return match self {
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+            Connection::Tcp(stream) => stream.read(buf),
+        };
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return match self {
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+            Connection::Tcp(stream) => stream.write(buf),
+        };
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return match self {
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+            Connection::Tcp(stream) => stream.flush(),
+        };
+    }
+}
+
+struct Client {
+    id: Uuid,
+    // None until the client subscribes, empty when subscribed to every event
+    topics: Option<HashSet<String>>,
+    // Locked on its own, so that events are written without holding up the list of clients
+    writer: Arc<Mutex<Connection>>,
+}
+
+impl Client {
+    fn is_subscribed_to(&self, name: &str) -> bool {
+        return match &self.topics {
+            Some(topics) => topics.is_empty() || topics.contains(name),
+            None => false,
+        };
+    }
+}
+
+type Clients = Arc<Mutex<Vec<Client>>>;
+
+fn accept_connections(
+    address: BridgeAddress,
+    clients: Clients,
+    spawn_person: super::PersonSpawner,
+) -> io::Result<()> {
+    match address {
+        #[cfg(unix)]
+        BridgeAddress::Unix(path) => {
+            // A socket file left behind by a previous run would make binding fail
+            let _ = std::fs::remove_file(path);
+            let listener = UnixListener::bind(path)?;
+            super::log(format!("Bridge listening on {:?}", address));
+            let incoming = listener
+                .incoming()
+                .map(|stream| stream.map(Connection::Unix));
+            serve_clients(incoming, clients, spawn_person);
+        }
+        BridgeAddress::Tcp(tcp_address) => {
+            let listener = TcpListener::bind(tcp_address)?;
+            super::log(format!("Bridge listening on {:?}", address));
+            let incoming = listener
+                .incoming()
+                .map(|stream| stream.map(Connection::Tcp));
+            serve_clients(incoming, clients, spawn_person);
+        }
+    };
+
+    return Ok(());
+}
+
+fn serve_clients(
+    incoming: impl Iterator<Item = io::Result<Connection>>,
+    clients: Clients,
+    spawn_person: super::PersonSpawner,
+) {
+    for connection in incoming {
+        let mut connection = match connection {
+            Ok(connection) => connection,
+            Err(_) => continue,
+        };
+
+        let welcome = ServerMessage::Welcome {
+            protocol_version: PROTOCOL_VERSION,
+            topics: event::ALL_EVENTS.iter().map(|e| e.to_string()).collect(),
+        };
+        if write_frame(&mut connection, &welcome).is_err() {
+            continue;
+        }
+
+        let client_id = Uuid::new_v4();
+        let writer = match connection.try_clone() {
+            Ok(writer) if writer.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok() => writer,
+            _ => continue,
+        };
+        clients.lock().unwrap().push(Client {
+            id: client_id,
+            topics: None,
+            writer: Arc::new(Mutex::new(writer)),
+        });
+
+        let clients = clients.clone();
+        let spawn_person = spawn_person.clone();
+        thread::spawn(move || {
+            handle_client(client_id, connection, &clients, &spawn_person);
+            clients
+                .lock()
+                .unwrap()
+                .retain(|client| client.id != client_id);
+            super::log(format!("Bridge client {} disconnected", client_id));
+        });
+    }
+}
+
+fn handle_client(
+    client_id: Uuid,
+    mut connection: Connection,
+    clients: &Clients,
+    spawn_person: &super::PersonSpawner,
+) {
+    loop {
+        match read_frame::<_, ClientMessage>(&mut connection) {
+            Ok(ClientMessage::Subscribe { topics }) => {
+                let mut clients = clients.lock().unwrap();
+                if let Some(client) = clients.iter_mut().find(|client| client.id == client_id) {
+                    client.topics = Some(topics.into_iter().collect());
+                }
+            }
+            Ok(ClientMessage::PublishArrival { gender }) => {
+                let bathroom_time = super::random_bathroom_time(&mut rand::thread_rng());
+                spawn_person(gender, bathroom_time);
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                let _ = write_frame(
+                    &mut connection,
+                    &ServerMessage::Error {
+                        message: e.to_string(),
+                    },
+                );
+                return;
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+// Serves external processes over `address`: they may publish arrivals and subscribe to events,
+// which the bridge receives from the router through `bridge_rx`
+pub fn spawn_bridge_threads(
+    address: BridgeAddress,
+    bridge_rx: Receiver,
+    spawn_person: super::PersonSpawner,
+) {
+    let clients: Clients = Arc::new(Mutex::new(vec![]));
+    let started_at = Instant::now();
+
+    let accepting_clients = clients.clone();
+    thread::spawn(move || {
+        if let Err(e) = accept_connections(address, accepting_clients, spawn_person) {
+            println!("💩 Could not start the bridge on {:?}: {}", address, e);
+        }
+    });
+
+    thread::spawn(move || {
+        while let Ok(msg) = bridge_rx.recv() {
+            // The list of clients is only locked to pick the subscribers, so that clients may
+            // connect and subscribe while the event is being written
+            let subscribers: Vec<(Uuid, Arc<Mutex<Connection>>)> = clients
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|client| client.is_subscribed_to(&msg.name))
+                .map(|client| (client.id, client.writer.clone()))
+                .collect();
+            if subscribers.is_empty() {
+                continue;
+            }
+
+            // Serialized once for all subscribers
+            let frame = match encode_frame(&ServerMessage::Event {
+                event: Box::new(to_wire_event(&msg, started_at)),
+            }) {
+                Ok(frame) => frame,
+                Err(_) => continue,
+            };
+            let failed: Vec<Uuid> = subscribers
+                .into_iter()
+                .filter(|(_, writer)| {
+                    write_encoded_frame(&mut *writer.lock().unwrap(), &frame).is_err()
+                })
+                .map(|(client_id, _)| client_id)
+                .collect();
+            if !failed.is_empty() {
+                clients
+                    .lock()
+                    .unwrap()
+                    .retain(|client| !failed.contains(&client.id));
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // A frame of `length` bytes announced with `version`, without its payload
+    fn header(version: u8, length: u32) -> Vec<u8> {
+        let mut frame = vec![version];
+        frame.extend_from_slice(&length.to_be_bytes());
+        return frame;
+    }
+
+    #[test]
+    fn frames_carry_the_version_and_length_of_their_payload() {
+        let mut frame = vec![];
+        write_frame(
+            &mut frame,
+            &ClientMessage::Subscribe {
+                topics: vec![event::NEW_PERSON.to_string()],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            frame[..5],
+            header(PROTOCOL_VERSION, frame.len() as u32 - 5)[..]
+        );
+        match read_frame(&mut Cursor::new(frame)).unwrap() {
+            ClientMessage::Subscribe { topics } => assert_eq!(topics, [event::NEW_PERSON]),
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn frames_of_another_version_are_rejected() {
+        let mut frame = vec![];
+        write_frame(&mut frame, &ClientMessage::Subscribe { topics: vec![] }).unwrap();
+        frame[0] = PROTOCOL_VERSION + 1;
+
+        let error = read_frame::<_, ClientMessage>(&mut Cursor::new(frame)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn frames_over_the_maximum_length_are_rejected_before_reading_them() {
+        let frame = header(PROTOCOL_VERSION, MAX_FRAME_LENGTH + 1);
+
+        let error = read_frame::<_, ClientMessage>(&mut Cursor::new(frame)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let mut frame = header(PROTOCOL_VERSION, 100);
+        frame.extend_from_slice(b"{}");
+
+        let error = read_frame::<_, ClientMessage>(&mut Cursor::new(frame)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,487 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::event::Event;
+
+// What a bounded channel does when a new event arrives and it is already full
+#[allow(dead_code)] // Variants are picked through the channel constants in `simulation.rs`
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    // The sender waits until the receiver makes room
+    Block,
+    // The oldest queued event is discarded to make room for the new one
+    DropOldest,
+    // The new event is discarded
+    DropNewest,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct ChannelConfig {
+    // None means the channel is unbounded
+    pub capacity: Option<usize>,
+    pub overflow_policy: OverflowPolicy,
+}
+
+pub const UNBOUNDED: ChannelConfig = ChannelConfig {
+    capacity: None,
+    overflow_policy: OverflowPolicy::Block,
+};
+
+#[derive(Debug)]
+pub struct ChannelStatistics {
+    pub name: String,
+    pub config: ChannelConfig,
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+    pub dropped: AtomicU64,
+    pub blocked_sends: AtomicU64,
+    pub depth: AtomicUsize,
+    pub peak_depth: AtomicUsize,
+}
+
+// Every channel created during a simulation registers its statistics here, so they can be
+// reported at shutdown. Once both ends of a channel are dropped, its statistics are added to those
+// of the closed channels of the same name, so short-lived channels (e.g. every person's inbox)
+// don't pile up over a long run
+pub struct Registry {
+    next_id: u64,
+    open: BTreeMap<u64, Arc<ChannelStatistics>>,
+    closed: BTreeMap<String, ChannelReport>,
+}
+
+pub type ChannelRegistry = Arc<Mutex<Registry>>;
+
+pub fn new_channel_registry() -> ChannelRegistry {
+    return Arc::new(Mutex::new(Registry {
+        next_id: 0,
+        open: BTreeMap::new(),
+        closed: BTreeMap::new(),
+    }));
+}
+
+struct State {
+    events: VecDeque<Event>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    config: ChannelConfig,
+    statistics: Arc<ChannelStatistics>,
+    // Where the channel is registered, and under which id
+    registry: ChannelRegistry,
+    id: u64,
+}
+
+impl Shared {
+    fn is_full(&self, state: &State) -> bool {
+        return match self.config.capacity {
+            Some(capacity) => state.events.len() >= capacity,
+            None => false,
+        };
+    }
+
+    fn update_depth(&self, state: &State) {
+        let depth = state.events.len();
+        self.statistics.depth.store(depth, Ordering::Relaxed);
+        self.statistics
+            .peak_depth
+            .fetch_max(depth, Ordering::Relaxed);
+    }
+}
+
+// Both ends of the channel are gone
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let mut registry = self.registry.lock().unwrap();
+        registry.open.remove(&self.id);
+        add_to_report(
+            registry
+                .closed
+                .entry(self.statistics.name.clone())
+                .or_insert_with(|| new_channel_report(&self.statistics)),
+            &self.statistics,
+        );
+    }
+}
+
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+pub fn new_channel(
+    registry: &ChannelRegistry,
+    name: &str,
+    config: ChannelConfig,
+) -> (Sender, Receiver) {
+    let statistics = Arc::new(ChannelStatistics {
+        name: name.to_string(),
+        config,
+        sent: AtomicU64::new(0),
+        received: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+        blocked_sends: AtomicU64::new(0),
+        depth: AtomicUsize::new(0),
+        peak_depth: AtomicUsize::new(0),
+    });
+    let id = {
+        let mut registry = registry.lock().unwrap();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.open.insert(id, statistics.clone());
+        id
+    };
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            events: VecDeque::new(),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        config,
+        statistics,
+        registry: registry.clone(),
+        id,
+    });
+
+    return (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    );
+}
+
+impl Sender {
+    // Mirrors `std::sync::mpsc::Sender::send`, which hands the event back on failure
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, event: Event) -> Result<(), SendError<Event>> {
+        let shared = &self.shared;
+        let mut state = shared.state.lock().unwrap();
+
+        if !state.receiver_alive {
+            return Err(SendError(event));
+        }
+
+        // Discarded events are dropped only after the lock is released, since they may hold
+        // senders of this very channel
+        let mut discarded = None;
+
+        if shared.is_full(&state) {
+            match shared.config.overflow_policy {
+                OverflowPolicy::Block => {
+                    shared
+                        .statistics
+                        .blocked_sends
+                        .fetch_add(1, Ordering::Relaxed);
+                    while shared.is_full(&state) && state.receiver_alive {
+                        state = shared.not_full.wait(state).unwrap();
+                    }
+                    if !state.receiver_alive {
+                        return Err(SendError(event));
+                    }
+                }
+                OverflowPolicy::DropOldest => {
+                    discarded = state.events.pop_front();
+                    shared.statistics.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::DropNewest => {
+                    shared.statistics.dropped.fetch_add(1, Ordering::Relaxed);
+                    drop(state);
+                    drop(event);
+                    return Ok(());
+                }
+            }
+        }
+
+        state.events.push_back(event);
+        shared.statistics.sent.fetch_add(1, Ordering::Relaxed);
+        shared.update_depth(&state);
+        shared.not_empty.notify_one();
+        drop(state);
+        drop(discarded);
+
+        return Ok(());
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        return Sender {
+            shared: self.shared.clone(),
+        };
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+impl fmt::Debug for Sender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sender {{ channel: {} }}", self.shared.statistics.name)
+    }
+}
+
+impl Receiver {
+    fn take(&self, state: &mut State) -> Option<Event> {
+        let event = state.events.pop_front()?;
+        self.shared
+            .statistics
+            .received
+            .fetch_add(1, Ordering::Relaxed);
+        self.shared.update_depth(state);
+        self.shared.not_full.notify_one();
+        return Some(event);
+    }
+
+    pub fn try_recv(&self) -> Result<Event, TryRecvError> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        return match self.take(&mut state) {
+            Some(event) => Ok(event),
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        };
+    }
+
+    pub fn recv(&self) -> Result<Event, RecvError> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        loop {
+            if let Some(event) = self.take(&mut state) {
+                return Ok(event);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state = self.shared.not_empty.wait(state).unwrap();
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+
+        loop {
+            if let Some(event) = self.take(&mut state) {
+                return Ok(event);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self
+                .shared
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        return self.shared.state.lock().unwrap().events.len();
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receiver_alive = false;
+        self.shared.not_full.notify_all();
+    }
+}
+
+// Depth metrics of all channels sharing the same name (e.g. every person's inbox)
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelReport {
+    pub name: String,
+    pub channels: u64,
+    pub capacity: Option<usize>,
+    pub overflow_policy: OverflowPolicy,
+    pub sent: u64,
+    pub received: u64,
+    pub dropped: u64,
+    pub blocked_sends: u64,
+    pub peak_depth: usize,
+    pub depth_at_shutdown: usize,
+}
+
+fn new_channel_report(statistics: &ChannelStatistics) -> ChannelReport {
+    return ChannelReport {
+        name: statistics.name.clone(),
+        channels: 0,
+        capacity: statistics.config.capacity,
+        overflow_policy: statistics.config.overflow_policy,
+        sent: 0,
+        received: 0,
+        dropped: 0,
+        blocked_sends: 0,
+        peak_depth: 0,
+        depth_at_shutdown: 0,
+    };
+}
+
+fn add_to_report(report: &mut ChannelReport, statistics: &ChannelStatistics) {
+    report.channels += 1;
+    report.sent += statistics.sent.load(Ordering::Relaxed);
+    report.received += statistics.received.load(Ordering::Relaxed);
+    report.dropped += statistics.dropped.load(Ordering::Relaxed);
+    report.blocked_sends += statistics.blocked_sends.load(Ordering::Relaxed);
+    report.peak_depth = report
+        .peak_depth
+        .max(statistics.peak_depth.load(Ordering::Relaxed));
+    report.depth_at_shutdown += statistics.depth.load(Ordering::Relaxed);
+}
+
+// Reports of the closed channels, along with the open ones as they stand
+pub fn channel_reports(registry: &ChannelRegistry) -> Vec<ChannelReport> {
+    let registry = registry.lock().unwrap();
+    let mut reports: BTreeMap<String, ChannelReport> = registry
+        .closed
+        .values()
+        .map(|report| (report.name.clone(), report.clone()))
+        .collect();
+
+    registry.open.values().for_each(|statistics| {
+        add_to_report(
+            reports
+                .entry(statistics.name.clone())
+                .or_insert_with(|| new_channel_report(statistics)),
+            statistics,
+        );
+    });
+
+    return reports.into_values().collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    use crate::simulation::event;
+
+    fn numbered_event(number: u32) -> Event {
+        return event::new_event(number.to_string(), Uuid::new_v4(), None, None, None);
+    }
+
+    fn bounded(overflow_policy: OverflowPolicy) -> (ChannelRegistry, Sender, Receiver) {
+        let registry = new_channel_registry();
+        let (tx, rx) = new_channel(
+            &registry,
+            "test",
+            ChannelConfig {
+                capacity: Some(2),
+                overflow_policy,
+            },
+        );
+        return (registry, tx, rx);
+    }
+
+    fn received_names(rx: &Receiver) -> Vec<String> {
+        let mut names = vec![];
+        while let Ok(event) = rx.try_recv() {
+            names.push(event.name);
+        }
+        return names;
+    }
+
+    #[test]
+    fn drop_oldest_discards_the_front_of_a_full_channel() {
+        let (registry, tx, rx) = bounded(OverflowPolicy::DropOldest);
+        (1..=3).for_each(|number| tx.send(numbered_event(number)).unwrap());
+
+        assert_eq!(received_names(&rx), vec!["2", "3"]);
+        let report = &channel_reports(&registry)[0];
+        assert_eq!(report.sent, 3);
+        assert_eq!(report.received, 2);
+        assert_eq!(report.dropped, 1);
+        assert_eq!(report.peak_depth, 2);
+    }
+
+    #[test]
+    fn drop_newest_discards_the_new_event_of_a_full_channel() {
+        let (registry, tx, rx) = bounded(OverflowPolicy::DropNewest);
+        (1..=3).for_each(|number| tx.send(numbered_event(number)).unwrap());
+
+        assert_eq!(received_names(&rx), vec!["1", "2"]);
+        let report = &channel_reports(&registry)[0];
+        assert_eq!(report.sent, 2);
+        assert_eq!(report.dropped, 1);
+    }
+
+    #[test]
+    fn block_waits_until_the_receiver_makes_room() {
+        let (registry, tx, rx) = bounded(OverflowPolicy::Block);
+        tx.send(numbered_event(1)).unwrap();
+        tx.send(numbered_event(2)).unwrap();
+
+        let sender = thread::spawn(move || tx.send(numbered_event(3)).is_ok());
+        while channel_reports(&registry)[0].blocked_sends == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(channel_reports(&registry)[0].sent, 2);
+
+        assert_eq!(rx.recv().unwrap().name, "1");
+        assert!(sender.join().unwrap());
+        assert_eq!(received_names(&rx), vec!["2", "3"]);
+        assert_eq!(channel_reports(&registry)[0].dropped, 0);
+    }
+
+    #[test]
+    fn block_gives_up_once_the_receiver_is_gone() {
+        let (_registry, tx, rx) = bounded(OverflowPolicy::Block);
+        tx.send(numbered_event(1)).unwrap();
+        tx.send(numbered_event(2)).unwrap();
+
+        let sender = thread::spawn(move || tx.send(numbered_event(3)).is_err());
+        drop(rx);
+        assert!(sender.join().unwrap());
+    }
+
+    #[test]
+    fn closed_channels_are_folded_into_their_name() {
+        let registry = new_channel_registry();
+        for _ in 0..3 {
+            let (tx, rx) = new_channel(&registry, "person", UNBOUNDED);
+            tx.send(numbered_event(1)).unwrap();
+            rx.recv().unwrap();
+        }
+        let (_tx, _rx) = new_channel(&registry, "person", UNBOUNDED);
+
+        assert_eq!(registry.lock().unwrap().open.len(), 1);
+        let reports = channel_reports(&registry);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].channels, 4);
+        assert_eq!(reports[0].sent, 3);
+        assert_eq!(reports[0].received, 3);
+    }
+}
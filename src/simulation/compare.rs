@@ -0,0 +1,310 @@
+use serde_json::Value;
+use std::fs;
+
+use super::report::{self, Metadata};
+
+// Fields of a `Statistic` that are compared, besides its percentiles, when both reports have them
+const COMPARED_FIELDS: [&str; 6] = ["count", "avg", "std_dev", "min", "median", "max"];
+// Fields every version of the report has, which tell statistics apart from other objects
+const STATISTIC_FIELDS: [&str; 4] = ["avg", "min", "median", "max"];
+
+// Statistics found in a JSON report, keyed by their path in it (e.g. "male_queue_size")
+pub struct ComparedReport {
+    pub filename: String,
+    pub schema_version: u32,
+    pub metadata: Option<Metadata>,
+    pub statistics: Vec<(String, Value)>,
+}
+
+pub fn load_compared_report(filename: &str) -> Result<ComparedReport, String> {
+    let contents =
+        fs::read_to_string(filename).map_err(|e| format!("could not read {}: {}", filename, e))?;
+    let report = report::load_report(&contents)
+        .map_err(|e| format!("{} is not a statistics report: {}", filename, e))?;
+
+    let mut statistics = vec![];
+    find_statistics(&report.metrics, "", &mut statistics);
+
+    return Ok(ComparedReport {
+        filename: filename.to_string(),
+        schema_version: report.schema_version,
+        metadata: report.metadata,
+        statistics,
+    });
+}
+
+fn describe(report: &ComparedReport) -> String {
+    return match &report.metadata {
+        Some(metadata) => format!(
+            "{} (schema v{}, seed {}, code {} ({}), finished at {})",
+            report.filename,
+            report.schema_version,
+            metadata.seed,
+            metadata.code_version,
+            metadata.git_commit,
+            metadata.finished_at
+        ),
+        None => format!(
+            "{} (schema v{}, no metadata)",
+            report.filename, report.schema_version
+        ),
+    };
+}
+
+fn is_statistic(value: &Value) -> bool {
+    return STATISTIC_FIELDS
+        .iter()
+        .all(|field| value.get(field).is_some_and(Value::is_number));
+}
+
+fn find_statistics(value: &Value, path: &str, statistics: &mut Vec<(String, Value)>) {
+    if let Value::Object(fields) = value {
+        if is_statistic(value) {
+            statistics.push((path.to_string(), value.clone()));
+            return;
+        }
+
+        fields.iter().for_each(|(name, field)| {
+            let path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}.{}", path, name)
+            };
+            find_statistics(field, &path, statistics);
+        });
+    }
+}
+
+// Compared values of a statistic, e.g. ("avg", 12.5) or ("p95", 40.0)
+fn statistic_values(statistic: &Value) -> Vec<(String, f64)> {
+    let mut values: Vec<(String, f64)> = COMPARED_FIELDS
+        .iter()
+        .filter_map(|field| Some((field.to_string(), statistic.get(field)?.as_f64()?)))
+        .collect();
+
+    if let Some(Value::Object(percentiles)) = statistic.get("percentiles") {
+        let mut percentiles: Vec<(String, f64)> = percentiles
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), value.as_f64()?)))
+            .collect();
+        percentiles.sort_by(|(a, _), (b, _)| {
+            let rank = |name: &str| name.trim_start_matches('p').parse::<f64>().unwrap_or(0.0);
+            rank(a).total_cmp(&rank(b))
+        });
+        values.extend(percentiles);
+    }
+
+    return values;
+}
+
+// Change of `value` relative to `baseline`, None when the baseline is zero and the value is not
+fn relative_change(baseline: f64, value: f64) -> Option<f64> {
+    if baseline == 0.0 {
+        return if value == 0.0 { Some(0.0) } else { None };
+    }
+
+    return Some((value - baseline) / baseline.abs());
+}
+
+// Every statistic measures a cost (a time or a queue size), so a value that grew by more than
+// `tolerance` (relative to the baseline) is a regression. Counts are not costs and never regress
+fn is_regression(field: &str, baseline: f64, value: f64, tolerance: f64) -> bool {
+    if field == "count" {
+        return false;
+    }
+
+    return match relative_change(baseline, value) {
+        Some(change) => change > tolerance,
+        None => value > baseline,
+    };
+}
+
+// Prints every statistic of the other reports side by side with the baseline's, with absolute
+// and relative changes, and returns how many regressions were found, or an error when no value
+// could be compared at all
+pub fn compare_reports(
+    baseline: &ComparedReport,
+    others: &[ComparedReport],
+    tolerance: f64,
+) -> Result<usize, String> {
+    let mut regressions = 0;
+    let mut compared = 0;
+
+    println!("📊 Baseline: {}", describe(baseline));
+    others
+        .iter()
+        .enumerate()
+        .for_each(|(i, other)| println!("📊 Report #{}: {}", i + 1, describe(other)));
+    println!(
+        "📊 Regressions are increases of more than {:.1}% over the baseline",
+        tolerance * 100.0
+    );
+
+    for (path, statistic) in &baseline.statistics {
+        // None for the reports without this statistic
+        let other_values: Vec<Option<Vec<(String, f64)>>> = others
+            .iter()
+            .map(|other| {
+                other
+                    .statistics
+                    .iter()
+                    .find(|(other_path, _)| other_path == path)
+                    .map(|(_, other_statistic)| statistic_values(other_statistic))
+            })
+            .collect();
+        // Only the fields every report with the statistic has are compared, e.g. older reports
+        // have no count nor standard deviation
+        let fields: Vec<(String, f64)> = statistic_values(statistic)
+            .into_iter()
+            .filter(|(field, _)| {
+                other_values
+                    .iter()
+                    .flatten()
+                    .all(|values| values.iter().any(|(other_field, _)| other_field == field))
+            })
+            .collect();
+
+        println!();
+        println!("{}", path);
+
+        for (field, baseline_value) in fields {
+            let mut line = format!("  {:<8} {:>14.3}", field, baseline_value);
+
+            for values in &other_values {
+                let value = values.as_ref().and_then(|values| {
+                    values
+                        .iter()
+                        .find(|(other_field, _)| *other_field == field)
+                        .map(|(_, value)| *value)
+                });
+
+                let value = match value {
+                    Some(value) => value,
+                    None => {
+                        line += &format!(" | {:>14} {:>10} {:>9}  ", "missing", "", "");
+                        continue;
+                    }
+                };
+                compared += 1;
+
+                let relative = match relative_change(baseline_value, value) {
+                    Some(change) => format!("{:+.1}%", change * 100.0),
+                    None => "new".to_string(),
+                };
+                let regression = is_regression(&field, baseline_value, value, tolerance);
+                regressions += regression as usize;
+
+                line += &format!(
+                    " | {:>14.3} {:>+10.3} {:>9} {}",
+                    value,
+                    value - baseline_value,
+                    relative,
+                    if regression { "⚠️" } else { "  " }
+                );
+            }
+
+            println!("{}", line.trim_end());
+        }
+    }
+
+    others.iter().for_each(|other| {
+        baseline
+            .statistics
+            .iter()
+            .filter(|(path, _)| {
+                !other
+                    .statistics
+                    .iter()
+                    .any(|(other_path, _)| other_path == path)
+            })
+            .for_each(|(path, _)| println!("💩 {} has no {} statistic", other.filename, path));
+    });
+
+    if compared == 0 {
+        return Err(format!(
+            "no statistic of {} could be compared with the other reports",
+            baseline.filename
+        ));
+    }
+
+    println!();
+    println!("📊 {} regression(s) found", regressions);
+
+    return Ok(regressions);
+}
+
+// Entry point of `cargo run -- compare <baseline.json> <report.json>... [--tolerance <fraction>]`,
+// returns the process exit code: 0 without regressions, 1 with regressions, 2 on usage errors or
+// when nothing could be compared
+pub fn run(args: &[String]) -> i32 {
+    let mut tolerance = super::COMPARE_TOLERANCE;
+    let mut filenames = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--tolerance" {
+            tolerance = match args.next().and_then(|value| value.parse::<f64>().ok()) {
+                Some(value) if value >= 0.0 => value,
+                _ => {
+                    println!("💩 --tolerance expects a non-negative fraction, e.g. 0.05 for 5%");
+                    return 2;
+                }
+            };
+        } else {
+            filenames.push(arg.clone());
+        }
+    }
+
+    if filenames.len() < 2 {
+        println!(
+            "Usage: cargo run -- compare <baseline.json> <report.json>... [--tolerance <fraction>]"
+        );
+        return 2;
+    }
+
+    let mut reports = vec![];
+    for filename in &filenames {
+        match load_compared_report(filename) {
+            Ok(report) => reports.push(report),
+            Err(e) => {
+                println!("💩 {}", e);
+                return 2;
+            }
+        }
+    }
+
+    return match compare_reports(&reports[0], &reports[1..], tolerance) {
+        Ok(0) => 0,
+        Ok(_) => 1,
+        Err(e) => {
+            println!("💩 {}", e);
+            2
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_growing_past_the_tolerance_regress() {
+        assert!(!is_regression("avg", 10.0, 10.5, 0.05));
+        assert!(is_regression("avg", 10.0, 10.6, 0.05));
+        assert!(is_regression("max", 10.0, 10.1, 0.0));
+        // Every statistic is a cost, so shrinking is an improvement
+        assert!(!is_regression("median", 10.0, 1.0, 0.05));
+    }
+
+    #[test]
+    fn values_growing_from_zero_regress_whatever_the_tolerance() {
+        assert!(is_regression("avg", 0.0, 0.1, 10.0));
+        assert!(!is_regression("avg", 0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn counts_never_regress() {
+        assert!(!is_regression("count", 10.0, 1000.0, 0.0));
+        assert!(!is_regression("count", 0.0, 1.0, 0.0));
+    }
+}
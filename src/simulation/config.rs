@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::channel::ChannelConfig;
+use super::metrics_collector::{Interpolation, StatisticMode};
+
+// Every parameter a run was simulated with, recorded in the report's metadata. Durations are in
+// seconds, simulated ones unless stated otherwise
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Config {
+    pub time_scale: f64,
+    // Real time
+    pub rx_polling_wait: f64,
+    pub min_person_bathroom_seconds: u64,
+    pub max_person_bathroom_seconds: u64,
+    pub person_generation_interval: f64,
+    pub person_generation_rate: f64,
+    pub female_arrival_share: f64,
+    pub bathroom_size: usize,
+    pub max_use_time_threshold: f64,
+    // As many as there are cabins when None
+    pub max_uses_per_turn: Option<u32>,
+    pub implementation: Implementation,
+    pub warm_up: Option<String>,
+    pub warm_up_detector_window: f64,
+    pub warm_up_detector_windows: usize,
+    pub warm_up_detector_tolerance: f64,
+    pub starvation_threshold: f64,
+    pub router_channel: ChannelConfig,
+    pub bathroom_channel: ChannelConfig,
+    pub person_channel: ChannelConfig,
+    pub metrics_collector_channel: ChannelConfig,
+    pub statistic_mode: StatisticMode,
+    pub report_raw_measures: bool,
+    pub reported_percentiles: Vec<f64>,
+    pub percentile_interpolation: Interpolation,
+    pub time_series_bucket: f64,
+    pub enable_tracing: bool,
+    pub bridge_address: Option<String>,
+    pub enable_controller: bool,
+    pub controller_interval: f64,
+    pub controller_queue_threshold: usize,
+    pub controller_wait_ratio: f64,
+    pub controller_adjustment: f64,
+    pub controller_min_max_use_time_threshold: f64,
+    pub controller_smoothing: f64,
+    pub enable_monitor: bool,
+    pub monitor_query_interval: f64,
+    // Real time
+    pub monitor_request_timeout: f64,
+}
+
+// Parameters of the bathroom and its workload which may differ between runs of the same process,
+// e.g. those of a parameter sweep
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Parameters {
+    pub bathroom_size: usize,
+    // Simulated time
+    pub max_use_time_threshold: Duration,
+    // As many as there are cabins when None
+    pub max_uses_per_turn: Option<u32>,
+    pub person_generation_rate: f64,
+    pub female_arrival_share: f64,
+    pub implementation: Implementation,
+}
+
+// How people and the bathroom synchronize
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Implementation {
+    // A bathroom actor owns its state, and people exchange events with it through the router
+    MessagePassing,
+    // People lock the bathroom's state themselves, and block on a condition variable until they
+    // may enter (see `locking.rs`)
+    LockBased,
+    // People get past a lightswitch of their gender and take a cabin from a counting semaphore, both
+    // on atomics (see `lock_free.rs`)
+    LockFree,
+}
+
+// The parameters defined in `simulation.rs`
+pub fn default_parameters() -> Parameters {
+    return Parameters {
+        bathroom_size: super::BATHROOM_SIZE,
+        max_use_time_threshold: super::MAX_USE_TIME_THRESHOLD,
+        max_uses_per_turn: super::MAX_USES_PER_TURN,
+        person_generation_rate: super::PERSON_GENERATION_RATE,
+        female_arrival_share: super::FEMALE_ARRIVAL_SHARE,
+        implementation: super::IMPLEMENTATION,
+    };
+}
+
+// The parameters defined in `simulation.rs`, but for those a run was given
+pub fn current_config(parameters: &Parameters) -> Config {
+    return Config {
+        time_scale: super::TIME_SCALE,
+        rx_polling_wait: super::RX_POLLING_WAIT.as_secs_f64(),
+        min_person_bathroom_seconds: super::MIN_PERSON_BATHROOM_SECONDS,
+        max_person_bathroom_seconds: super::MAX_PERSON_BATHROOM_SECONDS,
+        person_generation_interval: super::PERSON_GENERATION_INTERVAL.as_secs_f64(),
+        person_generation_rate: parameters.person_generation_rate,
+        female_arrival_share: parameters.female_arrival_share,
+        bathroom_size: parameters.bathroom_size,
+        max_use_time_threshold: parameters.max_use_time_threshold.as_secs_f64(),
+        max_uses_per_turn: parameters.max_uses_per_turn,
+        implementation: parameters.implementation,
+        warm_up: super::WARM_UP.map(|warm_up| format!("{:?}", warm_up)),
+        warm_up_detector_window: super::WARM_UP_DETECTOR_WINDOW.as_secs_f64(),
+        warm_up_detector_windows: super::WARM_UP_DETECTOR_WINDOWS,
+        warm_up_detector_tolerance: super::WARM_UP_DETECTOR_TOLERANCE,
+        starvation_threshold: super::STARVATION_THRESHOLD.as_secs_f64(),
+        router_channel: super::ROUTER_CHANNEL,
+        bathroom_channel: super::BATHROOM_CHANNEL,
+        person_channel: super::PERSON_CHANNEL,
+        metrics_collector_channel: super::METRICS_COLLECTOR_CHANNEL,
+        statistic_mode: super::STATISTIC_MODE,
+        report_raw_measures: super::REPORT_RAW_MEASURES,
+        reported_percentiles: super::REPORTED_PERCENTILES.to_vec(),
+        percentile_interpolation: super::PERCENTILE_INTERPOLATION,
+        time_series_bucket: super::TIME_SERIES_BUCKET.as_secs_f64(),
+        enable_tracing: super::ENABLE_TRACING,
+        bridge_address: super::BRIDGE_ADDRESS.map(|address| format!("{:?}", address)),
+        enable_controller: super::ENABLE_CONTROLLER,
+        controller_interval: super::CONTROLLER_INTERVAL.as_secs_f64(),
+        controller_queue_threshold: super::CONTROLLER_QUEUE_THRESHOLD,
+        controller_wait_ratio: super::CONTROLLER_WAIT_RATIO,
+        controller_adjustment: super::CONTROLLER_ADJUSTMENT,
+        controller_min_max_use_time_threshold: super::CONTROLLER_MIN_MAX_USE_TIME_THRESHOLD
+            .as_secs_f64(),
+        controller_smoothing: super::CONTROLLER_SMOOTHING,
+        enable_monitor: super::ENABLE_MONITOR,
+        monitor_query_interval: super::MONITOR_QUERY_INTERVAL.as_secs_f64(),
+        monitor_request_timeout: super::MONITOR_REQUEST_TIMEOUT.as_secs_f64(),
+    };
+}
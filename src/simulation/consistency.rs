@@ -0,0 +1,239 @@
+use serde::Serialize;
+use std::time::Instant;
+
+use super::metrics_collector::{self, MetricsCollector};
+use super::person::Person;
+
+// A relation the metrics of a run must satisfy, which only fails through lost events (e.g.
+// dropped by a full channel) or bookkeeping bugs (e.g. in `Bathroom`)
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub name: String,
+    // Both sides of the relation, along with how they were worked out
+    pub expected: f64,
+    pub actual: f64,
+    pub description: String,
+    // Relative difference allowed between both sides
+    pub tolerance: f64,
+    pub passed: bool,
+}
+
+// Values under 1 count as 1 when comparing, so that nearly empty queues don't fail on timing noise
+fn new_check(name: &str, expected: f64, actual: f64, description: String, tolerance: f64) -> Check {
+    let scale = expected.abs().max(actual.abs()).max(1.0);
+
+    return Check {
+        name: name.to_string(),
+        expected,
+        actual,
+        description,
+        tolerance,
+        passed: (actual - expected).abs() <= tolerance * scale,
+    };
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Consistency {
+    pub checks: Vec<Check>,
+    // Descriptions of the checks that failed
+    pub violations: Vec<String>,
+}
+
+pub fn new_consistency() -> Consistency {
+    return Consistency {
+        checks: vec![],
+        violations: vec![],
+    };
+}
+
+// Checks the metrics of a run once it finished at `at`:
+// - conservation: everyone who arrived (or was there when the warm-up ended) either left, is still
+//   queued, or is still in a cabin
+// - Little's law, L = λW, for the queue and for the whole system (queue and cabins), W counting the
+//   time spent so far by people still there. Both sides match up to timing noise when the whole run
+//   is measured, while people who arrived during the warm-up bring in some of their waits from
+//   before it
+pub fn check_consistency(metrics_collector: &MetricsCollector, at: Instant) -> Consistency {
+    let measured_from = metrics_collector.measured_from;
+    // Simulated seconds spent since joining the queue, within the measured period
+    let time_since_joining = |person: &Person| match person.joined_queue_at {
+        Some(joined_queue_at) => {
+            metrics_collector::simulated_seconds(joined_queue_at.max(measured_from), at)
+        }
+        None => 0.0,
+    };
+
+    let (queued, in_cabins): (Vec<Person>, Vec<Person>) =
+        match &metrics_collector.last_bathroom_snapshot {
+            Some(bathroom) => (
+                bathroom
+                    .male_queue
+                    .iter()
+                    .chain(bathroom.female_queue.iter())
+                    .cloned()
+                    .collect(),
+                bathroom.cabins.iter().flatten().cloned().collect(),
+            ),
+            None => (vec![], vec![]),
+        };
+
+    let mut checks = vec![new_check(
+        "conservation",
+        (metrics_collector.arrivals + metrics_collector.present_at_start) as f64,
+        (metrics_collector.departures + queued.len() as u64 + in_cabins.len() as u64) as f64,
+        format!(
+            "{} arrivals + {} present at the start vs {} departures + {} queued + {} in cabins",
+            metrics_collector.arrivals,
+            metrics_collector.present_at_start,
+            metrics_collector.departures,
+            queued.len(),
+            in_cabins.len()
+        ),
+        0.0,
+    )];
+
+    if metrics_collector.measured_duration > 0.0 {
+        let arrival_rate = metrics_collector.arrivals as f64 / metrics_collector.measured_duration;
+        let mean = |sum: f64, count: u64| {
+            if count > 0 {
+                sum / count as f64
+            } else {
+                0.0
+            }
+        };
+
+        let male_wait = &metrics_collector.male_personal_total_wait_time;
+        let female_wait = &metrics_collector.female_personal_total_wait_time;
+        let mean_wait = mean(
+            male_wait.sum + female_wait.sum + queued.iter().map(time_since_joining).sum::<f64>(),
+            male_wait.count + female_wait.count + queued.len() as u64,
+        );
+        let mean_queue_length =
+            metrics_collector.male_queue_length.avg + metrics_collector.female_queue_length.avg;
+        checks.push(new_check(
+            "littles_law_queue",
+            arrival_rate * mean_wait,
+            mean_queue_length,
+            format!(
+                "Lq = {:.3} vs λ × Wq = {:.4}/s × {:.1}s = {:.3}",
+                mean_queue_length,
+                arrival_rate,
+                mean_wait,
+                arrival_rate * mean_wait
+            ),
+            super::LITTLES_LAW_TOLERANCE,
+        ));
+
+        let male_time_spent = &metrics_collector.male_personal_total_time_spent;
+        let female_time_spent = &metrics_collector.female_personal_total_time_spent;
+        let mean_time_in_system = mean(
+            male_time_spent.sum
+                + female_time_spent.sum
+                + queued
+                    .iter()
+                    .chain(in_cabins.iter())
+                    .map(time_since_joining)
+                    .sum::<f64>(),
+            male_time_spent.count
+                + female_time_spent.count
+                + queued.len() as u64
+                + in_cabins.len() as u64,
+        );
+        let mean_number_in_system = mean_queue_length + metrics_collector.occupied_cabins.avg;
+        checks.push(new_check(
+            "littles_law_system",
+            arrival_rate * mean_time_in_system,
+            mean_number_in_system,
+            format!(
+                "L = {:.3} vs λ × W = {:.4}/s × {:.1}s = {:.3}",
+                mean_number_in_system,
+                arrival_rate,
+                mean_time_in_system,
+                arrival_rate * mean_time_in_system
+            ),
+            super::LITTLES_LAW_TOLERANCE,
+        ));
+    }
+
+    let violations = checks
+        .iter()
+        .filter(|check| !check.passed)
+        .map(|check| format!("{}: {}", check.name, check.description))
+        .collect();
+
+    return Consistency { checks, violations };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::bathroom;
+    use crate::simulation::config;
+    use crate::simulation::person::{self, Gender};
+
+    // A run where 10 women arrived in 100 simulated seconds, waited 10s each on average, and the
+    // queue held a woman on average, so that λ × Wq = 0.1/s × 10s = 1 = Lq
+    fn consistent_run() -> MetricsCollector {
+        let mut metrics_collector = metrics_collector::new_metrics_collector();
+        metrics_collector.arrivals = 10;
+        metrics_collector.departures = 10;
+        metrics_collector.measured_duration = 100.0;
+        (0..10).for_each(|_| {
+            metrics_collector
+                .female_personal_total_wait_time
+                .add_measure(10.0);
+            metrics_collector
+                .female_personal_total_time_spent
+                .add_measure(20.0);
+        });
+        metrics_collector.female_queue_length.avg = 1.0;
+        metrics_collector.occupied_cabins.avg = 1.0;
+        return metrics_collector;
+    }
+
+    #[test]
+    fn consistent_runs_pass_every_check() {
+        let consistency = check_consistency(&consistent_run(), Instant::now());
+
+        assert_eq!(consistency.checks.len(), 3);
+        assert!(consistency.checks.iter().all(|check| check.passed));
+        assert!(consistency.violations.is_empty());
+    }
+
+    #[test]
+    fn lost_people_violate_conservation() {
+        let mut metrics_collector = consistent_run();
+        // 10 arrivals, but only 7 departures, 1 queued and 1 in a cabin
+        metrics_collector.departures = 7;
+        let mut bathroom = bathroom::new_bathroom(Gender::Female, &config::default_parameters());
+        bathroom.enqueue(person::new_person(Gender::Female));
+        bathroom.cabins[0] = Some(person::new_person(Gender::Female));
+        metrics_collector.last_bathroom_snapshot = Some(bathroom);
+
+        let consistency = check_consistency(&metrics_collector, Instant::now());
+
+        let conservation = &consistency.checks[0];
+        assert_eq!(conservation.name, "conservation");
+        assert!(!conservation.passed);
+        assert_eq!(conservation.expected, 10.0);
+        assert_eq!(conservation.actual, 9.0);
+        assert!(consistency.violations[0].starts_with("conservation: 10 arrivals"));
+    }
+
+    #[test]
+    fn a_queue_longer_than_littles_law_allows_is_a_violation() {
+        let mut metrics_collector = consistent_run();
+        metrics_collector.female_queue_length.avg = 5.0;
+
+        let consistency = check_consistency(&metrics_collector, Instant::now());
+
+        let failed: Vec<&str> = consistency
+            .checks
+            .iter()
+            .filter(|check| !check.passed)
+            .map(|check| check.name.as_str())
+            .collect();
+        assert_eq!(failed, vec!["littles_law_queue", "littles_law_system"]);
+        assert_eq!(consistency.violations.len(), 2);
+    }
+}
@@ -0,0 +1,182 @@
+use super::bathroom::{Bathroom, Policy};
+use super::person::Gender;
+
+// Live metrics of the bathroom, kept up to date from routed events, from which its policy is
+// retuned while the simulation runs
+#[derive(Clone, Debug)]
+pub struct Controller {
+    // The policy the bathroom started with, which adjustments are bounded by and relax back to
+    pub base_policy: Policy,
+    pub policy: Policy,
+    pub male_queue_length: usize,
+    pub female_queue_length: usize,
+    // Exponentially weighted moving averages of each gender's waits, in simulated seconds
+    pub male_mean_wait: f64,
+    pub female_mean_wait: f64,
+}
+
+pub fn new_controller(policy: Policy) -> Controller {
+    return Controller {
+        base_policy: policy,
+        policy,
+        male_queue_length: 0,
+        female_queue_length: 0,
+        male_mean_wait: 0.0,
+        female_mean_wait: 0.0,
+    };
+}
+
+impl Controller {
+    pub fn record_bathroom_state(&mut self, bathroom: &Bathroom) {
+        self.male_queue_length = bathroom.male_queue.len();
+        self.female_queue_length = bathroom.female_queue.len();
+    }
+
+    pub fn record_wait(&mut self, gender: Gender, wait: f64) {
+        let mean_wait = match gender {
+            Gender::Male => &mut self.male_mean_wait,
+            Gender::Female => &mut self.female_mean_wait,
+        };
+        *mean_wait += super::CONTROLLER_SMOOTHING * (wait - *mean_wait);
+    }
+
+    // The policy the bathroom should follow from now on, None if it should keep its current one.
+    // Each call moves the policy one CONTROLLER_ADJUSTMENT step away from (or back towards) the
+    // base policy
+    pub fn next_policy(&mut self) -> Option<Policy> {
+        let mut policy = self.policy;
+
+        // Long queues call for shorter turns, so that both genders get in more often
+        let min_threshold = super::CONTROLLER_MIN_MAX_USE_TIME_THRESHOLD
+            .min(self.base_policy.max_use_time_threshold);
+        policy.max_use_time_threshold = if self.male_queue_length + self.female_queue_length
+            > super::CONTROLLER_QUEUE_THRESHOLD
+        {
+            policy
+                .max_use_time_threshold
+                .mul_f64(super::CONTROLLER_ADJUSTMENT)
+                .max(min_threshold)
+        } else {
+            policy
+                .max_use_time_threshold
+                .div_f64(super::CONTROLLER_ADJUSTMENT)
+                .min(self.base_policy.max_use_time_threshold)
+        };
+
+        // A gender waiting disproportionately longer than the other calls for switching earlier.
+        // Mean waits under a second count as a second, so that nearly instant waits don't make
+        // any wait look disproportionate
+        let longer_wait = self.male_mean_wait.max(self.female_mean_wait);
+        let shorter_wait = self.male_mean_wait.min(self.female_mean_wait).max(1.0);
+        policy.max_uses_per_turn = if longer_wait > super::CONTROLLER_WAIT_RATIO * shorter_wait {
+            ((policy.max_uses_per_turn as f64 * super::CONTROLLER_ADJUSTMENT).floor() as u32).max(1)
+        } else {
+            ((policy.max_uses_per_turn as f64 / super::CONTROLLER_ADJUSTMENT).ceil() as u32)
+                .min(self.base_policy.max_uses_per_turn)
+        };
+
+        if policy == self.policy {
+            return None;
+        }
+
+        self.policy = policy;
+        return Some(policy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::bathroom::new_bathroom;
+    use crate::simulation::person::new_person;
+    use crate::simulation::{
+        config, CONTROLLER_ADJUSTMENT, CONTROLLER_MIN_MAX_USE_TIME_THRESHOLD,
+        CONTROLLER_QUEUE_THRESHOLD, CONTROLLER_SMOOTHING,
+    };
+    use std::time::Duration;
+
+    fn base_policy() -> Policy {
+        return Policy {
+            max_use_time_threshold: Duration::from_secs(10 * 60),
+            max_uses_per_turn: 10,
+        };
+    }
+
+    // A controller whose bathroom has `queued` men waiting
+    fn controller_with_queue(queued: usize) -> Controller {
+        let mut controller = new_controller(base_policy());
+        let mut bathroom = new_bathroom(Gender::Male, &config::default_parameters());
+        bathroom.male_queue = (0..queued).map(|_| new_person(Gender::Male)).collect();
+        controller.record_bathroom_state(&bathroom);
+        return controller;
+    }
+
+    #[test]
+    fn waits_are_smoothed_into_an_exponentially_weighted_average() {
+        let mut controller = new_controller(base_policy());
+
+        controller.record_wait(Gender::Female, 100.0);
+        assert_eq!(controller.female_mean_wait, CONTROLLER_SMOOTHING * 100.0);
+        assert_eq!(controller.male_mean_wait, 0.0);
+
+        let mean_wait = controller.female_mean_wait;
+        controller.record_wait(Gender::Female, 0.0);
+        assert_eq!(
+            controller.female_mean_wait,
+            mean_wait - CONTROLLER_SMOOTHING * mean_wait
+        );
+    }
+
+    #[test]
+    fn long_queues_shorten_turns_one_step_at_a_time_down_to_the_minimum() {
+        let mut controller = controller_with_queue(CONTROLLER_QUEUE_THRESHOLD + 1);
+
+        let policy = controller.next_policy().unwrap();
+        assert_eq!(
+            policy.max_use_time_threshold,
+            base_policy()
+                .max_use_time_threshold
+                .mul_f64(CONTROLLER_ADJUSTMENT)
+        );
+        assert_eq!(controller.policy, policy);
+
+        while controller.next_policy().is_some() {}
+        assert_eq!(
+            controller.policy.max_use_time_threshold,
+            CONTROLLER_MIN_MAX_USE_TIME_THRESHOLD
+        );
+    }
+
+    #[test]
+    fn short_queues_relax_turns_back_to_the_base_policy() {
+        let mut controller = controller_with_queue(CONTROLLER_QUEUE_THRESHOLD + 1);
+        (0..3).for_each(|_| {
+            controller.next_policy();
+        });
+
+        controller
+            .record_bathroom_state(&new_bathroom(Gender::Male, &config::default_parameters()));
+        while controller.next_policy().is_some() {}
+        assert_eq!(controller.policy, base_policy());
+        assert_eq!(controller.next_policy(), None);
+    }
+
+    #[test]
+    fn disproportionate_waits_cut_the_uses_per_turn_down_to_one() {
+        let mut controller = controller_with_queue(0);
+        (0..20).for_each(|_| controller.record_wait(Gender::Female, 600.0));
+
+        let policy = controller.next_policy().unwrap();
+        assert_eq!(
+            policy.max_uses_per_turn,
+            (10.0 * CONTROLLER_ADJUSTMENT).floor() as u32
+        );
+
+        while controller.next_policy().is_some() {}
+        assert_eq!(controller.policy.max_uses_per_turn, 1);
+        // Once the waits even out, turns relax back to the base policy
+        (0..100).for_each(|_| controller.record_wait(Gender::Female, 0.0));
+        while controller.next_policy().is_some() {}
+        assert_eq!(controller.policy.max_uses_per_turn, 10);
+    }
+}
@@ -1,6 +1,8 @@
-use std::sync::mpsc::Sender;
+use std::time::Instant;
 use uuid::Uuid;
 
+use super::channel::Sender;
+
 // Person events
 pub const NEW_PERSON: &str = "new_person";
 pub const PERSON_JOINED_THE_QUEUE: &str = "person_joined_the_queue";
@@ -11,30 +13,71 @@ pub const PERSON_LEFT_THE_BATHROOM: &str = "person_left_the_bathroom";
 // Bathroom events
 pub const NEW_BATHROOM: &str = "new_bathroom";
 pub const BATHROOM_SWITCHED_GENDERS: &str = "bathroom_switched_genders";
+pub const BATHROOM_STATE_REQUESTED: &str = "bathroom_state_requested";
+pub const BATHROOM_STATE_REPLIED: &str = "bathroom_state_replied";
+pub const BATHROOM_POLICY_UPDATED: &str = "bathroom_policy_updated";
+
+// Monitor events
+pub const NEW_MONITOR: &str = "new_monitor";
+
+// Invariant monitor events
+pub const INVARIANT_VIOLATED: &str = "invariant_violated";
+
+// Watchdog events
+pub const HEARTBEAT: &str = "heartbeat";
+pub const LIVENESS_CHECK_FAILED: &str = "liveness_check_failed";
 
 // Simulation events
 pub const SIMULATION_STOPPED: &str = "simulation_stopped";
 pub const SIMULATION_FINISHED: &str = "simulation_finished";
 
-pub const ALL_EVENTS: [&str; 8] = [
+pub const ALL_EVENTS: [&str; 15] = [
     NEW_BATHROOM,
+    NEW_MONITOR,
     NEW_PERSON,
     PERSON_JOINED_THE_QUEUE,
     PERSON_ENTERED_THE_BATHROOM,
     PERSON_FINISHED_USING_BATHROOM,
     PERSON_LEFT_THE_BATHROOM,
     BATHROOM_SWITCHED_GENDERS,
+    BATHROOM_STATE_REQUESTED,
+    BATHROOM_STATE_REPLIED,
+    BATHROOM_POLICY_UPDATED,
+    INVARIANT_VIOLATED,
+    HEARTBEAT,
+    LIVENESS_CHECK_FAILED,
     SIMULATION_STOPPED,
 ];
 
 #[derive(Clone, Debug)]
 pub struct Event {
+    pub id: Uuid,
     pub name: String,
     pub producer_id: Uuid,
     pub destination_id: Option<Uuid>,
-    pub producer_sender: Option<Sender<Event>>,
+    pub producer_sender: Option<Sender>,
     pub person_snapshot: Option<super::person::Person>,
     pub bathroom_snapshot: Option<super::bathroom::Bathroom>,
+    pub sent_at: Instant,
+    // Requests carry the id of the actor expecting the reply, and replies carry the correlation
+    // id of the request they answer
+    pub correlation_id: Option<Uuid>,
+    pub reply_to: Option<Uuid>,
+    // Events about a person share the person's id as their trace id (their journey), and point to
+    // the event that caused them, e.g. PERSON_ENTERED_THE_BATHROOM -> PERSON_JOINED_THE_QUEUE
+    pub trace_id: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
+    // Set by the router when it receives the event
+    pub routed_at: Option<Instant>,
+    // Set on BATHROOM_SWITCHED_GENDERS events
+    pub switch_rule: Option<super::bathroom::SwitchRule>,
+    // Set on BATHROOM_POLICY_UPDATED events
+    pub policy: Option<super::bathroom::Policy>,
+    // Set on INVARIANT_VIOLATED and LIVENESS_CHECK_FAILED events, describes what went wrong
+    pub violation: Option<String>,
+    // The kind of actor producing the event, e.g. "bathroom". Set on the creation events actors
+    // register with, on HEARTBEAT events, and on every event of actors that never register
+    pub actor: Option<String>,
 }
 
 pub fn new_event(
@@ -44,13 +87,26 @@ pub fn new_event(
     person: Option<super::person::Person>,
     bathroom: Option<super::bathroom::Bathroom>,
 ) -> Event {
+    let trace_id = person.as_ref().map(|person| person.id);
+
     return Event {
+        id: Uuid::new_v4(),
         name,
         producer_id,
         destination_id,
         producer_sender: None,
         person_snapshot: person,
         bathroom_snapshot: bathroom,
+        sent_at: Instant::now(),
+        correlation_id: None,
+        reply_to: None,
+        trace_id,
+        parent_id: None,
+        routed_at: None,
+        switch_rule: None,
+        policy: None,
+        violation: None,
+        actor: None,
     };
 }
 
@@ -58,15 +114,68 @@ pub fn new_creation_event(
     name: String,
     producer_id: Uuid,
     destination_id: Option<Uuid>,
-    producer_sender: Sender<Event>,
+    producer_sender: Sender,
     person: Option<super::person::Person>,
 ) -> Event {
+    let trace_id = person.as_ref().map(|person| person.id);
+
     return Event {
+        id: Uuid::new_v4(),
         name,
         producer_id,
         destination_id,
         producer_sender: Some(producer_sender),
         person_snapshot: person,
         bathroom_snapshot: None,
+        sent_at: Instant::now(),
+        correlation_id: None,
+        reply_to: None,
+        trace_id,
+        parent_id: None,
+        routed_at: None,
+        switch_rule: None,
+        policy: None,
+        violation: None,
+        actor: None,
     };
 }
+
+pub fn new_request_event(name: String, producer_id: Uuid, destination_id: Uuid) -> Event {
+    let mut event = new_event(name, producer_id, Some(destination_id), None, None);
+    event.correlation_id = Some(Uuid::new_v4());
+    event.reply_to = Some(producer_id);
+    return event;
+}
+
+pub fn new_reply_event(
+    name: String,
+    producer_id: Uuid,
+    request: &Event,
+    person: Option<super::person::Person>,
+    bathroom: Option<super::bathroom::Bathroom>,
+) -> Event {
+    let mut event = new_event(name, producer_id, request.reply_to, person, bathroom);
+    event.correlation_id = request.correlation_id;
+    return event.caused_by(request);
+}
+
+impl Event {
+    pub fn with_parent(mut self, parent_id: Uuid) -> Event {
+        self.parent_id = Some(parent_id);
+        return self;
+    }
+
+    pub fn with_actor(mut self, actor: &str) -> Event {
+        self.actor = Some(actor.to_string());
+        return self;
+    }
+
+    // Makes this event a consequence of `parent`, inheriting its trace if it has none of its own
+    pub fn caused_by(mut self, parent: &Event) -> Event {
+        self.parent_id = Some(parent.id);
+        if self.trace_id.is_none() {
+            self.trace_id = parent.trace_id;
+        }
+        return self;
+    }
+}
@@ -0,0 +1,218 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// HDR-style log-linear histogram: values below 2^SUB_BUCKET_BITS are counted exactly, larger
+// values fall into buckets whose width grows with the value, so every recorded value is known
+// within a relative error of 2^-(SUB_BUCKET_BITS - 1) (under 1.6%) using a bounded amount of memory
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKET_COUNT: u64 = 1 << SUB_BUCKET_BITS;
+const SUB_BUCKET_HALF_COUNT: u64 = SUB_BUCKET_COUNT / 2;
+// Values are recorded as integer multiples of 1/RESOLUTION (e.g. milliseconds for seconds), and
+// negative values are recorded as zero
+const RESOLUTION: f64 = 1000.0;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HistogramBucket {
+    pub low: f64,
+    pub high: f64,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    // Only non-empty buckets are kept, there are at most a few thousand of them
+    counts: BTreeMap<u64, u64>,
+    pub count: u64,
+}
+
+pub fn new_histogram() -> Histogram {
+    return Histogram {
+        counts: BTreeMap::new(),
+        count: 0,
+    };
+}
+
+fn bucket_index(value: u64) -> u64 {
+    if value < SUB_BUCKET_COUNT {
+        return value;
+    }
+
+    let exponent = 63 - value.leading_zeros() as u64;
+    let shift = exponent - SUB_BUCKET_BITS as u64 + 1;
+    let mantissa = value >> shift;
+
+    return SUB_BUCKET_COUNT
+        + (shift - 1) * SUB_BUCKET_HALF_COUNT
+        + (mantissa - SUB_BUCKET_HALF_COUNT);
+}
+
+// Inclusive range of values counted in the bucket
+fn bucket_range(index: u64) -> (u64, u64) {
+    if index < SUB_BUCKET_COUNT {
+        return (index, index);
+    }
+
+    let shift = (index - SUB_BUCKET_COUNT) / SUB_BUCKET_HALF_COUNT + 1;
+    let mantissa = (index - SUB_BUCKET_COUNT) % SUB_BUCKET_HALF_COUNT + SUB_BUCKET_HALF_COUNT;
+    let low = mantissa << shift;
+
+    return (low, low + ((1 << shift) - 1));
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: f64) {
+        let units = (value.max(0.0) * RESOLUTION).round() as u64;
+        *self.counts.entry(bucket_index(units)).or_insert(0) += 1;
+        self.count += 1;
+    }
+
+    // Adds the values recorded by `other`, which share the buckets of this histogram
+    pub fn merge(&mut self, other: &Histogram) {
+        for (index, count) in &other.counts {
+            *self.counts.entry(*index).or_insert(0) += count;
+        }
+        self.count += other.count;
+    }
+
+    // Value of the measure at `rank` (0-based) among all recorded values in ascending order,
+    // approximated by the middle of its bucket
+    pub fn value_at_rank(&self, rank: u64) -> Option<f64> {
+        let mut seen = 0;
+
+        for (index, count) in &self.counts {
+            seen += count;
+            if seen > rank {
+                let (low, high) = bucket_range(*index);
+                return Some((low + (high - low) / 2) as f64 / RESOLUTION);
+            }
+        }
+
+        return None;
+    }
+
+    pub fn buckets(&self) -> Vec<HistogramBucket> {
+        return self
+            .counts
+            .iter()
+            .map(|(index, count)| {
+                let (low, high) = bucket_range(*index);
+                HistogramBucket {
+                    low: low as f64 / RESOLUTION,
+                    high: high as f64 / RESOLUTION,
+                    count: *count,
+                }
+            })
+            .collect();
+    }
+}
+
+impl Serialize for Histogram {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return self.buckets().serialize(serializer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_RELATIVE_ERROR: f64 = 1.0 / (1 << (SUB_BUCKET_BITS - 1)) as f64;
+
+    // Values spread over every magnitude up to a few days in milliseconds, along with the edges
+    // of the buckets around powers of two
+    fn sample_values() -> Vec<u64> {
+        let mut values: Vec<u64> = (0..1000).collect();
+        for exponent in SUB_BUCKET_BITS..40 {
+            let power = 1u64 << exponent;
+            values.extend([power - 1, power, power + 1, power + power / 3]);
+        }
+        return values;
+    }
+
+    #[test]
+    fn every_value_falls_within_its_bucket() {
+        for value in sample_values() {
+            let (low, high) = bucket_range(bucket_index(value));
+            assert!(
+                low <= value && value <= high,
+                "{} not in {:?}",
+                value,
+                (low, high)
+            );
+        }
+    }
+
+    #[test]
+    fn small_values_are_counted_exactly() {
+        for value in 0..SUB_BUCKET_COUNT {
+            assert_eq!(bucket_range(bucket_index(value)), (value, value));
+        }
+    }
+
+    #[test]
+    fn buckets_are_contiguous() {
+        let last = bucket_index(1 << 40);
+        for index in 0..last {
+            assert_eq!(bucket_range(index).1 + 1, bucket_range(index + 1).0);
+        }
+    }
+
+    #[test]
+    fn bucket_width_stays_within_the_relative_error() {
+        for value in sample_values().into_iter().filter(|value| *value > 0) {
+            let (low, high) = bucket_range(bucket_index(value));
+            assert!((high - low) as f64 / low as f64 <= MAX_RELATIVE_ERROR);
+        }
+    }
+
+    #[test]
+    fn value_at_rank_stays_within_the_relative_error() {
+        let mut values: Vec<f64> = (1..=2000)
+            .map(|i| (i as f64 * 0.731).powf(1.7) / 10.0)
+            .collect();
+        let mut histogram = new_histogram();
+        values.iter().for_each(|value| histogram.record(*value));
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        for (rank, value) in values.iter().enumerate() {
+            let approximation = histogram.value_at_rank(rank as u64).unwrap();
+            // Recording rounds values to the resolution first
+            let tolerance = value * MAX_RELATIVE_ERROR + 0.5 / RESOLUTION;
+            assert!(
+                (approximation - value).abs() <= tolerance,
+                "rank {}: {} approximated as {}",
+                rank,
+                value,
+                approximation
+            );
+        }
+        assert_eq!(histogram.value_at_rank(values.len() as u64), None);
+    }
+
+    #[test]
+    fn merge_adds_the_counts() {
+        let mut histogram = new_histogram();
+        let mut other = new_histogram();
+        // Values under SUB_BUCKET_COUNT units are counted exactly
+        [0.001, 0.002, 0.1]
+            .iter()
+            .for_each(|value| histogram.record(*value));
+        [0.002, 5000.0]
+            .iter()
+            .for_each(|value| other.record(*value));
+        histogram.merge(&other);
+
+        assert_eq!(histogram.count, 5);
+        assert_eq!(
+            histogram
+                .buckets()
+                .iter()
+                .map(|bucket| bucket.count)
+                .sum::<u64>(),
+            5
+        );
+        assert_eq!(histogram.value_at_rank(1), Some(0.002));
+        assert_eq!(histogram.value_at_rank(2), Some(0.002));
+        assert_eq!(histogram.value_at_rank(3), Some(0.1));
+    }
+}
@@ -0,0 +1,512 @@
+use std::io::{self, Write};
+
+use super::metrics_collector::{MetricsCollector, Statistic};
+use super::person::Gender;
+use super::report::{self, Report, ReportWriter};
+use super::timeseries::TimeSeries;
+
+// Charts are inline SVG and styles are embedded, so the page opens offline
+const CHART_WIDTH: f64 = 720.0;
+const CHART_HEIGHT: f64 = 260.0;
+const MARGIN_LEFT: f64 = 60.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 30.0;
+const MARGIN_BOTTOM: f64 = 45.0;
+const TICKS: usize = 5;
+const HISTOGRAM_BINS: usize = 20;
+
+const MALE_COLOR: &str = "#1f77b4";
+const FEMALE_COLOR: &str = "#d62728";
+const OCCUPANCY_COLOR: &str = "#2ca02c";
+
+const STYLE: &str =
+    "body { font-family: sans-serif; margin: 2em auto; max-width: 960px; color: #222; }
+table { border-collapse: collapse; margin: 1em 0; font-size: 0.9em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; }
+td.number { text-align: right; font-variant-numeric: tabular-nums; }
+svg { display: block; margin: 1em 0; }
+svg text { font-size: 11px; fill: #444; }";
+
+// Makes text safe to interpolate in elements and quoted attributes. Only data from outside the code
+// needs it (hostnames, configured names, metric names and violation descriptions), not the fixed
+// labels and colors of the report
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    text.chars().for_each(|c| match c {
+        '&' => escaped += "&amp;",
+        '<' => escaped += "&lt;",
+        '>' => escaped += "&gt;",
+        '"' => escaped += "&quot;",
+        '\'' => escaped += "&#39;",
+        _ => escaped.push(c),
+    });
+    return escaped;
+}
+
+pub struct HtmlReportWriter {}
+
+impl ReportWriter for HtmlReportWriter {
+    fn extension(&self) -> &'static str {
+        return "html";
+    }
+
+    fn write(&self, report: &Report, writer: &mut dyn Write) -> io::Result<()> {
+        let metrics_collector = report.metrics;
+        let time_series = &metrics_collector.time_series;
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Bathroom simulation report</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>Bathroom simulation report</h1>\n<p>Times are in simulated seconds.</p>\n",
+            STYLE
+        );
+
+        html += "<h2>Run</h2>\n<table>\n";
+        report::metadata_summary(&report.metadata)
+            .iter()
+            .for_each(|(field, value)| {
+                html += &format!(
+                    "<tr><th>{}</th><td>{}</td></tr>\n",
+                    field,
+                    escape_html(value)
+                );
+            });
+        html += "</table>\n";
+
+        html += "<h2>Summary</h2>\n<table>\n";
+        report::summary(metrics_collector)
+            .iter()
+            .for_each(|(metric, value)| {
+                html += &format!(
+                    "<tr><th>{}</th><td class=\"number\">{}</td></tr>\n",
+                    escape_html(metric),
+                    escape_html(value)
+                );
+            });
+        html += "</table>\n";
+
+        if !metrics_collector.invariant_violations.is_empty() {
+            html += "<h2>Invariant violations</h2>\n<ul>\n";
+            metrics_collector
+                .invariant_violations
+                .iter()
+                .for_each(|violation| html += &format!("<li>{}</li>\n", escape_html(violation)));
+            html += "</ul>\n";
+        }
+        if !metrics_collector.liveness_failures.is_empty() {
+            html += "<h2>Liveness failures</h2>\n<ul>\n";
+            metrics_collector
+                .liveness_failures
+                .iter()
+                .for_each(|failure| html += &format!("<li>{}</li>\n", escape_html(failure)));
+            html += "</ul>\n";
+        }
+        if !metrics_collector.consistency.violations.is_empty() {
+            html += "<h2>Consistency violations</h2>\n<ul>\n";
+            metrics_collector
+                .consistency
+                .violations
+                .iter()
+                .for_each(|violation| html += &format!("<li>{}</li>\n", escape_html(violation)));
+            html += "</ul>\n";
+        }
+
+        html += "<h2>Wait times</h2>\n";
+        html += &histogram_chart(
+            "Wait time (s)",
+            &[
+                (
+                    "Male",
+                    MALE_COLOR,
+                    &metrics_collector.male_personal_total_wait_time,
+                ),
+                (
+                    "Female",
+                    FEMALE_COLOR,
+                    &metrics_collector.female_personal_total_wait_time,
+                ),
+            ],
+        );
+
+        // Marked on the charts, which cover the whole run
+        let warm_up_end = metrics_collector
+            .warm_up
+            .ended_at
+            .filter(|ended_at| *ended_at > 0.0);
+
+        html += "<h2>Queue lengths over time</h2>\n";
+        html += &timeline_chart(
+            "Queue length",
+            time_series,
+            &[
+                (
+                    "Male",
+                    MALE_COLOR,
+                    time_series
+                        .buckets
+                        .iter()
+                        .map(|bucket| bucket.male_queue_length)
+                        .collect(),
+                ),
+                (
+                    "Female",
+                    FEMALE_COLOR,
+                    time_series
+                        .buckets
+                        .iter()
+                        .map(|bucket| bucket.female_queue_length)
+                        .collect(),
+                ),
+            ],
+            false,
+            warm_up_end,
+        );
+
+        html += "<h2>Occupancy over time</h2>\n<p>The background shows the gender allowed in the bathroom (blue for male, red for female).</p>\n";
+        html += &timeline_chart(
+            "Occupied cabins",
+            time_series,
+            &[(
+                "Occupied cabins",
+                OCCUPANCY_COLOR,
+                time_series
+                    .buckets
+                    .iter()
+                    .map(|bucket| bucket.occupied_cabins)
+                    .collect(),
+            )],
+            true,
+            warm_up_end,
+        );
+
+        html += "<h2>Statistics</h2>\n";
+        html += &statistics_table(metrics_collector);
+        html += "</body>\n</html>\n";
+
+        writer.write_all(html.as_bytes())?;
+        return writer.flush();
+    }
+}
+
+fn statistics_table(metrics_collector: &MetricsCollector) -> String {
+    let mut table = "<table>\n<tr><th>Statistic</th><th>Count</th><th>Avg</th><th>Std dev</th><th>Min</th><th>Median</th>".to_string();
+    report::percentile_names()
+        .iter()
+        .for_each(|name| table += &format!("<th>{}</th>", escape_html(name)));
+    table += "<th>Max</th></tr>\n";
+
+    for (name, statistic) in metrics_collector.statistics() {
+        table += &format!(
+            "<tr><th>{}</th>{}",
+            escape_html(name),
+            number_cell(statistic.count as f64)
+        );
+        [
+            statistic.avg,
+            statistic.std_dev,
+            statistic.min,
+            statistic.median,
+        ]
+        .iter()
+        .chain(
+            report::percentile_values(statistic)
+                .collect::<Vec<f64>>()
+                .iter(),
+        )
+        .chain([statistic.max].iter())
+        .for_each(|value| table += &number_cell(*value));
+        table += "</tr>\n";
+    }
+
+    return table + "</table>\n";
+}
+
+fn number_cell(value: f64) -> String {
+    if value.fract() == 0.0 {
+        return format!("<td class=\"number\">{}</td>", value);
+    }
+
+    return format!("<td class=\"number\">{:.2}</td>", value);
+}
+
+fn plot_width() -> f64 {
+    return CHART_WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+}
+
+fn plot_height() -> f64 {
+    return CHART_HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+}
+
+fn x_position(x: f64, x_max: f64) -> f64 {
+    return MARGIN_LEFT + x / x_max * plot_width();
+}
+
+fn y_position(y: f64, y_max: f64) -> f64 {
+    return MARGIN_TOP + plot_height() - y / y_max * plot_height();
+}
+
+fn tick_label(value: f64) -> String {
+    if value.abs() >= 10.0 || value.fract() == 0.0 {
+        return format!("{:.0}", value);
+    }
+
+    return format!("{:.1}", value);
+}
+
+// Axes with evenly spaced ticks, and a legend of the series above the plot
+fn axes(x_label: &str, y_label: &str, x_max: f64, y_max: f64, legend: &[(&str, &str)]) -> String {
+    let bottom = MARGIN_TOP + plot_height();
+    let right = MARGIN_LEFT + plot_width();
+    let mut svg = String::new();
+
+    (0..=TICKS).for_each(|tick| {
+        let x_value = x_max * tick as f64 / TICKS as f64;
+        let y_value = y_max * tick as f64 / TICKS as f64;
+        let x = x_position(x_value, x_max);
+        let y = y_position(y_value, y_max);
+
+        svg += &format!(
+            "<line x1=\"{x:.1}\" y1=\"{b}\" x2=\"{x:.1}\" y2=\"{}\" stroke=\"#444\"/><text x=\"{x:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            bottom + 4.0,
+            bottom + 16.0,
+            tick_label(x_value),
+            x = x,
+            b = bottom
+        );
+        svg += &format!(
+            "<line x1=\"{}\" y1=\"{y:.1}\" x2=\"{}\" y2=\"{y:.1}\" stroke=\"#eee\"/><text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            MARGIN_LEFT,
+            right,
+            MARGIN_LEFT - 6.0,
+            y + 4.0,
+            tick_label(y_value),
+            y = y
+        );
+    });
+
+    // Drawn over the grid
+    svg += &format!(
+        "<line x1=\"{l}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"#444\"/><line x1=\"{l}\" y1=\"{t}\" x2=\"{l}\" y2=\"{b}\" stroke=\"#444\"/>",
+        l = MARGIN_LEFT,
+        r = right,
+        t = MARGIN_TOP,
+        b = bottom
+    );
+
+    svg += &format!(
+        "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text><text x=\"14\" y=\"{:.1}\" text-anchor=\"middle\" transform=\"rotate(-90 14 {:.1})\">{}</text>",
+        MARGIN_LEFT + plot_width() / 2.0,
+        CHART_HEIGHT - 8.0,
+        x_label,
+        MARGIN_TOP + plot_height() / 2.0,
+        MARGIN_TOP + plot_height() / 2.0,
+        y_label
+    );
+
+    legend.iter().enumerate().for_each(|(i, (name, color))| {
+        let x = MARGIN_LEFT + i as f64 * 140.0;
+        svg += &format!(
+            "<rect x=\"{}\" y=\"8\" width=\"12\" height=\"12\" fill=\"{}\"/><text x=\"{}\" y=\"18\">{}</text>",
+            x,
+            color,
+            x + 16.0,
+            name
+        );
+    });
+
+    return svg;
+}
+
+fn svg(content: String) -> String {
+    return format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">{}</svg>\n",
+        content,
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT
+    );
+}
+
+// Measures of the statistic (or the midpoints of its histogram buckets in streaming mode), each
+// with how many times it was measured
+fn weighted_measures(statistic: &Statistic) -> Vec<(f64, u64)> {
+    return match &statistic.histogram {
+        Some(histogram) => histogram
+            .buckets()
+            .iter()
+            .map(|bucket| ((bucket.low + bucket.high) / 2.0, bucket.count))
+            .collect(),
+        None => statistic
+            .measures
+            .iter()
+            .map(|measure| (*measure, 1))
+            .collect(),
+    };
+}
+
+// Side-by-side bars of how many measures of each statistic fall into each of HISTOGRAM_BINS bins
+fn histogram_chart(x_label: &str, groups: &[(&str, &str, &Statistic)]) -> String {
+    if groups.iter().all(|(_, _, statistic)| statistic.count == 0) {
+        return "<p>No measures yet.</p>\n".to_string();
+    }
+
+    let x_max = groups
+        .iter()
+        .map(|(_, _, statistic)| statistic.max)
+        .fold(0.0, f64::max);
+    let x_max = if x_max > 0.0 { x_max } else { 1.0 };
+
+    let bins: Vec<Vec<u64>> = groups
+        .iter()
+        .map(|(_, _, statistic)| {
+            let mut counts = vec![0; HISTOGRAM_BINS];
+            weighted_measures(statistic)
+                .iter()
+                .for_each(|(measure, count)| {
+                    let bin = (measure.max(0.0) / x_max * HISTOGRAM_BINS as f64) as usize;
+                    counts[bin.min(HISTOGRAM_BINS - 1)] += count;
+                });
+            counts
+        })
+        .collect();
+    let y_max = bins.iter().flatten().copied().max().unwrap_or(0).max(1) as f64;
+
+    let legend: Vec<(&str, &str)> = groups
+        .iter()
+        .map(|(name, color, _)| (*name, *color))
+        .collect();
+    let mut content = axes(x_label, "People", x_max, y_max, &legend);
+    let bar_width = plot_width() / HISTOGRAM_BINS as f64 / groups.len() as f64;
+
+    bins.iter().enumerate().for_each(|(group, counts)| {
+        counts.iter().enumerate().for_each(|(bin, count)| {
+            let x = x_position(bin as f64 * x_max / HISTOGRAM_BINS as f64, x_max)
+                + group as f64 * bar_width;
+            let y = y_position(*count as f64, y_max);
+            content += &format!(
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"><title>{}: {}</title></rect>",
+                x,
+                y,
+                bar_width,
+                MARGIN_TOP + plot_height() - y,
+                groups[group].1,
+                groups[group].0,
+                count
+            );
+        });
+    });
+
+    return svg(content);
+}
+
+// Lines of per-bucket values over simulated minutes, optionally drawn over bands showing the gender
+// allowed in the bathroom during each bucket
+fn timeline_chart(
+    y_label: &str,
+    time_series: &TimeSeries,
+    series: &[(&str, &str, Vec<f64>)],
+    gender_bands: bool,
+    // In simulated seconds
+    warm_up_end: Option<f64>,
+) -> String {
+    if time_series.buckets.is_empty() {
+        return "<p>No data yet.</p>\n".to_string();
+    }
+
+    let bucket_minutes = time_series.bucket_width / 60.0;
+    let x_max = time_series.buckets.len() as f64 * bucket_minutes;
+    let y_max = series
+        .iter()
+        .flat_map(|(_, _, values)| values.iter().copied())
+        .fold(0.0, f64::max)
+        .max(1.0)
+        .ceil();
+
+    let mut content = String::new();
+    if gender_bands {
+        time_series.buckets.iter().for_each(|bucket| {
+            let x = x_position(bucket.start / 60.0, x_max);
+            content += &format!(
+                "<rect x=\"{:.1}\" y=\"{}\" width=\"{:.2}\" height=\"{}\" fill=\"{}\" fill-opacity=\"0.15\"/>",
+                x,
+                MARGIN_TOP,
+                x_position(bucket_minutes, x_max) - MARGIN_LEFT,
+                plot_height(),
+                match bucket.allowed_gender {
+                    Gender::Male => MALE_COLOR,
+                    Gender::Female => FEMALE_COLOR,
+                }
+            );
+        });
+    }
+
+    let legend: Vec<(&str, &str)> = series
+        .iter()
+        .map(|(name, color, _)| (*name, *color))
+        .collect();
+    content += &axes("Simulated minutes", y_label, x_max, y_max, &legend);
+
+    if let Some(warm_up_end) = warm_up_end {
+        let x = x_position((warm_up_end / 60.0).min(x_max), x_max);
+        content += &format!(
+            "<line x1=\"{x:.1}\" y1=\"{}\" x2=\"{x:.1}\" y2=\"{}\" stroke=\"#555\" stroke-dasharray=\"4 3\"/><text x=\"{:.1}\" y=\"{}\" font-size=\"11\">end of warm-up</text>",
+            MARGIN_TOP,
+            MARGIN_TOP + plot_height(),
+            x + 4.0,
+            MARGIN_TOP + 12.0
+        );
+    }
+
+    series.iter().for_each(|(_, color, values)| {
+        let points: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                format!(
+                    "{:.1},{:.1}",
+                    x_position((i as f64 + 0.5) * bucket_minutes, x_max),
+                    y_position(*value, y_max)
+                )
+            })
+            .collect();
+        content += &format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>",
+            points.join(" "),
+            color
+        );
+    });
+
+    return svg(content);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{config, metrics_collector};
+
+    #[test]
+    fn escape_html_escapes_markup_and_quotes() {
+        assert_eq!(
+            escape_html("<b>\"Tom\" & 'Jerry'</b>"),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+        assert_eq!(escape_html("host-1.local"), "host-1.local");
+    }
+
+    #[test]
+    fn outside_data_is_escaped_in_the_report() {
+        let metrics_collector = metrics_collector::new_metrics_collector();
+        let mut metadata = report::new_metadata(
+            &config::default_parameters(),
+            42,
+            chrono::offset::Local::now(),
+            0.0,
+        );
+        metadata.host.hostname = "<script>alert('rack 1 & 2')</script>".to_string();
+
+        let mut html = vec![];
+        HtmlReportWriter {}
+            .write(&report::new_report(metadata, &metrics_collector), &mut html)
+            .unwrap();
+        let html = String::from_utf8(html).unwrap();
+
+        assert!(html.contains("&lt;script&gt;alert(&#39;rack 1 &amp; 2&#39;)&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+}
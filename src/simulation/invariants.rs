@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use super::bathroom::Bathroom;
+use super::event::{self, Event};
+use super::person::Gender;
+
+// Safety properties of the bathroom, checked against every routed event: both against the
+// bathroom snapshot the event carries, if any, and against where people are according to the
+// events so far
+pub struct InvariantMonitor {
+    pub capacity: usize,
+    // People who joined the queue and haven't entered the bathroom yet
+    pub queued: HashSet<Uuid>,
+    // People in a cabin, and their gender
+    pub inside: HashMap<Uuid, Gender>,
+    // Every violation found, in the order it was found. A violation lasting over several events
+    // is only reported once
+    pub violations: Vec<String>,
+}
+
+pub fn new_invariant_monitor(capacity: usize) -> InvariantMonitor {
+    return InvariantMonitor {
+        capacity,
+        queued: HashSet::new(),
+        inside: HashMap::new(),
+        violations: vec![],
+    };
+}
+
+impl InvariantMonitor {
+    // The violations `msg` brings to light and weren't reported before
+    pub fn check(&mut self, msg: &Event) -> Vec<String> {
+        let mut violations = vec![];
+
+        if let Some(person) = &msg.person_snapshot {
+            match msg.name.as_str() {
+                event::PERSON_JOINED_THE_QUEUE => {
+                    if self.inside.contains_key(&person.id) {
+                        violations.push(format!(
+                            "person {} joined the queue while in a cabin",
+                            person.id
+                        ));
+                    }
+                    if !self.queued.insert(person.id) {
+                        violations.push(format!(
+                            "person {} joined the queue while already queued",
+                            person.id
+                        ));
+                    }
+                }
+                event::PERSON_ENTERED_THE_BATHROOM => {
+                    if !self.queued.remove(&person.id) {
+                        violations.push(format!(
+                            "person {} entered the bathroom without having joined the queue",
+                            person.id
+                        ));
+                    }
+                    if self.inside.insert(person.id, person.gender).is_some() {
+                        violations.push(format!(
+                            "person {} entered the bathroom while already in a cabin",
+                            person.id
+                        ));
+                    }
+                }
+                event::PERSON_LEFT_THE_BATHROOM => {
+                    let was_inside = self.inside.remove(&person.id).is_some();
+                    if !was_inside {
+                        violations.push(format!(
+                            "person {} left the bathroom without being in a cabin",
+                            person.id
+                        ));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if self.inside.len() > self.capacity {
+            violations.push(format!(
+                "{} people entered the bathroom and haven't left, over its {} cabins",
+                self.inside.len(),
+                self.capacity
+            ));
+        }
+        let genders: Vec<&Gender> = self.inside.values().collect();
+        if genders.iter().any(|gender| *gender != genders[0]) {
+            violations.push(format!(
+                "people of both genders entered the bathroom and haven't left ({} people)",
+                self.inside.len()
+            ));
+        }
+
+        if let Some(bathroom) = &msg.bathroom_snapshot {
+            violations.extend(check_bathroom(bathroom, self.capacity));
+        }
+
+        violations.retain(|violation| !self.violations.contains(violation));
+        self.violations.extend(violations.iter().cloned());
+        return violations;
+    }
+}
+
+// Violations visible in a single snapshot of the bathroom
+fn check_bathroom(bathroom: &Bathroom, capacity: usize) -> Vec<String> {
+    let mut violations = vec![];
+    let occupants: Vec<_> = bathroom.cabins.iter().flatten().collect();
+
+    if occupants.len() > capacity {
+        violations.push(format!(
+            "bathroom {} has {} occupants, over its {} cabins",
+            bathroom.id,
+            occupants.len(),
+            capacity
+        ));
+    }
+    // Whichever gender the bathroom allows
+    if occupants
+        .iter()
+        .any(|occupant| occupant.gender != occupants[0].gender)
+    {
+        violations.push(format!(
+            "bathroom {} has occupants of both genders ({} people)",
+            bathroom.id,
+            occupants.len()
+        ));
+    }
+    if let Some(occupant) = occupants
+        .iter()
+        .find(|occupant| occupant.gender != bathroom.allowed_gender)
+    {
+        violations.push(format!(
+            "bathroom {} allows {} people but person {} of gender {} is in a cabin",
+            bathroom.id, bathroom.allowed_gender, occupant.id, occupant.gender
+        ));
+    }
+    if let Some(person) = bathroom
+        .male_queue
+        .iter()
+        .find(|person| person.gender != Gender::Male)
+        .or_else(|| {
+            bathroom
+                .female_queue
+                .iter()
+                .find(|person| person.gender != Gender::Female)
+        })
+    {
+        violations.push(format!(
+            "bathroom {} has person {} of gender {} in the other gender's queue",
+            bathroom.id, person.id, person.gender
+        ));
+    }
+
+    let mut places = HashSet::new();
+    if let Some(person) = bathroom
+        .male_queue
+        .iter()
+        .chain(bathroom.female_queue.iter())
+        .chain(occupants.iter().copied())
+        .find(|person| !places.insert(person.id))
+    {
+        violations.push(format!(
+            "bathroom {} has person {} in two places at once",
+            bathroom.id, person.id
+        ));
+    }
+
+    return violations;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::bathroom::new_bathroom;
+    use crate::simulation::person::{new_person, Person};
+    use crate::simulation::{channel, config, spawn_invariant_monitor_thread};
+
+    fn person_event(name: &str, person: &Person) -> Event {
+        return event::new_event(
+            name.to_string(),
+            Uuid::new_v4(),
+            None,
+            Some(person.clone()),
+            None,
+        );
+    }
+
+    // A woman and a man entering a bathroom of two cabins, one after the other
+    fn mixed_gender_events() -> Vec<Event> {
+        let woman = new_person(Gender::Female);
+        let man = new_person(Gender::Male);
+
+        return vec![
+            person_event(event::PERSON_JOINED_THE_QUEUE, &woman),
+            person_event(event::PERSON_JOINED_THE_QUEUE, &man),
+            person_event(event::PERSON_ENTERED_THE_BATHROOM, &woman),
+            person_event(event::PERSON_ENTERED_THE_BATHROOM, &man),
+        ];
+    }
+
+    #[test]
+    fn people_of_both_genders_inside_violate_an_invariant_once() {
+        let mut invariant_monitor = new_invariant_monitor(2);
+        let violations: Vec<String> = mixed_gender_events()
+            .iter()
+            .flat_map(|msg| invariant_monitor.check(msg))
+            .collect();
+
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("both genders"));
+        // The violation lasts, but was already reported
+        let heartbeat = event::new_event(
+            event::HEARTBEAT.to_string(),
+            Uuid::new_v4(),
+            None,
+            None,
+            None,
+        );
+        assert!(invariant_monitor.check(&heartbeat).is_empty());
+        assert_eq!(invariant_monitor.violations, violations);
+    }
+
+    #[test]
+    fn more_people_inside_than_cabins_violate_an_invariant() {
+        let mut invariant_monitor = new_invariant_monitor(1);
+        let women: Vec<Person> = (0..2).map(|_| new_person(Gender::Female)).collect();
+
+        let violations: Vec<String> = women
+            .iter()
+            .flat_map(|woman| {
+                [
+                    person_event(event::PERSON_JOINED_THE_QUEUE, woman),
+                    person_event(event::PERSON_ENTERED_THE_BATHROOM, woman),
+                ]
+            })
+            .flat_map(|msg| invariant_monitor.check(&msg))
+            .collect();
+
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("over its 1 cabins"));
+    }
+
+    #[test]
+    fn snapshots_of_events_about_no_person_are_checked_too() {
+        let mut invariant_monitor = new_invariant_monitor(2);
+        let mut bathroom = new_bathroom(Gender::Female, &config::default_parameters());
+        bathroom.cabins[0] = Some(new_person(Gender::Female));
+        bathroom.cabins[1] = Some(new_person(Gender::Male));
+        let reply = event::new_event(
+            event::BATHROOM_STATE_REPLIED.to_string(),
+            bathroom.id,
+            None,
+            None,
+            Some(bathroom),
+        );
+
+        let violations = invariant_monitor.check(&reply);
+        assert!(violations
+            .iter()
+            .any(|violation| violation.contains("occupants of both genders")));
+        assert!(violations
+            .iter()
+            .any(|violation| violation.contains("allows Female people")));
+    }
+
+    #[test]
+    fn the_invariant_monitor_emits_an_event_per_violation() {
+        let channels = channel::new_channel_registry();
+        let (router_tx, router_rx) = channel::new_channel(&channels, "router", channel::UNBOUNDED);
+        let (invariant_monitor_tx, invariant_monitor_rx) =
+            channel::new_channel(&channels, "invariant_monitor", channel::UNBOUNDED);
+        let parameters = config::Parameters {
+            bathroom_size: 2,
+            ..config::default_parameters()
+        };
+        let invariant_monitor_t =
+            spawn_invariant_monitor_thread(router_tx, invariant_monitor_rx, parameters, false);
+
+        let events = mixed_gender_events();
+        let last_event_id = events.last().unwrap().id;
+        events
+            .into_iter()
+            .for_each(|msg| invariant_monitor_tx.send(msg).unwrap());
+        invariant_monitor_tx
+            .send(event::new_event(
+                event::SIMULATION_STOPPED.to_string(),
+                Uuid::new_v4(),
+                None,
+                None,
+                None,
+            ))
+            .unwrap();
+        let violations = invariant_monitor_t.join().unwrap();
+
+        let mut emitted = vec![];
+        while let Ok(msg) = router_rx.try_recv() {
+            if msg.name == event::INVARIANT_VIOLATED {
+                emitted.push(msg);
+            }
+        }
+        assert_eq!(violations.len(), 1);
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].violation.as_ref(), Some(&violations[0]));
+        assert_eq!(emitted[0].parent_id, Some(last_event_id));
+    }
+}
@@ -0,0 +1,499 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::bathroom::{self, Bathroom, Policy};
+use super::channel::Sender;
+use super::config::Parameters;
+use super::event;
+use super::locking::{self, SharedState};
+use super::person::{self, Gender};
+
+// Counting semaphore of the free cabins, on a single atomic
+pub struct Semaphore {
+    pub permits: AtomicUsize,
+}
+
+pub fn new_semaphore(permits: usize) -> Semaphore {
+    return Semaphore {
+        permits: AtomicUsize::new(permits),
+    };
+}
+
+impl Semaphore {
+    pub fn try_acquire(&self) -> bool {
+        return self
+            .permits
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok();
+    }
+
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Occupants and uses are packed along with the gender in a single word, so that they change
+// together: the gender in the highest bit, the uses of the current turn in the next 31 and the
+// occupants in the lowest 32
+const MALE_BIT: u64 = 1 << 63;
+const USES_SHIFT: u32 = 32;
+const OCCUPANTS_MASK: u64 = (1 << USES_SHIFT) - 1;
+
+fn pack(gender: Gender, uses: u64, occupants: u64) -> u64 {
+    let gender_bit = match gender {
+        Gender::Male => MALE_BIT,
+        Gender::Female => 0,
+    };
+    return gender_bit | (uses << USES_SHIFT) | occupants;
+}
+
+fn unpack(state: u64) -> (Gender, u64, u64) {
+    let gender = if state & MALE_BIT != 0 {
+        Gender::Male
+    } else {
+        Gender::Female
+    };
+    return (
+        gender,
+        (state & !MALE_BIT) >> USES_SHIFT,
+        state & OCCUPANTS_MASK,
+    );
+}
+
+// Lightswitch of the bathroom's gender: the first person in turns the light on for their gender,
+// keeping the other one out until the last person out turns it off. It also enforces the turn
+// limits of the bathroom's policy, once the other gender is waiting
+pub struct Lightswitch {
+    pub state: AtomicU64,
+    // When the current turn started, in nanoseconds since `started_at`. It is set right after the
+    // turn starts, so a concurrent entrant may still see the previous turn's, which only makes it
+    // wait longer
+    pub turn_started_at: AtomicU64,
+    pub started_at: Instant,
+    // The bathroom's policy, which the controller may update at any time
+    pub max_uses_per_turn: AtomicU32,
+    pub max_use_time_threshold: AtomicU64,
+}
+
+pub fn new_lightswitch(gender: Gender, policy: &Policy) -> Lightswitch {
+    return Lightswitch {
+        state: AtomicU64::new(pack(gender, 0, 0)),
+        turn_started_at: AtomicU64::new(0),
+        started_at: Instant::now(),
+        max_uses_per_turn: AtomicU32::new(policy.max_uses_per_turn),
+        max_use_time_threshold: AtomicU64::new(policy.max_use_time_threshold.as_nanos() as u64),
+    };
+}
+
+impl Lightswitch {
+    pub fn set_policy(&self, policy: &Policy) {
+        self.max_uses_per_turn
+            .store(policy.max_uses_per_turn, Ordering::SeqCst);
+        self.max_use_time_threshold.store(
+            policy.max_use_time_threshold.as_nanos() as u64,
+            Ordering::SeqCst,
+        );
+    }
+
+    // Whether the current turn used up the policy's limits
+    fn turn_over(&self, uses: u64) -> bool {
+        let turn_duration = self
+            .started_at
+            .elapsed()
+            .saturating_sub(Duration::from_nanos(
+                self.turn_started_at.load(Ordering::SeqCst),
+            ))
+            .mul_f64(super::TIME_SCALE);
+
+        return uses >= self.max_uses_per_turn.load(Ordering::SeqCst) as u64
+            || turn_duration.as_nanos() as u64
+                >= self.max_use_time_threshold.load(Ordering::SeqCst);
+    }
+
+    // Lets a person of `gender` in, under the same rules as the bathroom actor: an empty bathroom
+    // goes to the other gender whenever it is waiting, and people of the current gender may only
+    // join those inside until the turn is over while the other gender is waiting. A new turn
+    // starts whenever the other gender isn't waiting, as the usage statistics are reset then
+    pub fn try_enter(&self, gender: Gender, other_gender_waiting: bool) -> bool {
+        let mut state = self.state.load(Ordering::SeqCst);
+
+        loop {
+            let (current_gender, uses, occupants) = unpack(state);
+            let new_turn = if occupants == 0 {
+                if current_gender == gender && other_gender_waiting {
+                    return false;
+                }
+                current_gender != gender || !other_gender_waiting
+            } else {
+                if current_gender != gender || (other_gender_waiting && self.turn_over(uses)) {
+                    return false;
+                }
+                !other_gender_waiting
+            };
+
+            let uses = if new_turn { 1 } else { uses + 1 };
+            match self.state.compare_exchange(
+                state,
+                pack(gender, uses, occupants + 1),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => {
+                    if new_turn {
+                        self.turn_started_at.store(
+                            self.started_at.elapsed().as_nanos() as u64,
+                            Ordering::SeqCst,
+                        );
+                    }
+                    return true;
+                }
+                Err(current_state) => state = current_state,
+            }
+        }
+    }
+
+    pub fn leave(&self) {
+        self.state.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// The bathroom of the lock-free implementation: people get past the lightswitch of their gender,
+// then take a cabin from the counting semaphore, both on atomics, polling them while they can't.
+// The bathroom's state is still kept under a lock for the events to carry, as the other actors
+// observe it, but it doesn't decide who may enter. People update it after entering and before
+// leaving, so that it never shows more than the atomics let in
+pub struct LockFreeBathroom {
+    pub bathroom: Mutex<Bathroom>,
+    pub lightswitch: Lightswitch,
+    pub cabins: Semaphore,
+    // People of each gender who haven't got past the lightswitch yet
+    pub waiting_males: AtomicUsize,
+    pub waiting_females: AtomicUsize,
+    // Set once the simulation is over, so that people still waiting give up
+    pub closed: AtomicBool,
+}
+
+// A quiet bathroom does not print its state whenever it changes
+pub fn new_lock_free_bathroom(parameters: &Parameters, verbose: bool) -> Arc<LockFreeBathroom> {
+    let mut bathroom = bathroom::new_bathroom(Gender::Female, parameters);
+    bathroom.verbose = verbose;
+
+    return Arc::new(LockFreeBathroom {
+        lightswitch: new_lightswitch(bathroom.allowed_gender, &bathroom.policy),
+        cabins: new_semaphore(bathroom.cabins.len()),
+        bathroom: Mutex::new(bathroom),
+        waiting_males: AtomicUsize::new(0),
+        waiting_females: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+    });
+}
+
+impl LockFreeBathroom {
+    fn waiting(&self, gender: Gender) -> &AtomicUsize {
+        return match gender {
+            Gender::Male => &self.waiting_males,
+            Gender::Female => &self.waiting_females,
+        };
+    }
+}
+
+impl SharedState for LockFreeBathroom {
+    fn bathroom(&self) -> &Mutex<Bathroom> {
+        return &self.bathroom;
+    }
+
+    fn policy_updated(&self, policy: &Policy) {
+        self.lightswitch.set_policy(policy);
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+// Polls `condition` every RX_POLLING_WAIT until it holds, or gives up once the bathroom is closed
+fn wait_until(shared: &LockFreeBathroom, condition: impl Fn() -> bool) -> bool {
+    while !condition() {
+        if shared.closed.load(Ordering::SeqCst) {
+            return false;
+        }
+        super::wait(super::RX_POLLING_WAIT);
+    }
+
+    return true;
+}
+
+// A person who queues, gets past the lightswitch and the semaphore, uses a cabin and leaves.
+// People of a gender race for the bathroom instead of entering in their queue's order
+pub fn spawn_person_thread(
+    router_tx: Sender,
+    shared: Arc<LockFreeBathroom>,
+    gender: Gender,
+    bathroom_time: Duration,
+) -> JoinHandle<()> {
+    let mut person = person::new_person(gender);
+    super::log(format!(
+        "Person {} of gender {} spawned!",
+        person.id, person.gender
+    ));
+
+    return thread::spawn(move || {
+        let mut bathroom = shared.bathroom.lock().unwrap();
+        bathroom.enqueue(person.clone());
+        shared.waiting(gender).fetch_add(1, Ordering::SeqCst);
+        person.joined_queue_at = Some(Instant::now());
+        super::log(format!(
+            "Person {} joined the {} queue",
+            person.id, person.gender
+        ));
+        let joined_queue_event = event::new_event(
+            event::PERSON_JOINED_THE_QUEUE.to_string(),
+            bathroom.id,
+            None,
+            Some(person.clone()),
+            Some(bathroom.clone()),
+        );
+        let _ = router_tx.send(joined_queue_event.clone());
+        drop(bathroom);
+
+        let other_gender_waiting = shared.waiting(gender.opposite());
+        if !wait_until(&shared, || {
+            shared
+                .lightswitch
+                .try_enter(gender, other_gender_waiting.load(Ordering::SeqCst) > 0)
+        }) {
+            return;
+        }
+        shared.waiting(gender).fetch_sub(1, Ordering::SeqCst);
+        if !wait_until(&shared, || shared.cabins.try_acquire()) {
+            shared.lightswitch.leave();
+            return;
+        }
+
+        let mut bathroom = shared.bathroom.lock().unwrap();
+        // Those of the other gender left before turning the light off, so the cabins are empty
+        if bathroom.allowed_gender != gender {
+            locking::switch_genders_if_needed(&mut bathroom, &router_tx);
+        }
+        let position_in_queue = match gender {
+            Gender::Male => &bathroom.male_queue,
+            Gender::Female => &bathroom.female_queue,
+        }
+        .iter()
+        .position(|p| p.id == person.id);
+        let free_cabin_idx = bathroom.cabins.iter().position(|cabin| cabin.is_none());
+        let (position_in_queue, free_cabin_idx) = match (position_in_queue, free_cabin_idx) {
+            (Some(position_in_queue), Some(free_cabin_idx)) => (position_in_queue, free_cabin_idx),
+            // The bathroom's state disagrees with the atomics, which let the person in: they give up
+            // rather than corrupting it further
+            _ => {
+                bathroom.log(format!(
+                    "Person {} got a cabin, but isn't queued or no cabin is free, giving up",
+                    person.id
+                ));
+                drop(bathroom);
+                shared.cabins.release();
+                shared.lightswitch.leave();
+                return;
+            }
+        };
+        let mut entered = match gender {
+            Gender::Male => bathroom.male_queue.remove(position_in_queue),
+            Gender::Female => bathroom.female_queue.remove(position_in_queue),
+        };
+        if bathroom.use_count == 0 {
+            bathroom.first_user_entered_at = Some(Instant::now());
+        }
+        bathroom.use_count += 1;
+        entered.entered_bathroom_at = Some(Instant::now());
+        bathroom.cabins[free_cabin_idx] = Some(entered.clone());
+        bathroom.display();
+
+        super::log(format!("Person {} entered the bathroom", person.id));
+        let entered_event = event::new_event(
+            event::PERSON_ENTERED_THE_BATHROOM.to_string(),
+            bathroom.id,
+            None,
+            Some(entered.clone()),
+            Some(bathroom.clone()),
+        )
+        .with_parent(joined_queue_event.id);
+        let _ = router_tx.send(entered_event.clone());
+        drop(bathroom);
+
+        person.entered_bathroom_at = entered.entered_bathroom_at;
+        super::wait(bathroom_time);
+        let finished_event = event::new_event(
+            event::PERSON_FINISHED_USING_BATHROOM.to_string(),
+            person.id,
+            None,
+            Some(person.clone()),
+            None,
+        )
+        .caused_by(&entered_event)
+        // People never register with the router in this implementation
+        .with_actor("person");
+        let _ = router_tx.send(finished_event.clone());
+
+        let mut bathroom = shared.bathroom.lock().unwrap();
+        super::log(format!(
+            "Person {} left the {} bathroom",
+            person.id, person.gender
+        ));
+        bathroom.free_cabin(person.id);
+        person.left_bathroom_at = Some(Instant::now());
+        let _ = router_tx.send(
+            event::new_event(
+                event::PERSON_LEFT_THE_BATHROOM.to_string(),
+                bathroom.id,
+                None,
+                Some(person),
+                Some(bathroom.clone()),
+            )
+            .caused_by(&finished_event),
+        );
+        // As with the bathroom actor, an idle bathroom goes to the other gender. People queue under
+        // the lock before getting past the lightswitch, so nobody is on their way in. Any other
+        // switch is left to the first of the other gender to enter, once the lightswitch let them
+        if bathroom.male_queue.is_empty()
+            && bathroom.female_queue.is_empty()
+            && bathroom.cabins.iter().all(|cabin| cabin.is_none())
+        {
+            locking::switch_genders_if_needed(&mut bathroom, &router_tx);
+        }
+        drop(bathroom);
+
+        shared.cabins.release();
+        shared.lightswitch.leave();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::bathroom::SwitchRule;
+    use crate::simulation::{channel, config};
+
+    fn policy(max_uses_per_turn: u32) -> Policy {
+        return Policy {
+            max_uses_per_turn,
+            // Long enough for the turn's duration never to end it here
+            max_use_time_threshold: Duration::from_secs(24 * 60 * 60),
+        };
+    }
+
+    #[test]
+    fn pack_and_unpack_round_trip() {
+        for (gender, uses, occupants) in [
+            (Gender::Male, 0, 0),
+            (Gender::Female, 0, 0),
+            (Gender::Male, 3, 2),
+            (Gender::Female, 3, 2),
+            (Gender::Male, (1 << 31) - 1, OCCUPANTS_MASK),
+        ] {
+            assert_eq!(
+                unpack(pack(gender, uses, occupants)),
+                (gender, uses, occupants)
+            );
+        }
+        assert_eq!(pack(Gender::Male, 0, 0), MALE_BIT);
+        assert_eq!(pack(Gender::Female, 0, 0), 0);
+    }
+
+    #[test]
+    fn an_empty_bathroom_goes_to_the_other_gender_when_it_waits() {
+        let lightswitch = new_lightswitch(Gender::Female, &policy(10));
+
+        assert!(!lightswitch.try_enter(Gender::Female, true));
+        assert!(lightswitch.try_enter(Gender::Male, false));
+        assert_eq!(
+            unpack(lightswitch.state.load(Ordering::SeqCst)),
+            (Gender::Male, 1, 1)
+        );
+    }
+
+    #[test]
+    fn the_other_gender_is_kept_out_while_occupied() {
+        let lightswitch = new_lightswitch(Gender::Female, &policy(10));
+        assert!(lightswitch.try_enter(Gender::Female, false));
+
+        assert!(!lightswitch.try_enter(Gender::Male, false));
+        assert!(!lightswitch.try_enter(Gender::Male, true));
+
+        // The last person out turns the light off
+        lightswitch.leave();
+        assert!(lightswitch.try_enter(Gender::Male, true));
+    }
+
+    #[test]
+    fn the_turn_ends_at_max_uses_per_turn() {
+        let lightswitch = new_lightswitch(Gender::Female, &policy(3));
+        // The other gender arrives once the turn started
+        assert!(lightswitch.try_enter(Gender::Female, false));
+        assert!(lightswitch.try_enter(Gender::Female, true));
+        assert!(lightswitch.try_enter(Gender::Female, true));
+
+        assert!(!lightswitch.try_enter(Gender::Female, true));
+        // The limits only apply while the other gender waits
+        assert!(lightswitch.try_enter(Gender::Female, false));
+        assert_eq!(
+            unpack(lightswitch.state.load(Ordering::SeqCst)),
+            (Gender::Female, 1, 4)
+        );
+    }
+
+    #[test]
+    fn policy_updates_apply_to_the_current_turn() {
+        let lightswitch = new_lightswitch(Gender::Female, &policy(10));
+        assert!(lightswitch.try_enter(Gender::Female, false));
+
+        lightswitch.set_policy(&policy(1));
+        assert!(!lightswitch.try_enter(Gender::Female, true));
+    }
+
+    #[test]
+    fn try_acquire_fails_without_permits() {
+        let semaphore = new_semaphore(2);
+        assert!(semaphore.try_acquire());
+        assert!(semaphore.try_acquire());
+
+        assert!(!semaphore.try_acquire());
+        assert_eq!(semaphore.permits.load(Ordering::SeqCst), 0);
+
+        semaphore.release();
+        assert!(semaphore.try_acquire());
+    }
+
+    #[test]
+    fn the_bathroom_goes_to_the_other_gender_once_idle() {
+        let channels = channel::new_channel_registry();
+        let (router_tx, router_rx) = channel::new_channel(&channels, "router", channel::UNBOUNDED);
+        let shared = new_lock_free_bathroom(&config::default_parameters(), false);
+
+        spawn_person_thread(router_tx, shared.clone(), Gender::Male, Duration::ZERO)
+            .join()
+            .unwrap();
+
+        let mut switch_rules = vec![];
+        while let Ok(msg) = router_rx.try_recv() {
+            if msg.name == event::BATHROOM_SWITCHED_GENDERS {
+                switch_rules.push(msg.switch_rule.unwrap());
+            }
+        }
+        assert_eq!(
+            switch_rules,
+            vec![
+                SwitchRule::CurrentGenderQueueEmpty,
+                SwitchRule::BathroomIdle
+            ]
+        );
+        assert_eq!(
+            shared.bathroom.lock().unwrap().allowed_gender,
+            Gender::Female
+        );
+    }
+}
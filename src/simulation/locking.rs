@@ -0,0 +1,268 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::TryRecvError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::bathroom::{self, Bathroom, Policy};
+use super::channel::{self, ChannelRegistry, Sender};
+use super::config::Parameters;
+use super::event;
+use super::person::{self, Gender};
+use super::watchdog;
+
+// The bathroom of the lock-based implementation, whose state people lock to join a queue, enter and
+// leave, instead of asking a bathroom actor to. The bathroom's events are still sent through the
+// router, so the metrics collector, invariant monitor, watchdog and tracer observe both
+// implementations alike
+pub struct SharedBathroom {
+    pub bathroom: Mutex<Bathroom>,
+    // Notified whenever the bathroom changed in a way that may let someone in
+    pub changed: Condvar,
+    // Set once the simulation is over, so that people still waiting give up
+    pub closed: AtomicBool,
+}
+
+// A quiet bathroom does not print its state whenever it changes
+pub fn new_shared_bathroom(parameters: &Parameters, verbose: bool) -> Arc<SharedBathroom> {
+    let mut bathroom = bathroom::new_bathroom(Gender::Female, parameters);
+    bathroom.verbose = verbose;
+
+    return Arc::new(SharedBathroom {
+        bathroom: Mutex::new(bathroom),
+        changed: Condvar::new(),
+        closed: AtomicBool::new(false),
+    });
+}
+
+// A bathroom whose state people update themselves instead of asking a bathroom actor to, served
+// by the bathroom thread to the other actors
+pub trait SharedState: Send + Sync + 'static {
+    // The bathroom's state, as its events carry it
+    fn bathroom(&self) -> &Mutex<Bathroom>;
+    // Called with the bathroom's state locked, once the controller updated its policy
+    fn policy_updated(&self, policy: &Policy);
+    // Called with the bathroom's state locked, once the simulation is over, so that people still
+    // waiting give up
+    fn close(&self);
+}
+
+impl SharedState for SharedBathroom {
+    fn bathroom(&self) -> &Mutex<Bathroom> {
+        return &self.bathroom;
+    }
+
+    fn policy_updated(&self, _: &Policy) {
+        // Looser limits may let people in
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.changed.notify_all();
+    }
+}
+
+// Lets the other gender in if the bathroom's rules call for it, as the bathroom actor does. Returns
+// whether it switched
+pub fn switch_genders_if_needed(bathroom: &mut Bathroom, router_tx: &Sender) -> bool {
+    let previous_bathroom_state = bathroom.clone();
+
+    return match bathroom.should_switch_genders() {
+        Some(switch_rule) => {
+            bathroom.switch_genders();
+            let mut switched_genders_event = event::new_event(
+                event::BATHROOM_SWITCHED_GENDERS.to_string(),
+                bathroom.id,
+                None,
+                None,
+                Some(previous_bathroom_state),
+            );
+            switched_genders_event.switch_rule = Some(switch_rule);
+            let _ = router_tx.send(switched_genders_event);
+            true
+        }
+        None => false,
+    };
+}
+
+// A person who queues, waits for their turn, uses a cabin and leaves, all while holding the
+// bathroom's lock but for the time spent in the cabin. Nobody addresses events to people here, so
+// they aren't registered with the router
+pub fn spawn_person_thread(
+    router_tx: Sender,
+    shared: Arc<SharedBathroom>,
+    gender: Gender,
+    bathroom_time: Duration,
+) -> JoinHandle<()> {
+    let mut person = person::new_person(gender);
+    super::log(format!(
+        "Person {} of gender {} spawned!",
+        person.id, person.gender
+    ));
+
+    return thread::spawn(move || {
+        let mut bathroom = shared.bathroom.lock().unwrap();
+        bathroom.enqueue(person.clone());
+        person.joined_queue_at = Some(Instant::now());
+        super::log(format!(
+            "Person {} joined the {} queue",
+            person.id, person.gender
+        ));
+        let joined_queue_event = event::new_event(
+            event::PERSON_JOINED_THE_QUEUE.to_string(),
+            bathroom.id,
+            None,
+            Some(person.clone()),
+            Some(bathroom.clone()),
+        );
+        let _ = router_tx.send(joined_queue_event.clone());
+        // Someone of the other gender arriving may call for a switch
+        shared.changed.notify_all();
+
+        // Only the head of a queue may enter, as with the bathroom actor
+        let entered = loop {
+            if switch_genders_if_needed(&mut bathroom, &router_tx) {
+                shared.changed.notify_all();
+            }
+
+            let first_in_queue = match gender {
+                Gender::Male => bathroom.male_queue.first(),
+                Gender::Female => bathroom.female_queue.first(),
+            };
+            if first_in_queue.is_some_and(|first| first.id == person.id) {
+                if let Some(entered) = bathroom.allocate_cabin(gender) {
+                    break entered;
+                }
+            }
+
+            if shared.closed.load(Ordering::SeqCst) {
+                return;
+            }
+            bathroom = shared.changed.wait(bathroom).unwrap();
+        };
+
+        super::log(format!("Person {} entered the bathroom", person.id));
+        let entered_event = event::new_event(
+            event::PERSON_ENTERED_THE_BATHROOM.to_string(),
+            bathroom.id,
+            None,
+            Some(entered.clone()),
+            Some(bathroom.clone()),
+        )
+        .with_parent(joined_queue_event.id);
+        let _ = router_tx.send(entered_event.clone());
+        // The next in line may take another free cabin
+        shared.changed.notify_all();
+        drop(bathroom);
+
+        person.entered_bathroom_at = entered.entered_bathroom_at;
+        super::wait(bathroom_time);
+        let finished_event = event::new_event(
+            event::PERSON_FINISHED_USING_BATHROOM.to_string(),
+            person.id,
+            None,
+            Some(person.clone()),
+            None,
+        )
+        .caused_by(&entered_event)
+        // People never register with the router in this implementation
+        .with_actor("person");
+        let _ = router_tx.send(finished_event.clone());
+
+        let mut bathroom = shared.bathroom.lock().unwrap();
+        super::log(format!(
+            "Person {} left the {} bathroom",
+            person.id, person.gender
+        ));
+        bathroom.free_cabin(person.id);
+        person.left_bathroom_at = Some(Instant::now());
+        let _ = router_tx.send(
+            event::new_event(
+                event::PERSON_LEFT_THE_BATHROOM.to_string(),
+                bathroom.id,
+                None,
+                Some(person),
+                Some(bathroom.clone()),
+            )
+            .caused_by(&finished_event),
+        );
+        switch_genders_if_needed(&mut bathroom, &router_tx);
+        shared.changed.notify_all();
+    });
+}
+
+// Registers the shared bathroom with the router, and serves the events addressed to it by others
+// than people (state requests from the monitor, policy updates from the controller) by locking it.
+// Its heartbeats take the lock too, so the watchdog notices a deadlocked bathroom. Once the router
+// is gone, people still waiting are sent home. As with the bathroom actor, the bathroom is
+// registered with the router before returning
+pub fn spawn_bathroom_thread<S: SharedState>(
+    router_tx: Sender,
+    channels: ChannelRegistry,
+    shared: Arc<S>,
+) {
+    let bathroom_id = shared.bathroom().lock().unwrap().id;
+    let (tx_bathroom, rx_bathroom) =
+        channel::new_channel(&channels, "bathroom", super::BATHROOM_CHANNEL);
+
+    router_tx
+        .send(
+            event::new_creation_event(
+                event::NEW_BATHROOM.to_string(),
+                bathroom_id,
+                None,
+                tx_bathroom,
+                None,
+            )
+            .with_actor("bathroom"),
+        )
+        .unwrap();
+
+    let _ = thread::spawn(move || {
+        super::log("Bathroom spawned!".to_string());
+        let mut heartbeat = watchdog::new_heartbeat("bathroom", bathroom_id);
+
+        loop {
+            if heartbeat.is_due() {
+                drop(shared.bathroom().lock().unwrap());
+                heartbeat.beat(&router_tx);
+            }
+
+            match &rx_bathroom.try_recv() {
+                Ok(msg) => match msg.name.as_str() {
+                    event::BATHROOM_STATE_REQUESTED => {
+                        let bathroom = shared.bathroom().lock().unwrap();
+                        let _ = router_tx.send(event::new_reply_event(
+                            event::BATHROOM_STATE_REPLIED.to_string(),
+                            bathroom.id,
+                            msg,
+                            None,
+                            Some(bathroom.clone()),
+                        ));
+                    }
+                    event::BATHROOM_POLICY_UPDATED => {
+                        if let Some(policy) = msg.policy {
+                            let mut bathroom = shared.bathroom().lock().unwrap();
+                            bathroom.log(format!(
+                                "Policy updated: max use time threshold {:?}, max uses per turn {}",
+                                policy.max_use_time_threshold, policy.max_uses_per_turn
+                            ));
+                            bathroom.policy = policy;
+                            shared.policy_updated(&policy);
+                        }
+                    }
+                    // People handle their own arrivals and departures
+                    &_ => (),
+                },
+                // The router is gone, the simulation is over
+                Err(TryRecvError::Disconnected) => {
+                    let _bathroom = shared.bathroom().lock().unwrap();
+                    shared.close();
+                    break;
+                }
+                Err(TryRecvError::Empty) => super::wait(super::RX_POLLING_WAIT),
+            };
+        }
+    });
+}
@@ -1,34 +1,317 @@
-use serde::Serialize;
-use std::ops::Div;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use super::bathroom::Bathroom;
+use super::channel::ChannelReport;
+use super::consistency::{self, Consistency};
+use super::histogram::{self, Histogram};
+use super::person::{Gender, Person};
+use super::router::{self, RouterStatistics};
+use super::timeseries::{self, TimeSeries};
+use super::warmup::{self, WarmUpPeriod};
 
 #[derive(Debug, Serialize)]
 pub struct MetricsCollector {
     pub male_queue_size: Statistic,
     pub female_queue_size: Statistic,
     pub gender_switches: u64,
+    // Every switch, including those of an idle bathroom, by the rule that triggered it
+    pub switches_by_rule: BTreeMap<String, u64>,
+    // Policies set by the controller
+    pub policy_updates: u64,
     pub time_bathroom_was_male: Statistic,
     pub time_bathroom_was_female: Statistic,
     pub male_personal_total_time_spent: Statistic,
     pub female_personal_total_time_spent: Statistic,
     pub male_personal_total_wait_time: Statistic,
     pub female_personal_total_wait_time: Statistic,
+    pub fairness: Fairness,
+    // Time-weighted metrics, tracked from every bathroom state change
+    pub male_queue_length: TimeWeightedStatistic,
+    pub female_queue_length: TimeWeightedStatistic,
+    pub occupied_cabins: TimeWeightedStatistic,
+    pub idle: TimeWeightedStatistic,
+    pub arrivals: u64,
+    pub departures: u64,
+    // People queued or in cabins when the warm-up ended, 0 without one
+    pub present_at_start: u64,
+    // Everything above is counted from the end of the warm-up
+    pub warm_up: WarmUpPeriod,
+    // In simulated seconds, of the whole run and since the end of the warm-up
+    pub simulated_duration: f64,
+    pub measured_duration: f64,
+    pub busy_cabin_seconds: f64,
+    pub idle_time: f64,
+    // Busy cabin-seconds over the capacity's cabin-seconds
+    pub cabin_utilization: f64,
+    // Departures per simulated hour
+    pub throughput_per_hour: f64,
+    #[serde(skip)]
+    pub started_at: Instant,
+    // End of the warm-up, or start of the simulation without one
+    #[serde(skip)]
+    pub measured_from: Instant,
+    #[serde(skip)]
+    pub cabins: usize,
+    #[serde(skip)]
+    pub last_bathroom_snapshot: Option<Bathroom>,
+    // Exported as CSV alongside the report
+    #[serde(skip)]
+    pub time_series: TimeSeries,
+    pub channels: Vec<ChannelReport>,
+    pub router: RouterStatistics,
+    pub consistency: Consistency,
+    // Safety properties the invariant monitor found violated over the whole run (see
+    // `invariants.rs`), filled in once the simulation stopped
+    pub invariant_violations: Vec<String>,
+    // Liveness checks the watchdog found failing over the whole run (see `watchdog.rs`), filled in
+    // once the simulation stopped
+    pub liveness_failures: Vec<String>,
 }
 
 pub fn new_metrics_collector() -> MetricsCollector {
+    let now = Instant::now();
+
     return MetricsCollector {
         male_queue_size: new_statistic(),
         female_queue_size: new_statistic(),
         gender_switches: 0,
+        switches_by_rule: BTreeMap::new(),
+        policy_updates: 0,
         time_bathroom_was_male: new_statistic(),
         time_bathroom_was_female: new_statistic(),
         male_personal_total_time_spent: new_statistic(),
         female_personal_total_time_spent: new_statistic(),
         male_personal_total_wait_time: new_statistic(),
         female_personal_total_wait_time: new_statistic(),
+        fairness: new_fairness(),
+        male_queue_length: new_time_weighted_statistic(0.0),
+        female_queue_length: new_time_weighted_statistic(0.0),
+        occupied_cabins: new_time_weighted_statistic(0.0),
+        // The bathroom starts empty
+        idle: new_time_weighted_statistic(1.0),
+        arrivals: 0,
+        departures: 0,
+        present_at_start: 0,
+        warm_up: warmup::new_warm_up_period(super::WARM_UP),
+        simulated_duration: 0.0,
+        measured_duration: 0.0,
+        busy_cabin_seconds: 0.0,
+        idle_time: 0.0,
+        cabin_utilization: 0.0,
+        throughput_per_hour: 0.0,
+        started_at: now,
+        measured_from: now,
+        cabins: 0,
+        last_bathroom_snapshot: None,
+        time_series: timeseries::new_time_series(super::TIME_SERIES_BUCKET.as_secs_f64()),
+        channels: vec![],
+        router: router::new_router_statistics(),
+        consistency: consistency::new_consistency(),
+        invariant_violations: vec![],
+        liveness_failures: vec![],
     };
 }
 
 impl MetricsCollector {
+    // `allowed_gender` may differ from the snapshot's, which is taken before switching genders
+    pub fn record_bathroom_state(
+        &mut self,
+        bathroom: &Bathroom,
+        allowed_gender: Gender,
+        at: Instant,
+    ) {
+        let occupied_cabins = bathroom
+            .cabins
+            .iter()
+            .filter(|cabin| cabin.is_some())
+            .count();
+
+        self.cabins = bathroom.cabins.len();
+        self.male_queue_length
+            .update(bathroom.male_queue.len() as f64, at, self.measured_from);
+        self.female_queue_length
+            .update(bathroom.female_queue.len() as f64, at, self.measured_from);
+        self.occupied_cabins
+            .update(occupied_cabins as f64, at, self.measured_from);
+        self.idle.update(
+            if occupied_cabins == 0 { 1.0 } else { 0.0 },
+            at,
+            self.measured_from,
+        );
+        self.warm_up.record_queue_length(
+            simulated_seconds(self.started_at, at),
+            (bathroom.male_queue.len() + bathroom.female_queue.len()) as f64,
+        );
+        self.time_series.record_state(
+            simulated_seconds(self.started_at, at),
+            bathroom.male_queue.len(),
+            bathroom.female_queue.len(),
+            occupied_cabins,
+            allowed_gender,
+        );
+        self.last_bathroom_snapshot = Some(bathroom.clone());
+    }
+
+    // Ends the warm-up once it is over, discarding everything counted until then. The bathroom's
+    // state carries over, as do the time series and starvation of people still waiting, which
+    // cover the whole run
+    pub fn update_warm_up(&mut self, at: Instant) {
+        let elapsed = simulated_seconds(self.started_at, at);
+        if self.warm_up.is_over() || !self.warm_up.should_end(elapsed, self.departures) {
+            return;
+        }
+
+        self.warm_up.end(elapsed, self.arrivals, self.departures);
+        let last_bathroom_snapshot = self.last_bathroom_snapshot.take();
+        *self = MetricsCollector {
+            started_at: self.started_at,
+            measured_from: at,
+            cabins: self.cabins,
+            time_series: std::mem::replace(
+                &mut self.time_series,
+                timeseries::new_time_series(super::TIME_SERIES_BUCKET.as_secs_f64()),
+            ),
+            warm_up: std::mem::replace(&mut self.warm_up, warmup::new_warm_up_period(None)),
+            ..new_metrics_collector()
+        };
+
+        if let Some(bathroom) = &last_bathroom_snapshot {
+            let occupied_cabins = bathroom
+                .cabins
+                .iter()
+                .filter(|cabin| cabin.is_some())
+                .count();
+
+            self.present_at_start =
+                (bathroom.male_queue.len() + bathroom.female_queue.len() + occupied_cabins) as u64;
+            self.male_queue_length = new_time_weighted_statistic(bathroom.male_queue.len() as f64);
+            self.female_queue_length =
+                new_time_weighted_statistic(bathroom.female_queue.len() as f64);
+            self.occupied_cabins = new_time_weighted_statistic(occupied_cabins as f64);
+            self.idle = new_time_weighted_statistic(if occupied_cabins == 0 { 1.0 } else { 0.0 });
+        }
+        self.last_bathroom_snapshot = last_bathroom_snapshot;
+    }
+
+    pub fn record_arrival(&mut self, at: Instant) {
+        self.arrivals += 1;
+        self.time_series
+            .add_arrival(simulated_seconds(self.started_at, at));
+    }
+
+    pub fn record_departure(&mut self, at: Instant) {
+        self.departures += 1;
+        self.time_series
+            .add_departure(simulated_seconds(self.started_at, at));
+    }
+
+    pub fn record_wait(&mut self, gender: Gender, wait: f64) {
+        let starved = wait > super::STARVATION_THRESHOLD.as_secs_f64();
+
+        match gender {
+            Gender::Male => {
+                self.male_personal_total_wait_time.add_measure(wait);
+                self.fairness.male_starved += starved as u64;
+            }
+            Gender::Female => {
+                self.female_personal_total_wait_time.add_measure(wait);
+                self.fairness.female_starved += starved as u64;
+            }
+        }
+    }
+
+    // Closes the time-weighted metrics at the end of the simulation
+    pub fn finish(&mut self, at: Instant) {
+        self.simulated_duration = simulated_seconds(self.started_at, at);
+        self.measured_duration = simulated_seconds(self.measured_from, at);
+
+        self.male_queue_length.finish(at, self.measured_from);
+        self.female_queue_length.finish(at, self.measured_from);
+        self.occupied_cabins.finish(at, self.measured_from);
+        self.idle.finish(at, self.measured_from);
+        self.time_series.finish(self.simulated_duration);
+
+        self.update_fairness(at);
+
+        self.busy_cabin_seconds = self.occupied_cabins.integral;
+        self.idle_time = self.idle.integral;
+
+        if self.measured_duration > 0.0 {
+            if self.cabins > 0 {
+                self.cabin_utilization =
+                    self.busy_cabin_seconds / (self.cabins as f64 * self.measured_duration);
+            }
+            self.throughput_per_hour = self.departures as f64 / (self.measured_duration / 3600.0);
+        }
+
+        self.consistency = consistency::check_consistency(self, at);
+    }
+
+    fn update_fairness(&mut self, at: Instant) {
+        let male = &self.male_personal_total_wait_time;
+        let female = &self.female_personal_total_wait_time;
+        let fairness = &mut self.fairness;
+
+        fairness.male_max_wait = male.max;
+        fairness.female_max_wait = female.max;
+        fairness.female_to_male_mean_wait_ratio = if male.avg > 0.0 {
+            female.avg / male.avg
+        } else {
+            0.0
+        };
+
+        let sum_of_squares = male.avg.powi(2) + female.avg.powi(2);
+        fairness.jains_fairness_index = if sum_of_squares > 0.0 {
+            (male.avg + female.avg).powi(2) / (2.0 * sum_of_squares)
+        } else {
+            1.0
+        };
+
+        if let Some(bathroom) = &self.last_bathroom_snapshot {
+            let starving = |queue: &Vec<Person>| {
+                queue
+                    .iter()
+                    .filter(|person| match person.joined_queue_at {
+                        Some(joined_queue_at) => {
+                            simulated_seconds(joined_queue_at, at) > fairness.starvation_threshold
+                        }
+                        None => false,
+                    })
+                    .count() as u64
+            };
+            fairness.male_starving_at_end = starving(&bathroom.male_queue);
+            fairness.female_starving_at_end = starving(&bathroom.female_queue);
+        }
+    }
+
+    // Every statistic of the report, by name
+    pub fn statistics(&self) -> Vec<(&'static str, &Statistic)> {
+        return vec![
+            ("male_queue_size", &self.male_queue_size),
+            ("female_queue_size", &self.female_queue_size),
+            ("time_bathroom_was_male", &self.time_bathroom_was_male),
+            ("time_bathroom_was_female", &self.time_bathroom_was_female),
+            (
+                "male_personal_total_time_spent",
+                &self.male_personal_total_time_spent,
+            ),
+            (
+                "female_personal_total_time_spent",
+                &self.female_personal_total_time_spent,
+            ),
+            (
+                "male_personal_total_wait_time",
+                &self.male_personal_total_wait_time,
+            ),
+            (
+                "female_personal_total_wait_time",
+                &self.female_personal_total_wait_time,
+            ),
+        ];
+    }
+
     pub fn update_statistics(&mut self) {
         self.male_queue_size.update_statistics();
         self.female_queue_size.update_statistics();
@@ -41,145 +324,471 @@ impl MetricsCollector {
     }
 }
 
+// How evenly waiting is shared between genders, wait times are in simulated seconds
+#[derive(Debug, Serialize)]
+pub struct Fairness {
+    pub male_max_wait: f64,
+    pub female_max_wait: f64,
+    // Mean female wait over mean male wait, 0 if no man waited
+    pub female_to_male_mean_wait_ratio: f64,
+    // Jain's fairness index of both genders' mean waits, from 0.5 (one gender does all the
+    // waiting) to 1 (both wait as much)
+    pub jains_fairness_index: f64,
+    pub starvation_threshold: f64,
+    // People who entered the bathroom after waiting longer than the starvation threshold
+    pub male_starved: u64,
+    pub female_starved: u64,
+    // People still waiting when the simulation stopped, for longer than the starvation threshold
+    pub male_starving_at_end: u64,
+    pub female_starving_at_end: u64,
+}
+
+fn new_fairness() -> Fairness {
+    return Fairness {
+        male_max_wait: 0.0,
+        female_max_wait: 0.0,
+        female_to_male_mean_wait_ratio: 0.0,
+        jains_fairness_index: 1.0,
+        starvation_threshold: super::STARVATION_THRESHOLD.as_secs_f64(),
+        male_starved: 0,
+        female_starved: 0,
+        male_starving_at_end: 0,
+        female_starving_at_end: 0,
+    };
+}
+
+pub fn simulated_seconds(from: Instant, to: Instant) -> f64 {
+    return to.saturating_duration_since(from).as_secs_f64() * super::TIME_SCALE;
+}
+
+// A value that holds between changes (e.g. a queue length), averaged over simulated time
+#[derive(Debug, Serialize)]
+pub struct TimeWeightedStatistic {
+    pub avg: f64,
+    pub max: f64,
+    // Sum of value * simulated seconds it held for, e.g. busy cabin-seconds for occupied cabins
+    pub integral: f64,
+    #[serde(skip)]
+    value: f64,
+    #[serde(skip)]
+    changed_at: Option<Instant>,
+}
+
+// `initial_value` is held from the start of the simulation until the first update
+fn new_time_weighted_statistic(initial_value: f64) -> TimeWeightedStatistic {
+    return TimeWeightedStatistic {
+        avg: 0.0,
+        max: initial_value,
+        integral: 0.0,
+        value: initial_value,
+        changed_at: None,
+    };
+}
+
+impl TimeWeightedStatistic {
+    pub fn update(&mut self, value: f64, at: Instant, started_at: Instant) {
+        let changed_at = self.changed_at.unwrap_or(started_at);
+
+        self.integral += self.value * simulated_seconds(changed_at, at);
+        self.value = value;
+        self.max = self.max.max(value);
+        self.changed_at = Some(at.max(changed_at));
+    }
+
+    pub fn finish(&mut self, at: Instant, started_at: Instant) {
+        self.update(self.value, at, started_at);
+
+        let duration = simulated_seconds(started_at, at);
+        if duration > 0.0 {
+            self.avg = self.integral / duration;
+        }
+    }
+}
+
+#[allow(dead_code)] // Variants are picked through `STATISTIC_MODE` in `simulation.rs`
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StatisticMode {
+    // Every measure is kept and percentiles are exact
+    Exact,
+    // Measures are only counted in a histogram, so memory stays bounded however long the
+    // simulation runs, and percentiles are approximated within the histogram's precision
+    Streaming,
+}
+
+// How percentiles falling between two measures are computed, given the fractional rank
+// h = (count - 1) * p / 100 of the p-th percentile among the measures in ascending order
+#[allow(dead_code)] // Variants are picked through `PERCENTILE_INTERPOLATION` in `simulation.rs`
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+    // Linear interpolation between the two closest measures
+    Linear,
+    // The closest measure below h
+    Lower,
+    // The closest measure above h
+    Higher,
+    // The measure closest to h
+    Nearest,
+    // The average of the two closest measures
+    Midpoint,
+}
+
+// Measures are kept in f64 so sub-second times are not truncated, times are in simulated seconds
 #[derive(Debug, Serialize)]
 pub struct Statistic {
-    pub measures: Vec<u64>,
-    pub avg: u64,
-    pub min: u64,
-    pub max: u64,
-    pub median: u64,
-    pub percentile_10: u64,
-    pub percentile_25: u64,
-    pub percentile_75: u64,
-    pub percentile_90: u64,
-    pub ordered_measures: Vec<u64>,
-}
-
-fn new_statistic() -> Statistic {
+    pub mode: StatisticMode,
+    #[serde(skip_serializing_if = "omit_raw_measures")]
+    pub measures: Vec<f64>,
+    pub count: u64,
+    pub sum: f64,
+    pub avg: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+    pub median: f64,
+    // Keyed by percentile, e.g. "p99.9"
+    pub percentiles: BTreeMap<String, f64>,
+    #[serde(skip_serializing_if = "omit_raw_measures")]
+    pub ordered_measures: Vec<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<Histogram>,
+    // Sum of squared differences from the mean, kept up to date with Welford's algorithm
+    #[serde(skip)]
+    squared_deviations: f64,
+}
+
+fn omit_raw_measures(_: &[f64]) -> bool {
+    return !super::REPORT_RAW_MEASURES;
+}
+
+pub fn new_statistic() -> Statistic {
     return Statistic {
+        mode: super::STATISTIC_MODE,
         measures: vec![],
-        avg: 0,
-        min: 0,
-        max: 0,
-        median: 0,
-        percentile_10: 0,
-        percentile_25: 0,
-        percentile_75: 0,
-        percentile_90: 0,
+        count: 0,
+        sum: 0.0,
+        avg: 0.0,
+        std_dev: 0.0,
+        min: 0.0,
+        max: 0.0,
+        median: 0.0,
+        percentiles: BTreeMap::new(),
         ordered_measures: vec![],
+        histogram: match super::STATISTIC_MODE {
+            StatisticMode::Exact => None,
+            StatisticMode::Streaming => Some(histogram::new_histogram()),
+        },
+        squared_deviations: 0.0,
     };
 }
 
+pub fn percentile_name(p: f64) -> String {
+    return format!("p{}", p);
+}
+
 impl Statistic {
-    pub fn add_measure(&mut self, measure: u64) {
-        self.measures.push(measure);
-
-        self.ordered_measures.insert(
-            self.ordered_measures
-                .binary_search(&measure)
-                .unwrap_or_else(|e| e),
-            measure,
-        );
-    }
+    pub fn add_measure(&mut self, measure: f64) {
+        if self.count == 0 || measure < self.min {
+            self.min = measure;
+        }
+        if self.count == 0 || measure > self.max {
+            self.max = measure;
+        }
+        self.count += 1;
+        self.sum += measure;
 
-    pub fn update_avg(&mut self) -> u64 {
-        let measures_len = self.measures.len() as u64;
+        let previous_avg = self.avg;
+        self.avg += (measure - previous_avg) / self.count as f64;
+        self.squared_deviations += (measure - previous_avg) * (measure - self.avg);
 
-        if measures_len == 0 {
-            self.avg = 0;
-        } else {
-            self.avg = self.measures.iter().sum::<u64>().div(measures_len as u64);
+        match self.histogram.as_mut() {
+            Some(histogram) => histogram.record(measure),
+            None => self.measures.push(measure),
         }
+    }
 
-        return self.avg;
+    // Adds the measures of `other`, e.g. to pool those of both genders. Derived statistics must be
+    // updated afterwards
+    pub fn merge(&mut self, other: &Statistic) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        if self.count == 0 || other.max > self.max {
+            self.max = other.max;
+        }
+
+        let count = self.count + other.count;
+        let delta = other.avg - self.avg;
+        self.squared_deviations += other.squared_deviations
+            + delta * delta * (self.count * other.count) as f64 / count as f64;
+        self.avg += delta * other.count as f64 / count as f64;
+        self.count = count;
+        self.sum += other.sum;
+
+        match (self.histogram.as_mut(), &other.histogram) {
+            (Some(histogram), Some(other_histogram)) => histogram.merge(other_histogram),
+            _ => self.measures.extend(&other.measures),
+        }
     }
 
-    pub fn update_min(&mut self) -> u64 {
-        self.min = match self.ordered_measures.first() {
-            Some(i) => *i,
-            None => 0,
+    // Value of the measure at `rank` (0-based) among all measures in ascending order
+    fn measure_at_rank(&self, rank: usize) -> Option<f64> {
+        return match &self.histogram {
+            // Bucket midpoints may lie slightly beyond the exact extremes
+            Some(histogram) => histogram
+                .value_at_rank(rank as u64)
+                .map(|value| value.clamp(self.min, self.max)),
+            None => self.ordered_measures.get(rank).copied(),
         };
+    }
+
+    // The p-th percentile (0 <= p <= 100) of the measures, None if there are none. In exact mode,
+    // `update_ordered_measures` must have been called since the last measure was added
+    pub fn percentile(&self, p: f64, interpolation: Interpolation) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = (self.count - 1) as f64 * p.clamp(0.0, 100.0) / 100.0;
+        let lower = self.measure_at_rank(rank.floor() as usize)?;
+        let higher = self.measure_at_rank(rank.ceil() as usize)?;
 
-        return self.min;
+        return Some(match interpolation {
+            Interpolation::Linear => lower + (higher - lower) * rank.fract(),
+            Interpolation::Lower => lower,
+            Interpolation::Higher => higher,
+            Interpolation::Nearest => {
+                if rank.fract() < 0.5 {
+                    lower
+                } else {
+                    higher
+                }
+            }
+            Interpolation::Midpoint => (lower + higher) / 2.0,
+        });
     }
 
-    pub fn update_max(&mut self) -> u64 {
-        self.max = match self.ordered_measures.last() {
-            Some(i) => *i,
-            None => 0,
-        };
+    pub fn update_ordered_measures(&mut self) {
+        if self.histogram.is_none() {
+            self.ordered_measures = self.measures.clone();
+            self.ordered_measures.sort_unstable_by(f64::total_cmp);
+        }
+    }
+
+    // Sample standard deviation
+    pub fn update_std_dev(&mut self) -> f64 {
+        if self.count < 2 {
+            self.std_dev = 0.0;
+        } else {
+            self.std_dev = (self.squared_deviations / (self.count - 1) as f64).sqrt();
+        }
 
-        return self.max;
+        return self.std_dev;
     }
 
-    pub fn update_median(&mut self) -> u64 {
-        self.median = match self
-            .ordered_measures
-            .get(self.ordered_measures.len() / 2 as usize)
-        {
-            Some(i) => *i,
-            None => 0,
-        };
+    pub fn update_median(&mut self) -> f64 {
+        self.median = self
+            .percentile(50.0, super::PERCENTILE_INTERPOLATION)
+            .unwrap_or(0.0);
 
         return self.median;
     }
 
-    pub fn update_percentile_10(&mut self) -> u64 {
-        self.percentile_10 = match self
-            .ordered_measures
-            .get(self.ordered_measures.len() / 10 as usize)
-        {
-            Some(i) => *i,
-            None => 0,
-        };
+    pub fn update_percentiles(&mut self) {
+        self.percentiles = super::REPORTED_PERCENTILES
+            .iter()
+            .map(|p| {
+                (
+                    percentile_name(*p),
+                    self.percentile(*p, super::PERCENTILE_INTERPOLATION)
+                        .unwrap_or(0.0),
+                )
+            })
+            .collect();
+    }
 
-        return self.percentile_10;
+    pub fn update_statistics(&mut self) {
+        self.update_ordered_measures();
+        self.update_std_dev();
+        self.update_median();
+        self.update_percentiles();
     }
+}
 
-    pub fn update_percentile_25(&mut self) -> u64 {
-        self.percentile_25 = match self
-            .ordered_measures
-            .get(self.ordered_measures.len() / 4 as usize)
-        {
-            Some(i) => *i,
-            None => 0,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{bathroom, config, person};
+    use std::time::Duration;
+
+    // Statistics kept exactly, whatever STATISTIC_MODE is
+    fn exact_statistic(measures: &[f64]) -> Statistic {
+        let mut statistic = Statistic {
+            mode: StatisticMode::Exact,
+            histogram: None,
+            ..new_statistic()
         };
+        measures
+            .iter()
+            .for_each(|measure| statistic.add_measure(*measure));
+        statistic.update_statistics();
+        return statistic;
+    }
 
-        return self.percentile_25;
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} isn't {}",
+            actual,
+            expected
+        );
     }
 
-    pub fn update_percentile_75(&mut self) -> u64 {
-        self.percentile_75 = match self
-            .ordered_measures
-            .get((self.ordered_measures.len() / 4 as usize) * 3 as usize)
-        {
-            Some(i) => *i,
-            None => 0,
-        };
+    #[test]
+    fn percentile_interpolates_between_the_closest_measures() {
+        // Given in disorder, so that they have to be sorted
+        let statistic = exact_statistic(&[4.0, 1.0, 5.0, 3.0, 2.0]);
+
+        // h = 4 * 30 / 100 = 1.2, between 2 and 3
+        let cases = [
+            (Interpolation::Linear, 2.2),
+            (Interpolation::Lower, 2.0),
+            (Interpolation::Higher, 3.0),
+            (Interpolation::Nearest, 2.0),
+            (Interpolation::Midpoint, 2.5),
+        ];
+        for (interpolation, expected) in cases {
+            assert_close(statistic.percentile(30.0, interpolation).unwrap(), expected);
+        }
 
-        return self.percentile_75;
+        // h = 1.6, nearer to 3
+        assert_close(
+            statistic.percentile(40.0, Interpolation::Nearest).unwrap(),
+            3.0,
+        );
     }
 
-    pub fn update_percentile_90(&mut self) -> u64 {
-        self.percentile_90 = match self
-            .ordered_measures
-            .get((self.ordered_measures.len() / 10 as usize) * 9 as usize)
-        {
-            Some(i) => *i,
-            None => 0,
-        };
+    #[test]
+    fn percentile_of_the_extremes_and_exact_ranks() {
+        let statistic = exact_statistic(&[4.0, 1.0, 5.0, 3.0, 2.0]);
+
+        for interpolation in [
+            Interpolation::Linear,
+            Interpolation::Lower,
+            Interpolation::Higher,
+            Interpolation::Nearest,
+            Interpolation::Midpoint,
+        ] {
+            assert_close(statistic.percentile(0.0, interpolation).unwrap(), 1.0);
+            assert_close(statistic.percentile(50.0, interpolation).unwrap(), 3.0);
+            assert_close(statistic.percentile(100.0, interpolation).unwrap(), 5.0);
+        }
+        assert_eq!(
+            exact_statistic(&[]).percentile(50.0, Interpolation::Linear),
+            None
+        );
+    }
+
+    #[test]
+    fn welford_std_dev_matches_the_naive_computation() {
+        // Large offset, where the naive sum of squares would lose precision
+        let measures: Vec<f64> = (0..500)
+            .map(|i| 1e6 + ((i * 37) % 101) as f64 * 0.25)
+            .collect();
+        let statistic = exact_statistic(&measures);
+
+        let count = measures.len() as f64;
+        let mean = measures.iter().sum::<f64>() / count;
+        let variance = measures
+            .iter()
+            .map(|measure| (measure - mean).powi(2))
+            .sum::<f64>()
+            / (count - 1.0);
 
-        return self.percentile_90;
+        assert_close(statistic.avg, mean);
+        assert_close(statistic.std_dev, variance.sqrt());
+        assert_eq!(exact_statistic(&[7.0]).std_dev, 0.0);
     }
 
-    pub fn update_statistics(&mut self) {
-        self.update_avg();
-        self.update_min();
-        self.update_max();
-        self.update_median();
-        self.update_percentile_10();
-        self.update_percentile_25();
-        self.update_percentile_75();
-        self.update_percentile_90();
+    #[test]
+    fn ending_the_warm_up_keeps_the_bathroom_state_and_time_series() {
+        let mut metrics_collector = new_metrics_collector();
+        metrics_collector.warm_up =
+            warmup::new_warm_up_period(Some(warmup::WarmUp::ServedPeople(1)));
+        let started_at = metrics_collector.started_at;
+        let at = |seconds: u64| started_at + Duration::from_secs(seconds);
+
+        // Two women queued and a man in a cabin when the first person left
+        let mut bathroom = bathroom::new_bathroom(Gender::Male, &config::default_parameters());
+        bathroom.cabins[0] = Some(person::new_person(Gender::Male));
+        bathroom.female_queue = vec![
+            person::new_person(Gender::Female),
+            person::new_person(Gender::Female),
+        ];
+        metrics_collector.record_arrival(at(1));
+        metrics_collector.record_departure(at(2));
+        metrics_collector.record_bathroom_state(&bathroom, Gender::Male, at(2));
+        metrics_collector.update_warm_up(at(2));
+
+        assert!(metrics_collector.warm_up.is_over());
+        assert_eq!(metrics_collector.warm_up.excluded_arrivals, 1);
+        assert_eq!(metrics_collector.warm_up.excluded_departures, 1);
+        assert_eq!(metrics_collector.arrivals, 0);
+        assert_eq!(metrics_collector.departures, 0);
+        assert_eq!(metrics_collector.measured_from, at(2));
+        assert_eq!(metrics_collector.present_at_start, 3);
+        assert_eq!(
+            metrics_collector
+                .last_bathroom_snapshot
+                .as_ref()
+                .map(|snapshot| snapshot.id),
+            Some(bathroom.id)
+        );
+        assert_eq!(metrics_collector.female_queue_length.value, 2.0);
+        // The time series covers the whole run, warm-up included
+        let arrivals: u64 = metrics_collector
+            .time_series
+            .buckets
+            .iter()
+            .map(|bucket| bucket.arrivals)
+            .sum();
+        assert_eq!(arrivals, 1);
+    }
+
+    #[test]
+    fn merge_matches_adding_every_measure() {
+        let measures: Vec<f64> = (0..200).map(|i| ((i * 53) % 97) as f64 / 4.0).collect();
+        let whole = exact_statistic(&measures);
+
+        let mut merged = exact_statistic(&measures[..70]);
+        merged.merge(&exact_statistic(&measures[70..]));
+        // Merging nothing changes nothing
+        merged.merge(&exact_statistic(&[]));
+        merged.update_statistics();
+
+        assert_eq!(merged.count, whole.count);
+        assert_close(merged.sum, whole.sum);
+        assert_close(merged.avg, whole.avg);
+        assert_close(merged.std_dev, whole.std_dev);
+        assert_eq!(merged.min, whole.min);
+        assert_eq!(merged.max, whole.max);
+        assert_eq!(merged.median, whole.median);
+        assert_eq!(merged.percentiles, whole.percentiles);
+    }
+
+    #[test]
+    fn merge_into_an_empty_statistic_copies_it() {
+        let other = exact_statistic(&[3.0, 9.0, 6.0]);
+        let mut merged = exact_statistic(&[]);
+        merged.merge(&other);
+        merged.update_statistics();
+
+        assert_eq!(merged.count, 3);
+        assert_eq!(merged.min, 3.0);
+        assert_eq!(merged.max, 9.0);
+        assert_close(merged.avg, 6.0);
+        assert_close(merged.std_dev, other.std_dev);
     }
 }
@@ -0,0 +1,360 @@
+use rand::prelude::*;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::config::{self, Parameters};
+use super::replication::{self, AggregatedMetric, RunArgs};
+use super::report;
+use super::sweep::Range;
+
+// Cost of a run's metrics to be minimized, given the numeric fields of its report keyed by their
+// path (see `replication::flatten_metrics`)
+pub type Objective = fn(&BTreeMap<String, f64>) -> f64;
+
+// The worst 95th percentile wait of both genders, plus OPTIMIZER_FAIRNESS_WEIGHT times the
+// difference between their mean waits, in simulated seconds
+pub fn p95_wait_with_fairness_penalty(metrics: &BTreeMap<String, f64>) -> f64 {
+    let metric = |name: &str| metrics.get(name).copied().unwrap_or(0.0);
+
+    let p95_wait = metric("male_personal_total_wait_time.percentiles.p95")
+        .max(metric("female_personal_total_wait_time.percentiles.p95"));
+    let mean_wait_difference = (metric("male_personal_total_wait_time.avg")
+        - metric("female_personal_total_wait_time.avg"))
+    .abs();
+
+    return p95_wait + super::OPTIMIZER_FAIRNESS_WEIGHT * mean_wait_difference;
+}
+
+#[allow(dead_code)] // Variants are picked through `OPTIMIZER_SEARCH` in `simulation.rs`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Search {
+    // Every combination of the ranges' values
+    Grid,
+    // This many points drawn uniformly from the ranges
+    Random(usize),
+    // A population of random points, of which the better half survives each generation and breeds
+    // mutated copies of itself to replace the worse half
+    Evolutionary {
+        generations: usize,
+        population: usize,
+    },
+}
+
+// A point of the search space: a max use time threshold (in simulated seconds) and a number of
+// max uses per turn
+type Point = [f64; 2];
+
+fn new_parameters(point: Point) -> Parameters {
+    return Parameters {
+        max_use_time_threshold: Duration::from_secs_f64(point[0].max(0.0)),
+        max_uses_per_turn: Some(point[1].round().max(1.0) as u32),
+        ..config::default_parameters()
+    };
+}
+
+fn random_point<R: Rng>(ranges: &[Range; 2], rand: &mut R) -> Point {
+    return [
+        ranges[0].value_at(rand.gen()),
+        ranges[1].value_at(rand.gen()),
+    ];
+}
+
+// Moves each coordinate by up to `strength` of its range, staying within it
+fn mutate<R: Rng>(point: Point, ranges: &[Range; 2], strength: f64, rand: &mut R) -> Point {
+    let mut mutated = point;
+    for (i, range) in ranges.iter().enumerate() {
+        let span = range.max - range.min;
+        mutated[i] = (point[i] + rand.gen_range(-1.0..=1.0) * strength * span)
+            .clamp(range.min.min(range.max), range.max.max(range.min));
+    }
+
+    return mutated;
+}
+
+pub struct Evaluation {
+    pub parameters: Parameters,
+    pub generation: usize,
+    pub objective: AggregatedMetric,
+}
+
+// Evaluates points by replicating them, every point getting the same seeds so they are compared on
+// the same random draws. Points already evaluated (once rounded to parameters) aren't run again
+struct Evaluator {
+    objective: Objective,
+    seeds: Vec<u64>,
+    duration: Duration,
+    parallelism: usize,
+    running: Arc<AtomicBool>,
+    evaluations: Vec<Evaluation>,
+    file: std::fs::File,
+}
+
+impl Evaluator {
+    // The objective's mean over the replications of `point`, None if the search was interrupted
+    fn evaluate(&mut self, point: Point, generation: usize) -> Option<f64> {
+        let parameters = new_parameters(point);
+        if let Some(evaluation) = self
+            .evaluations
+            .iter()
+            .find(|evaluation| evaluation.parameters == parameters)
+        {
+            return Some(evaluation.objective.mean);
+        }
+        if !self.running.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let runs = replication::run_replications(
+            parameters,
+            &self.seeds,
+            self.duration,
+            self.parallelism,
+            self.running.clone(),
+        );
+        if runs.len() < self.seeds.len() {
+            return None;
+        }
+
+        let objectives: Vec<f64> = runs
+            .iter()
+            .map(|(metrics_collector, _)| {
+                let mut metrics = BTreeMap::new();
+                replication::flatten_metrics(
+                    &serde_json::to_value(metrics_collector).unwrap(),
+                    "",
+                    &mut metrics,
+                );
+                (self.objective)(&metrics)
+            })
+            .collect();
+        let objective = replication::aggregate(&objectives);
+
+        println!(
+            "🎯 Evaluation {} (generation {}): max use time threshold {:?}, max uses per turn {} → objective {:.3} ± {:.3}",
+            self.evaluations.len() + 1,
+            generation,
+            parameters.max_use_time_threshold,
+            parameters.max_uses_per_turn.unwrap_or(0),
+            objective.mean,
+            objective.ci95_half_width
+        );
+        // Written as points are evaluated, so an interrupted search keeps what it went through
+        let _ = writeln!(
+            self.file,
+            "{},{},{},{},{},{},{}",
+            self.evaluations.len() + 1,
+            generation,
+            parameters.max_use_time_threshold.as_secs_f64(),
+            parameters.max_uses_per_turn.unwrap_or(0),
+            objective.replications,
+            objective.mean,
+            objective.ci95_half_width
+        )
+        .and_then(|_| self.file.flush());
+
+        let mean = objective.mean;
+        self.evaluations.push(Evaluation {
+            parameters,
+            generation,
+            objective,
+        });
+
+        return Some(mean);
+    }
+
+    // The evaluation of lowest objective, along with its number
+    fn best(&self) -> Option<(usize, &Evaluation)> {
+        return self
+            .evaluations
+            .iter()
+            .enumerate()
+            .map(|(i, evaluation)| (i + 1, evaluation))
+            .min_by(|(_, a), (_, b)| a.objective.mean.total_cmp(&b.objective.mean));
+    }
+}
+
+fn search<R: Rng>(evaluator: &mut Evaluator, ranges: &[Range; 2], search: Search, rand: &mut R) {
+    match search {
+        Search::Grid => {
+            for max_use_time_threshold in ranges[0].grid_values() {
+                for max_uses_per_turn in ranges[1].grid_values() {
+                    if evaluator
+                        .evaluate([max_use_time_threshold, max_uses_per_turn], 0)
+                        .is_none()
+                    {
+                        return;
+                    }
+                }
+            }
+        }
+        Search::Random(evaluations) => {
+            for _ in 0..evaluations {
+                if evaluator.evaluate(random_point(ranges, rand), 0).is_none() {
+                    return;
+                }
+            }
+        }
+        Search::Evolutionary {
+            generations,
+            population,
+        } => {
+            let population = population.max(2);
+            let mut individuals: Vec<(Point, f64)> = vec![];
+            for _ in 0..population {
+                let point = random_point(ranges, rand);
+                match evaluator.evaluate(point, 0) {
+                    Some(objective) => individuals.push((point, objective)),
+                    None => return,
+                }
+            }
+
+            for generation in 1..generations {
+                individuals.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+                individuals.truncate(population / 2);
+
+                // Mutations shrink as generations go, to refine the best points found
+                let strength =
+                    super::OPTIMIZER_MUTATION * (1.0 - generation as f64 / generations as f64);
+                while individuals.len() < population {
+                    let (parent, _) = individuals[rand.gen_range(0..population / 2)];
+                    let child = mutate(parent, ranges, strength, rand);
+                    match evaluator.evaluate(child, generation) {
+                        Some(objective) => individuals.push((child, objective)),
+                        None => return,
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Entry point of `cargo run -- optimize [--grid | --random <evaluations> | --evolutionary
+// <generations>] [--population <n>] [--replications <n>] [--duration <simulated minutes>]
+// [--seed <base seed>] [--parallel <n>]`, returns the process exit code
+pub fn run(args: &[String], running: Arc<AtomicBool>) -> i32 {
+    let mut search_kind = super::OPTIMIZER_SEARCH;
+    let default_population = match super::OPTIMIZER_SEARCH {
+        Search::Evolutionary { population, .. } => population,
+        _ => 8,
+    };
+    let mut population = None;
+    let RunArgs {
+        replications,
+        duration,
+        base_seed,
+        parallelism,
+    } = match replication::parse_run_args(
+        args,
+        replication::new_run_args(super::OPTIMIZER_REPLICATIONS, super::OPTIMIZER_DURATION),
+        |flag, next_value| match flag {
+            "--grid" => {
+                search_kind = Search::Grid;
+                true
+            }
+            "--random" => next_value()
+                .filter(|value| *value > 0)
+                .map(|value| search_kind = Search::Random(value as usize))
+                .is_some(),
+            "--evolutionary" => next_value()
+                .filter(|value| *value > 0)
+                .map(|value| {
+                    search_kind = Search::Evolutionary {
+                        generations: value as usize,
+                        population: default_population,
+                    }
+                })
+                .is_some(),
+            "--population" => next_value()
+                .filter(|value| *value > 1)
+                .map(|value| population = Some(value as usize))
+                .is_some(),
+            _ => false,
+        },
+    ) {
+        Some(run_args) => run_args,
+        None => {
+            println!("Usage: cargo run -- optimize [--grid | --random <evaluations> | --evolutionary <generations>] [--population <n>] [--replications <n>] [--duration <simulated minutes>] [--seed <base seed>] [--parallel <n>]");
+            return 2;
+        }
+    };
+    search_kind = match (search_kind, population) {
+        (Search::Evolutionary { generations, .. }, Some(population)) => Search::Evolutionary {
+            generations,
+            population,
+        },
+        (_, None) => search_kind,
+        (_, Some(_)) => {
+            println!("💩 --population only applies to the evolutionary search");
+            return 2;
+        }
+    };
+
+    let (filename, mut file) =
+        match report::create_report_file(&report::new_report_path(), "optimization.csv") {
+            Ok(file) => file,
+            Err(e) => {
+                println!("💩 Could not create optimization report file: {}", e);
+                return 1;
+            }
+        };
+    if let Err(e) = writeln!(
+        file,
+        "evaluation,generation,max_use_time_threshold,max_uses_per_turn,replications,objective_mean,objective_ci95_half_width"
+    ) {
+        println!("💩 Could not write optimization report file: {}", e);
+        return 1;
+    }
+
+    println!(
+        "🎯 Optimizing with {:?} search, {} replications of {:?} of simulated time per evaluation, base seed {}",
+        search_kind, replications, duration, base_seed
+    );
+
+    let mut evaluator = Evaluator {
+        objective: super::OPTIMIZER_OBJECTIVE,
+        seeds: replication::derive_seeds(base_seed, replications),
+        duration,
+        parallelism: parallelism.unwrap_or(1),
+        running,
+        evaluations: vec![],
+        file,
+    };
+    let mut rand = StdRng::seed_from_u64(base_seed);
+    search(
+        &mut evaluator,
+        &[
+            super::OPTIMIZER_MAX_USE_TIME_THRESHOLD,
+            super::OPTIMIZER_MAX_USES_PER_TURN,
+        ],
+        search_kind,
+        &mut rand,
+    );
+
+    println!(
+        "📄 Optimization CSV file of {} evaluations created successfully! File located at {}",
+        evaluator.evaluations.len(),
+        filename
+    );
+
+    return match evaluator.best() {
+        Some((number, best)) => {
+            println!(
+                "🏆 Best: max use time threshold {:?}, max uses per turn {} (evaluation {}, generation {}) → objective {:.3} ± {:.3}",
+                best.parameters.max_use_time_threshold,
+                best.parameters.max_uses_per_turn.unwrap_or(0),
+                number,
+                best.generation,
+                best.objective.mean,
+                best.objective.ci95_half_width
+            );
+            0
+        }
+        None => {
+            println!("💩 No evaluation finished");
+            1
+        }
+    };
+}
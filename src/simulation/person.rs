@@ -1,10 +1,11 @@
 use core::fmt;
 use rand::distributions::Standard;
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::time::Instant;
 use uuid::Uuid;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Gender {
     Male,
     Female,
@@ -19,6 +20,15 @@ impl fmt::Display for Gender {
     }
 }
 
+impl Gender {
+    pub fn opposite(&self) -> Gender {
+        return match self {
+            Gender::Male => Gender::Female,
+            Gender::Female => Gender::Male,
+        };
+    }
+}
+
 impl Distribution<Gender> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Gender {
         if rng.gen_bool(0.5) {
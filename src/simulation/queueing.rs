@@ -0,0 +1,166 @@
+use serde::Serialize;
+
+use super::config::Parameters;
+
+// Closed-form expectations of a multi-server queue (first come, first served), to validate the
+// simulation against. Times are in simulated seconds
+#[derive(Clone, Debug, Serialize)]
+pub struct Expectations {
+    // Share of the servers' capacity in use, ρ = λ / (c μ)
+    pub utilization: f64,
+    pub probability_of_waiting: f64,
+    // Wq and Lq
+    pub mean_wait: f64,
+    pub mean_queue_length: f64,
+    // W and L
+    pub mean_time_in_system: f64,
+    pub mean_number_in_system: f64,
+}
+
+// Erlang C formula: probability that an arrival has to wait in an M/M/c queue of `offered_load`
+// erlangs (λ / μ), computed from Erlang B's recurrence to avoid overflowing factorials
+pub fn erlang_c(servers: usize, offered_load: f64) -> f64 {
+    let mut erlang_b = 1.0;
+    for k in 1..=servers {
+        erlang_b = offered_load * erlang_b / (k as f64 + offered_load * erlang_b);
+    }
+
+    let utilization = offered_load / servers as f64;
+    return erlang_b / (1.0 - utilization * (1.0 - erlang_b));
+}
+
+// M/M/c: Poisson arrivals, exponential service times and `servers` servers. None when the queue
+// is unstable (ρ >= 1) and grows without bound
+pub fn mmc(arrival_rate: f64, mean_service_time: f64, servers: usize) -> Option<Expectations> {
+    let offered_load = arrival_rate * mean_service_time;
+    let utilization = offered_load / servers as f64;
+    if servers == 0 || utilization >= 1.0 {
+        return None;
+    }
+
+    let probability_of_waiting = erlang_c(servers, offered_load);
+    let mean_wait = probability_of_waiting * mean_service_time / (servers as f64 - offered_load);
+
+    return Some(new_expectations(
+        arrival_rate,
+        mean_service_time,
+        utilization,
+        probability_of_waiting,
+        mean_wait,
+    ));
+}
+
+// Allen–Cunneen approximation of G/G/c: M/M/c's wait scaled by (ca² + cs²) / 2, the squared
+// coefficients of variation of the interarrival and service times (both 1 when exponential)
+pub fn allen_cunneen(
+    arrival_rate: f64,
+    mean_service_time: f64,
+    servers: usize,
+    interarrival_scv: f64,
+    service_scv: f64,
+) -> Option<Expectations> {
+    let mmc = mmc(arrival_rate, mean_service_time, servers)?;
+
+    return Some(new_expectations(
+        arrival_rate,
+        mean_service_time,
+        mmc.utilization,
+        mmc.probability_of_waiting,
+        mmc.mean_wait * (interarrival_scv + service_scv) / 2.0,
+    ));
+}
+
+// Everything else follows from the wait, through Little's law
+fn new_expectations(
+    arrival_rate: f64,
+    mean_service_time: f64,
+    utilization: f64,
+    probability_of_waiting: f64,
+    mean_wait: f64,
+) -> Expectations {
+    let mean_time_in_system = mean_wait + mean_service_time;
+
+    return Expectations {
+        utilization,
+        probability_of_waiting,
+        mean_wait,
+        mean_queue_length: arrival_rate * mean_wait,
+        mean_time_in_system,
+        mean_number_in_system: arrival_rate * mean_time_in_system,
+    };
+}
+
+// Arrival rate (per simulated second) and squared coefficient of variation of the interarrival
+// times of the simulation: a person arrives with probability `person_generation_rate` every
+// PERSON_GENERATION_INTERVAL, so interarrival times are geometric
+pub fn arrival_process(parameters: &Parameters) -> (f64, f64) {
+    let interval = super::PERSON_GENERATION_INTERVAL.as_secs_f64();
+    let rate = parameters.person_generation_rate;
+
+    return (rate / interval, 1.0 - rate);
+}
+
+// Mean (in simulated seconds) and squared coefficient of variation of the time people stay in
+// the bathroom, a whole number of seconds drawn uniformly from
+// MIN_PERSON_BATHROOM_SECONDS..MAX_PERSON_BATHROOM_SECONDS
+pub fn service_process() -> (f64, f64) {
+    let min = super::MIN_PERSON_BATHROOM_SECONDS as f64;
+    let values = (super::MAX_PERSON_BATHROOM_SECONDS - super::MIN_PERSON_BATHROOM_SECONDS) as f64;
+    let mean = min + (values - 1.0) / 2.0;
+    let variance = (values * values - 1.0) / 12.0;
+
+    return (mean, variance / (mean * mean));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} isn't {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn erlang_c_matches_textbook_values() {
+        // A single server makes people wait whenever it is busy
+        assert_close(erlang_c(1, 0.7), 0.7);
+        assert_close(erlang_c(2, 1.0), 1.0 / 3.0);
+        assert_close(erlang_c(3, 2.0), 4.0 / 9.0);
+        // The classic call center example: 10 agents, 8 erlangs
+        assert_close(erlang_c(10, 8.0), 0.409180);
+    }
+
+    #[test]
+    fn mmc_matches_the_textbook_m_m_2_queue() {
+        // ρ = 0.5, for which L = 2ρ / (1 - ρ²)
+        let expectations = mmc(1.0, 1.0, 2).unwrap();
+
+        assert_close(expectations.utilization, 0.5);
+        assert_close(expectations.mean_wait, 1.0 / 3.0);
+        assert_close(expectations.mean_queue_length, 1.0 / 3.0);
+        assert_close(expectations.mean_time_in_system, 4.0 / 3.0);
+        assert_close(expectations.mean_number_in_system, 4.0 / 3.0);
+    }
+
+    #[test]
+    fn unstable_queues_have_no_expectations() {
+        assert!(mmc(2.0, 1.0, 2).is_none());
+        assert!(mmc(1.0, 1.0, 0).is_none());
+        assert!(allen_cunneen(3.0, 1.0, 2, 1.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn allen_cunneen_is_m_m_c_for_exponential_times() {
+        let mmc = mmc(1.0, 1.0, 2).unwrap();
+        let exponential = allen_cunneen(1.0, 1.0, 2, 1.0, 1.0).unwrap();
+        let deterministic_service = allen_cunneen(1.0, 1.0, 2, 1.0, 0.0).unwrap();
+
+        assert_close(exponential.mean_wait, mmc.mean_wait);
+        assert_close(deterministic_service.mean_wait, mmc.mean_wait / 2.0);
+    }
+}
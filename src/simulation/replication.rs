@@ -0,0 +1,403 @@
+use rand::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::config::{self, Parameters};
+use super::metrics_collector::MetricsCollector;
+use super::report::{self, Metadata};
+
+// Two-sided 95% critical values of Student's t distribution for 1 to 30 degrees of freedom, the
+// normal distribution's is used beyond that
+const T_CRITICAL_VALUES: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+const Z_CRITICAL_VALUE: f64 = 1.960;
+
+// Metrics printed once every replication finished
+pub const SUMMARIZED_METRICS: [&str; 6] = [
+    "male_personal_total_wait_time.avg",
+    "female_personal_total_wait_time.avg",
+    "female_personal_total_wait_time.percentiles.p95",
+    "throughput_per_hour",
+    "cabin_utilization",
+    "fairness.jains_fairness_index",
+];
+
+pub type Run = (MetricsCollector, Metadata);
+
+fn t_critical_value(degrees_of_freedom: usize) -> f64 {
+    return match degrees_of_freedom {
+        0 => f64::NAN,
+        1..=30 => T_CRITICAL_VALUES[degrees_of_freedom - 1],
+        _ => Z_CRITICAL_VALUE,
+    };
+}
+
+// A metric across replications, with the 95% confidence interval of its mean
+#[derive(Clone, Debug, Serialize)]
+pub struct AggregatedMetric {
+    pub replications: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub ci95_half_width: f64,
+    pub ci95_low: f64,
+    pub ci95_high: f64,
+}
+
+pub fn aggregate(values: &[f64]) -> AggregatedMetric {
+    let n = values.len();
+    let mean = values.iter().sum::<f64>() / n as f64;
+    let std_dev = if n > 1 {
+        (values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / (n - 1) as f64)
+            .sqrt()
+    } else {
+        0.0
+    };
+    // A single replication says nothing about the spread of the mean
+    let ci95_half_width = if n > 1 {
+        t_critical_value(n - 1) * std_dev / (n as f64).sqrt()
+    } else {
+        f64::INFINITY
+    };
+
+    return AggregatedMetric {
+        replications: n,
+        mean,
+        std_dev,
+        ci95_half_width,
+        ci95_low: mean - ci95_half_width,
+        ci95_high: mean + ci95_half_width,
+    };
+}
+
+// Numeric fields of the metrics' JSON, keyed by their path, e.g. "fairness.jains_fairness_index"
+// or "male_personal_total_wait_time.percentiles.p95". Arrays (raw measures, histograms and
+// per-channel reports) are left out
+pub fn flatten_metrics(value: &Value, path: &str, metrics: &mut BTreeMap<String, f64>) {
+    match value {
+        Value::Number(number) => {
+            if let Some(number) = number.as_f64() {
+                metrics.insert(path.to_string(), number);
+            }
+        }
+        Value::Object(fields) => fields.iter().for_each(|(name, field)| {
+            let path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}.{}", path, name)
+            };
+            flatten_metrics(field, &path, metrics);
+        }),
+        _ => (),
+    }
+}
+
+// Aggregates every metric found in the runs, over the runs it was found in
+pub fn aggregate_runs(runs: &[Run]) -> BTreeMap<String, AggregatedMetric> {
+    let mut values: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+
+    runs.iter().for_each(|(metrics_collector, _)| {
+        let mut metrics = BTreeMap::new();
+        flatten_metrics(
+            &serde_json::to_value(metrics_collector).unwrap(),
+            "",
+            &mut metrics,
+        );
+        metrics.into_iter().for_each(|(path, value)| {
+            values.entry(path).or_default().push(value);
+        });
+    });
+
+    return values
+        .iter()
+        .map(|(path, values)| (path.clone(), aggregate(values)))
+        .collect();
+}
+
+// Seeds of each replication, derived from the base seed so the whole set can be reproduced
+pub fn derive_seeds(base_seed: u64, replications: usize) -> Vec<u64> {
+    let mut rand = StdRng::seed_from_u64(base_seed);
+    return (0..replications).map(|_| rand.gen()).collect();
+}
+
+// Options of the commands running replications
+pub struct RunArgs {
+    pub replications: usize,
+    // Simulated time of each replication
+    pub duration: Duration,
+    pub base_seed: u64,
+    // None for the commands that run one replication at a time, which don't accept `--parallel`
+    pub parallelism: Option<usize>,
+}
+
+// Options of a command running `replications` of `duration` by default, as many at a time as
+// there are CPUs unless PARALLEL_REPLICATIONS says otherwise
+pub fn new_run_args(replications: usize, duration: Duration) -> RunArgs {
+    return RunArgs {
+        replications,
+        duration,
+        base_seed: super::SEED.unwrap_or_else(rand::random),
+        parallelism: Some(super::PARALLEL_REPLICATIONS.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|cpus| cpus.get())
+                .unwrap_or(1)
+        })),
+    };
+}
+
+// Parses the `--replications <n>`, `--duration <simulated minutes>`, `--seed <base seed>` and
+// `--parallel <n>` options over `run_args`, handing any other flag to `parse_extra` along with a
+// function reading its value. Returns None on usage errors, including flags `parse_extra` refused
+pub fn parse_run_args(
+    args: &[String],
+    mut run_args: RunArgs,
+    mut parse_extra: impl FnMut(&str, &mut dyn FnMut() -> Option<u64>) -> bool,
+) -> Option<RunArgs> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut next_value = || args.next().and_then(|value| value.parse::<u64>().ok());
+        let parsed = match arg.as_str() {
+            "--replications" => next_value()
+                .filter(|value| *value > 0)
+                .map(|value| run_args.replications = value as usize)
+                .is_some(),
+            "--duration" => next_value()
+                .filter(|value| *value > 0)
+                .map(|value| run_args.duration = Duration::from_secs(value * 60))
+                .is_some(),
+            "--seed" => next_value()
+                .map(|value| run_args.base_seed = value)
+                .is_some(),
+            "--parallel" if run_args.parallelism.is_some() => next_value()
+                .filter(|value| *value > 0)
+                .map(|value| run_args.parallelism = Some(value as usize))
+                .is_some(),
+            flag => parse_extra(flag, &mut next_value),
+        };
+        if !parsed {
+            return None;
+        }
+    }
+
+    return Some(run_args);
+}
+
+// Runs `job` once per index below `jobs`, `parallelism` of them at a time, and returns their
+// results in the order of their indexes. Jobs that didn't start before `running` was cleared are
+// skipped
+pub fn run_in_pool<T: Send + 'static>(
+    jobs: usize,
+    parallelism: usize,
+    running: &Arc<AtomicBool>,
+    job: impl Fn(usize) -> T + Send + Sync + 'static,
+) -> Vec<T> {
+    let job = Arc::new(job);
+    let next_job = Arc::new(Mutex::new(0));
+    let results: Arc<Mutex<Vec<(usize, T)>>> = Arc::new(Mutex::new(vec![]));
+
+    let workers: Vec<thread::JoinHandle<()>> = (0..parallelism.clamp(1, jobs.max(1)))
+        .map(|_| {
+            let job = job.clone();
+            let next_job = next_job.clone();
+            let results = results.clone();
+            let running = running.clone();
+
+            thread::spawn(move || loop {
+                let index = {
+                    let mut next_job = next_job.lock().unwrap();
+                    *next_job += 1;
+                    *next_job - 1
+                };
+                if index >= jobs || !running.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let result = job(index);
+                results.lock().unwrap().push((index, result));
+            })
+        })
+        .collect();
+
+    workers
+        .into_iter()
+        .for_each(|worker| worker.join().unwrap());
+
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort_by_key(|(index, _)| *index);
+    return results.into_iter().map(|(_, result)| result).collect();
+}
+
+// Runs one replication of `parameters` per seed, `parallelism` of them at a time, each for
+// `duration` of simulated time. Replications that didn't start before `running` was cleared are
+// skipped
+pub fn run_replications(
+    parameters: Parameters,
+    seeds: &[u64],
+    duration: Duration,
+    parallelism: usize,
+    running: Arc<AtomicBool>,
+) -> Vec<Run> {
+    let seeds = seeds.to_vec();
+
+    return run_in_pool(
+        seeds.len(),
+        parallelism,
+        &running.clone(),
+        move |replication| {
+            let run = super::run_simulation(super::RunOptions {
+                parameters,
+                seed: seeds[replication],
+                duration: Some(duration),
+                running: running.clone(),
+                replication: true,
+            });
+            println!(
+            "🔁 Replication {}/{} (seed {}) finished: {} arrivals, {} departures, {} consistency violations, {} invariant violations",
+            replication + 1,
+            seeds.len(),
+            seeds[replication],
+            run.0.arrivals,
+            run.0.departures,
+            run.0.consistency.violations.len(),
+            run.0.invariant_violations.len()
+        );
+            return run;
+        },
+    );
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplicationsReport {
+    pub schema_version: u32,
+    pub base_seed: u64,
+    // Simulated seconds each replication ran for
+    pub duration: f64,
+    // Metadata of each replication, including its seed
+    pub runs: Vec<Metadata>,
+    pub metrics: BTreeMap<String, AggregatedMetric>,
+}
+
+// Writes the aggregated metrics as JSON (with every run's metadata) and as CSV, one row per metric
+pub fn write_replications_report(report: &ReplicationsReport) {
+    let path = report::new_report_path();
+
+    match report::create_report_file(&path, "replications.json").and_then(|(filename, file)| {
+        serde_json::to_writer(file, report)?;
+        return Ok(filename);
+    }) {
+        Ok(filename) => println!(
+            "📄 Replications report JSON file created successfully! File located at {}",
+            filename
+        ),
+        Err(e) => println!("💩 Could not create replications report file: {}", e),
+    };
+
+    match report::create_report_file(&path, "replications.csv").and_then(|(filename, mut file)| {
+        writeln!(
+            file,
+            "metric,replications,mean,std_dev,ci95_half_width,ci95_low,ci95_high"
+        )?;
+        for (name, metric) in &report.metrics {
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{}",
+                name,
+                metric.replications,
+                metric.mean,
+                metric.std_dev,
+                metric.ci95_half_width,
+                metric.ci95_low,
+                metric.ci95_high
+            )?;
+        }
+        return Ok(filename);
+    }) {
+        Ok(filename) => println!(
+            "📄 Replications report CSV file created successfully! File located at {}",
+            filename
+        ),
+        Err(e) => println!("💩 Could not create replications report file: {}", e),
+    };
+}
+
+// Entry point of `cargo run -- replicate [--replications <n>] [--duration <simulated minutes>]
+// [--seed <base seed>] [--parallel <n>]`, returns the process exit code
+pub fn run(args: &[String], running: Arc<AtomicBool>) -> i32 {
+    let RunArgs {
+        replications,
+        duration,
+        base_seed,
+        parallelism,
+    } = match parse_run_args(
+        args,
+        new_run_args(super::REPLICATIONS, super::REPLICATION_DURATION),
+        |_, _| false,
+    ) {
+        Some(run_args) => run_args,
+        None => {
+            println!("Usage: cargo run -- replicate [--replications <n>] [--duration <simulated minutes>] [--seed <base seed>] [--parallel <n>]");
+            return 2;
+        }
+    };
+    let parallelism = parallelism.unwrap_or(1);
+
+    println!(
+        "🔁 Running {} replications of {:?} of simulated time, {} at a time, base seed {}",
+        replications, duration, parallelism, base_seed
+    );
+    let runs = run_replications(
+        config::default_parameters(),
+        &derive_seeds(base_seed, replications),
+        duration,
+        parallelism,
+        running,
+    );
+    if runs.is_empty() {
+        println!("💩 No replication finished");
+        return 1;
+    }
+
+    let report = ReplicationsReport {
+        schema_version: report::SCHEMA_VERSION,
+        base_seed,
+        duration: duration.as_secs_f64(),
+        runs: runs.iter().map(|(_, metadata)| metadata.clone()).collect(),
+        metrics: aggregate_runs(&runs),
+    };
+
+    SUMMARIZED_METRICS.iter().for_each(|name| {
+        if let Some(metric) = report.metrics.get(*name) {
+            println!(
+                "📊 {}: {:.3} ± {:.3} (95% CI over {} replications, std dev {:.3})",
+                name, metric.mean, metric.ci95_half_width, metric.replications, metric.std_dev
+            );
+        }
+    });
+    write_replications_report(&report);
+
+    let violating_runs = runs
+        .iter()
+        .filter(|(metrics_collector, _)| !metrics_collector.invariant_violations.is_empty())
+        .count();
+    if violating_runs > 0 {
+        println!(
+            "🚨 {} replications violated invariants of the bathroom",
+            violating_runs
+        );
+        return 1;
+    }
+
+    return 0;
+}
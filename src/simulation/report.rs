@@ -0,0 +1,629 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use super::config::{self, Config};
+use super::html_report;
+use super::metrics_collector::{self, MetricsCollector, Statistic};
+
+// Version of the JSON report's layout, bumped whenever it changes in a way older readers can't
+// handle. Version 1 reports were a bare `MetricsCollector`, without envelope nor metadata
+pub const SCHEMA_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Host {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub cpus: usize,
+}
+
+fn current_host() -> Host {
+    return Host {
+        hostname: fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|hostname| hostname.trim().to_string())
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or_else(|_| "unknown".to_string()),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        cpus: std::thread::available_parallelism()
+            .map(|cpus| cpus.get())
+            .unwrap_or(1),
+    };
+}
+
+// What produced a report: the parameters and seed of the run, the code it ran, where and when
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Metadata {
+    pub config: Config,
+    pub seed: u64,
+    pub code_version: String,
+    pub git_commit: String,
+    // RFC 3339 local times
+    pub started_at: String,
+    pub finished_at: String,
+    // In seconds
+    pub wall_duration: f64,
+    pub simulated_duration: f64,
+    pub host: Host,
+}
+
+pub fn new_metadata(
+    parameters: &config::Parameters,
+    seed: u64,
+    started_at: chrono::DateTime<chrono::Local>,
+    simulated_duration: f64,
+) -> Metadata {
+    let finished_at = chrono::offset::Local::now();
+
+    return Metadata {
+        config: config::current_config(parameters),
+        seed,
+        code_version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: env!("GIT_COMMIT").to_string(),
+        started_at: started_at.to_rfc3339(),
+        finished_at: finished_at.to_rfc3339(),
+        wall_duration: (finished_at - started_at)
+            .to_std()
+            .unwrap_or_default()
+            .as_secs_f64(),
+        simulated_duration,
+        host: current_host(),
+    };
+}
+
+// The JSON report's envelope
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub schema_version: u32,
+    pub metadata: Metadata,
+    pub metrics: &'a MetricsCollector,
+}
+
+pub fn new_report(metadata: Metadata, metrics: &MetricsCollector) -> Report<'_> {
+    return Report {
+        schema_version: SCHEMA_VERSION,
+        metadata,
+        metrics,
+    };
+}
+
+// A JSON report read back from disk, whatever its schema version. Metrics are kept as plain JSON
+// since their fields vary between versions and settings (e.g. raw measures may be left out), but
+// the statistics of version 1 reports are normalized to the current layout
+#[derive(Debug)]
+pub struct LoadedReport {
+    pub schema_version: u32,
+    // None for version 1 reports, which had none
+    pub metadata: Option<Metadata>,
+    pub metrics: Value,
+}
+
+// Prefix of the percentiles of the first version 1 reports, e.g. `percentile_90`
+const V1_PERCENTILE_PREFIX: &str = "percentile_";
+
+// Every statistic of the first version 1 reports, all at the top level of the metrics
+const V1_STATISTICS: [&str; 8] = [
+    "male_queue_size",
+    "female_queue_size",
+    "time_bathroom_was_male",
+    "time_bathroom_was_female",
+    "male_personal_total_time_spent",
+    "female_personal_total_time_spent",
+    "male_personal_total_wait_time",
+    "female_personal_total_wait_time",
+];
+
+// Version 1 reports had two layouts of statistics: the first had integer `percentile_NN` fields
+// and no standard deviation (nor count, until the streaming mode), the later one has both and a
+// `percentiles` map keyed like `p90`. They are told apart by `male_queue_size`, which every report
+// has, and the statistics of the first layout are converted to the later one
+fn normalize_v1_statistics(report: &mut Value) {
+    if report["male_queue_size"].get("percentiles").is_some() {
+        return;
+    }
+
+    V1_STATISTICS.iter().for_each(|name| {
+        if let Some(Value::Object(fields)) = report.get_mut(*name) {
+            normalize_v1_statistic(fields);
+        }
+    });
+}
+
+// The count, sum and standard deviation are computed from the raw measures, which were always
+// reported then
+fn normalize_v1_statistic(fields: &mut serde_json::Map<String, Value>) {
+    let mut percentiles = serde_json::Map::new();
+    let percentile_fields: Vec<String> = fields
+        .keys()
+        .filter(|name| name.starts_with(V1_PERCENTILE_PREFIX))
+        .cloned()
+        .collect();
+    for name in percentile_fields {
+        let value = fields.remove(&name).unwrap();
+        if let Ok(p) = name[V1_PERCENTILE_PREFIX.len()..].parse::<f64>() {
+            percentiles.insert(metrics_collector::percentile_name(p), value);
+        }
+    }
+    fields.insert("percentiles".to_string(), Value::Object(percentiles));
+
+    let measures: Option<Vec<f64>> = fields
+        .get("measures")
+        .and_then(Value::as_array)
+        .map(|measures| measures.iter().filter_map(Value::as_f64).collect());
+    let (count, sum, std_dev) = match &measures {
+        Some(measures) => {
+            let count = measures.len() as f64;
+            let sum: f64 = measures.iter().sum();
+            let std_dev = if measures.len() < 2 {
+                0.0
+            } else {
+                let avg = sum / count;
+                (measures.iter().map(|m| (m - avg).powi(2)).sum::<f64>() / (count - 1.0)).sqrt()
+            };
+            (
+                Value::from(measures.len()),
+                Value::from(sum),
+                Value::from(std_dev),
+            )
+        }
+        None => (Value::Null, Value::Null, Value::Null),
+    };
+    fields.entry("count").or_insert(count);
+    fields.entry("sum").or_insert(sum);
+    fields.entry("std_dev").or_insert(std_dev);
+}
+
+pub fn load_report(contents: &str) -> Result<LoadedReport, String> {
+    let mut report: Value = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+
+    let schema_version = match report.get("schema_version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or("the schema version is not a number")? as u32,
+        None => 1,
+    };
+
+    return match schema_version {
+        1 => {
+            normalize_v1_statistics(&mut report);
+            Ok(LoadedReport {
+                schema_version,
+                metadata: None,
+                metrics: report,
+            })
+        }
+        2 => Ok(LoadedReport {
+            schema_version,
+            metadata: Some(
+                serde_json::from_value(report["metadata"].take())
+                    .map_err(|e| format!("invalid metadata: {}", e))?,
+            ),
+            metrics: report["metrics"].take(),
+        }),
+        _ => Err(format!(
+            "schema version {} is newer than the supported version {}",
+            schema_version, SCHEMA_VERSION
+        )),
+    };
+}
+
+// Renders a run's report into a file of a given format
+pub trait ReportWriter {
+    // Extension of the report file, without the leading dot
+    fn extension(&self) -> &'static str;
+    fn write(&self, report: &Report, writer: &mut dyn Write) -> io::Result<()>;
+}
+
+// Both JSON formats share the `json` extension, so only one of them should be picked
+#[allow(dead_code)] // Variants are picked through `REPORT_FORMATS` in `simulation.rs`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReportFormat {
+    // The whole report on a single line
+    Json,
+    // The whole report, indented
+    PrettyJson,
+    // One row per statistic
+    Csv,
+    // Summary tables
+    Markdown,
+    // Self-contained page with charts and tables
+    Html,
+}
+
+impl ReportFormat {
+    pub fn writer(&self) -> Box<dyn ReportWriter> {
+        return match self {
+            ReportFormat::Json => Box::new(JsonReportWriter { pretty: false }),
+            ReportFormat::PrettyJson => Box::new(JsonReportWriter { pretty: true }),
+            ReportFormat::Csv => Box::new(CsvReportWriter {}),
+            ReportFormat::Markdown => Box::new(MarkdownReportWriter {}),
+            ReportFormat::Html => Box::new(html_report::HtmlReportWriter {}),
+        };
+    }
+}
+
+// Path of the report files of a run, without extension, named after the current local time
+pub fn new_report_path() -> String {
+    return format!(
+        "{}/{}",
+        super::REPORTS_DIRECTORY,
+        chrono::offset::Local::now().format(super::REPORT_FILENAME_PATTERN)
+    );
+}
+
+// Creates `{path}.{extension}`, and the directories leading to it
+pub fn create_report_file(path: &str, extension: &str) -> io::Result<(String, File)> {
+    let filename = format!("{}.{}", path, extension);
+
+    if let Some(directory) = std::path::Path::new(&filename).parent() {
+        fs::create_dir_all(directory)?;
+    }
+
+    return Ok((filename.clone(), File::create(&filename)?));
+}
+
+// Writes the report in every format of `REPORT_FORMATS`
+pub fn write_reports(report: &Report, path: &str) {
+    super::REPORT_FORMATS.iter().for_each(|format| {
+        let writer = format.writer();
+
+        match create_report_file(path, writer.extension()).and_then(|(filename, mut file)| {
+            writer.write(report, &mut file)?;
+            return Ok(filename);
+        }) {
+            Ok(filename) => println!(
+                "📄 Statistics report {:?} file created successfully! File located at {}",
+                format, filename
+            ),
+            Err(e) => println!(
+                "💩 Could not create statistics report {:?} file: {}",
+                format, e
+            ),
+        };
+    });
+}
+
+pub struct JsonReportWriter {
+    pub pretty: bool,
+}
+
+impl ReportWriter for JsonReportWriter {
+    fn extension(&self) -> &'static str {
+        return "json";
+    }
+
+    fn write(&self, report: &Report, writer: &mut dyn Write) -> io::Result<()> {
+        if self.pretty {
+            serde_json::to_writer_pretty(&mut *writer, report)?;
+        } else {
+            serde_json::to_writer(&mut *writer, report)?;
+        }
+
+        return writer.flush();
+    }
+}
+
+pub struct CsvReportWriter {}
+
+impl ReportWriter for CsvReportWriter {
+    fn extension(&self) -> &'static str {
+        return "csv";
+    }
+
+    fn write(&self, report: &Report, writer: &mut dyn Write) -> io::Result<()> {
+        let metrics_collector = report.metrics;
+        let mut header = vec![
+            "statistic",
+            "count",
+            "sum",
+            "avg",
+            "std_dev",
+            "min",
+            "median",
+            "max",
+        ]
+        .into_iter()
+        .map(|column| column.to_string())
+        .collect::<Vec<String>>();
+        header.extend(percentile_names());
+        writeln!(writer, "{}", header.join(","))?;
+
+        for (name, statistic) in metrics_collector.statistics() {
+            let mut row = vec![
+                name.to_string(),
+                statistic.count.to_string(),
+                statistic.sum.to_string(),
+                statistic.avg.to_string(),
+                statistic.std_dev.to_string(),
+                statistic.min.to_string(),
+                statistic.median.to_string(),
+                statistic.max.to_string(),
+            ];
+            row.extend(percentile_values(statistic).map(|value| value.to_string()));
+            writeln!(writer, "{}", row.join(","))?;
+        }
+
+        return writer.flush();
+    }
+}
+
+pub struct MarkdownReportWriter {}
+
+impl ReportWriter for MarkdownReportWriter {
+    fn extension(&self) -> &'static str {
+        return "md";
+    }
+
+    fn write(&self, report: &Report, writer: &mut dyn Write) -> io::Result<()> {
+        let metrics_collector = report.metrics;
+        writeln!(writer, "# Bathroom simulation report")?;
+        writeln!(writer)?;
+        writeln!(writer, "Times are in simulated seconds.")?;
+        writeln!(writer)?;
+        writeln!(writer, "| Run | |")?;
+        writeln!(writer, "| --- | --- |")?;
+        metadata_summary(&report.metadata)
+            .iter()
+            .try_for_each(|(field, value)| writeln!(writer, "| {} | {} |", field, value))?;
+        writeln!(writer)?;
+        writeln!(writer, "| Metric | Value |")?;
+        writeln!(writer, "| --- | --- |")?;
+        summary(metrics_collector)
+            .iter()
+            .try_for_each(|(metric, value)| writeln!(writer, "| {} | {} |", metric, value))?;
+        writeln!(writer)?;
+
+        if !metrics_collector.invariant_violations.is_empty() {
+            writeln!(writer, "## Invariant violations")?;
+            writeln!(writer)?;
+            metrics_collector
+                .invariant_violations
+                .iter()
+                .try_for_each(|violation| writeln!(writer, "- {}", violation))?;
+            writeln!(writer)?;
+        }
+        if !metrics_collector.liveness_failures.is_empty() {
+            writeln!(writer, "## Liveness failures")?;
+            writeln!(writer)?;
+            metrics_collector
+                .liveness_failures
+                .iter()
+                .try_for_each(|failure| writeln!(writer, "- {}", failure))?;
+            writeln!(writer)?;
+        }
+        if !metrics_collector.consistency.violations.is_empty() {
+            writeln!(writer, "## Consistency violations")?;
+            writeln!(writer)?;
+            metrics_collector
+                .consistency
+                .violations
+                .iter()
+                .try_for_each(|violation| writeln!(writer, "- {}", violation))?;
+            writeln!(writer)?;
+        }
+
+        let percentile_names = percentile_names();
+        writeln!(
+            writer,
+            "| Statistic | Count | Avg | Std dev | Min | Median | {} | Max |",
+            percentile_names.join(" | ")
+        )?;
+        writeln!(
+            writer,
+            "| --- |{}",
+            " ---: |".repeat(percentile_names.len() + 6)
+        )?;
+        for (name, statistic) in metrics_collector.statistics() {
+            writeln!(
+                writer,
+                "| {} | {} | {:.2} | {:.2} | {:.2} | {:.2} | {} | {:.2} |",
+                name,
+                statistic.count,
+                statistic.avg,
+                statistic.std_dev,
+                statistic.min,
+                statistic.median,
+                percentile_values(statistic)
+                    .map(|value| format!("{:.2}", value))
+                    .collect::<Vec<String>>()
+                    .join(" | "),
+                statistic.max
+            )?;
+        }
+
+        return writer.flush();
+    }
+}
+
+// What produced the run, as (field, formatted value) pairs
+pub fn metadata_summary(metadata: &Metadata) -> Vec<(&'static str, String)> {
+    return vec![
+        ("Seed", metadata.seed.to_string()),
+        (
+            "Code version",
+            format!("{} ({})", metadata.code_version, metadata.git_commit),
+        ),
+        ("Started at", metadata.started_at.clone()),
+        ("Finished at", metadata.finished_at.clone()),
+        ("Wall duration", format!("{:.1}s", metadata.wall_duration)),
+        (
+            "Host",
+            format!(
+                "{} ({} {}, {} CPUs)",
+                metadata.host.hostname, metadata.host.os, metadata.host.arch, metadata.host.cpus
+            ),
+        ),
+    ];
+}
+
+// Key figures of the run, as (metric, formatted value) pairs
+pub fn summary(metrics_collector: &MetricsCollector) -> Vec<(&'static str, String)> {
+    let fairness = &metrics_collector.fairness;
+    let warm_up = &metrics_collector.warm_up;
+    let consistency = &metrics_collector.consistency;
+
+    return vec![
+        (
+            "Simulated duration",
+            format!("{:.0}", metrics_collector.simulated_duration),
+        ),
+        (
+            "Warm-up",
+            match warm_up.ended_at {
+                Some(ended_at) if ended_at > 0.0 => format!(
+                    "{} ended at {:.0} ({} arrivals and {} departures left out)",
+                    warm_up.mode, ended_at, warm_up.excluded_arrivals, warm_up.excluded_departures
+                ),
+                Some(_) => warm_up.mode.clone(),
+                None => format!("{} never ended, the whole run is counted", warm_up.mode),
+            },
+        ),
+        (
+            "Measured duration",
+            format!("{:.0}", metrics_collector.measured_duration),
+        ),
+        ("Arrivals", metrics_collector.arrivals.to_string()),
+        ("Departures", metrics_collector.departures.to_string()),
+        (
+            "Gender switches",
+            metrics_collector.gender_switches.to_string(),
+        ),
+        (
+            "Throughput per hour",
+            format!("{:.2}", metrics_collector.throughput_per_hour),
+        ),
+        (
+            "Cabin utilization",
+            format!("{:.1}%", metrics_collector.cabin_utilization * 100.0),
+        ),
+        ("Idle time", format!("{:.0}", metrics_collector.idle_time)),
+        (
+            "Average male queue length",
+            format!("{:.2}", metrics_collector.male_queue_length.avg),
+        ),
+        (
+            "Average female queue length",
+            format!("{:.2}", metrics_collector.female_queue_length.avg),
+        ),
+        (
+            "Jain's fairness index",
+            format!("{:.3}", fairness.jains_fairness_index),
+        ),
+        (
+            "Starved (male / female)",
+            format!("{} / {}", fairness.male_starved, fairness.female_starved),
+        ),
+        (
+            "Invariant violations",
+            metrics_collector.invariant_violations.len().to_string(),
+        ),
+        (
+            "Liveness failures",
+            metrics_collector.liveness_failures.len().to_string(),
+        ),
+        (
+            "Consistency checks passed",
+            format!(
+                "{} / {}",
+                consistency.checks.len() - consistency.violations.len(),
+                consistency.checks.len()
+            ),
+        ),
+    ];
+}
+
+// Reported percentiles, in the order of `REPORTED_PERCENTILES`
+pub fn percentile_names() -> Vec<String> {
+    return super::REPORTED_PERCENTILES
+        .iter()
+        .map(|p| metrics_collector::percentile_name(*p))
+        .collect();
+}
+
+pub fn percentile_values(statistic: &Statistic) -> impl Iterator<Item = f64> + '_ {
+    return percentile_names()
+        .into_iter()
+        .map(|name| statistic.percentiles.get(&name).copied().unwrap_or(0.0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_statistics_are_normalized() {
+        let report = load_report(
+            r#"{
+                "male_queue_size": {
+                    "measures": [1, 2, 3, 6], "avg": 3, "min": 1, "max": 6, "median": 2,
+                    "percentile_10": 1, "percentile_25": 1, "percentile_75": 3, "percentile_90": 6,
+                    "ordered_measures": [1, 2, 3, 6]
+                },
+                "female_queue_size": {
+                    "measures": [], "avg": 0, "min": 0, "max": 0, "median": 0,
+                    "percentile_10": 0, "percentile_25": 0, "percentile_75": 0, "percentile_90": 0,
+                    "ordered_measures": []
+                },
+                "router": {
+                    "person_joined_the_queue": {"avg": 1.5, "min": 1.0, "median": 1.5, "max": 2.0}
+                },
+                "arrivals": 4
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(report.schema_version, 1);
+        assert!(report.metadata.is_none());
+        let statistic = &report.metrics["male_queue_size"];
+        assert_eq!(
+            statistic["percentiles"],
+            serde_json::json!({"p10": 1, "p25": 1, "p75": 3, "p90": 6})
+        );
+        assert!(statistic.get("percentile_90").is_none());
+        assert_eq!(statistic["count"], 4);
+        assert_eq!(statistic["sum"], 12.0);
+        assert!((statistic["std_dev"].as_f64().unwrap() - (14.0f64 / 3.0).sqrt()).abs() < 1e-9);
+
+        assert_eq!(report.metrics["female_queue_size"]["count"], 0);
+        assert_eq!(report.metrics["female_queue_size"]["std_dev"], 0.0);
+
+        // Not a statistic, even if it looks like one
+        assert_eq!(
+            report.metrics["router"]["person_joined_the_queue"],
+            serde_json::json!({"avg": 1.5, "min": 1.0, "median": 1.5, "max": 2.0})
+        );
+        assert_eq!(report.metrics["arrivals"], 4);
+    }
+
+    #[test]
+    fn later_v1_statistics_are_left_untouched() {
+        let contents = r#"{
+            "male_queue_size": {
+                "count": 4, "sum": 12.0, "avg": 3.0, "std_dev": 2.2, "min": 1.0, "median": 2.5,
+                "max": 6.0, "percentiles": {"p90": 6.0}, "percentile_90": "not a percentile"
+            },
+            "female_personal_total_wait_time": {"avg": 1.5, "min": 1.0, "median": 1.5, "max": 2.0}
+        }"#;
+        let report = load_report(contents).unwrap();
+
+        assert_eq!(
+            report.metrics,
+            serde_json::from_str::<Value>(contents).unwrap()
+        );
+    }
+
+    #[test]
+    fn v1_statistics_without_measures_have_no_count() {
+        let report = load_report(
+            r#"{"male_queue_size": {"avg": 3, "min": 1, "max": 6, "median": 2, "percentile_90": 6}}"#,
+        )
+        .unwrap();
+
+        let statistic = &report.metrics["male_queue_size"];
+        assert_eq!(statistic["count"], Value::Null);
+        assert_eq!(statistic["std_dev"], Value::Null);
+        assert_eq!(statistic["percentiles"], serde_json::json!({"p90": 6}));
+    }
+}
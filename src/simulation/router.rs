@@ -1,20 +1,218 @@
-use std::collections::HashMap;
-use std::sync::mpsc::{self, Receiver, Sender};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use super::channel::{self, ChannelRegistry, Receiver, Sender};
+use super::event::Event;
+
 pub struct Router {
-    pub outbox: HashMap<Uuid, Sender<super::event::Event>>,
-    pub listeners: HashMap<String, Vec<Sender<super::event::Event>>>,
-    pub rx: Receiver<super::event::Event>,
-    pub tx: Sender<super::event::Event>,
+    pub outbox: HashMap<Uuid, Sender>,
+    pub listeners: HashMap<String, Vec<Sender>>,
+    pub rx: Receiver,
+    pub tx: Sender,
+    pub statistics: Arc<Mutex<RouterStatistics>>,
 }
 
-pub fn new_router() -> Router {
-    let (tx, rx): (Sender<super::event::Event>, Receiver<super::event::Event>) = mpsc::channel();
+pub fn new_router(channels: &ChannelRegistry) -> Router {
+    let (tx, rx) = channel::new_channel(channels, "router", super::ROUTER_CHANNEL);
     return Router {
         outbox: HashMap::new(),
         listeners: HashMap::new(),
         rx,
         tx,
+        statistics: Arc::new(Mutex::new(new_router_statistics())),
+    };
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RequestError {
+    // No reply arrived in time
+    Timeout,
+    // The router or the replies inbox is gone
+    Disconnected,
+}
+
+// Sends a request event through the router and waits for the reply with the same correlation id.
+// `replies_rx` must be the inbox the requester registered in the router's outbox, and it should
+// only be used for replies: replies to older, timed out requests are discarded while waiting
+pub fn request(
+    router_tx: &Sender,
+    replies_rx: &Receiver,
+    request: Event,
+    timeout: Duration,
+) -> Result<Event, RequestError> {
+    let deadline = Instant::now() + timeout;
+    let correlation_id = request.correlation_id;
+
+    if router_tx.send(request).is_err() {
+        return Err(RequestError::Disconnected);
+    }
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        match replies_rx.recv_timeout(remaining) {
+            Ok(reply) if reply.correlation_id == correlation_id => return Ok(reply),
+            Ok(_) => (),
+            Err(RecvTimeoutError::Timeout) => return Err(RequestError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => return Err(RequestError::Disconnected),
+        }
+    }
+}
+
+// Throughput of a single kind of event. Latencies are measured in real (not simulated) time,
+// from the moment the event was created to the moment the router received it
+#[derive(Clone, Debug, Serialize)]
+pub struct TopicStatistics {
+    pub routed: u64,
+    pub deliveries: u64,
+    pub max_fan_out: u64,
+    pub mean_fan_out: f64,
+    pub total_delivery_latency_micros: u64,
+    pub mean_delivery_latency_micros: f64,
+    pub max_delivery_latency_micros: u64,
+}
+
+fn new_topic_statistics() -> TopicStatistics {
+    return TopicStatistics {
+        routed: 0,
+        deliveries: 0,
+        max_fan_out: 0,
+        mean_fan_out: 0.0,
+        total_delivery_latency_micros: 0,
+        mean_delivery_latency_micros: 0.0,
+        max_delivery_latency_micros: 0,
+    };
+}
+
+impl TopicStatistics {
+    pub fn add_routed_event(&mut self, fan_out: u64, latency: Duration) {
+        let latency_micros = latency.as_micros() as u64;
+
+        self.routed += 1;
+        self.deliveries += fan_out;
+        self.max_fan_out = self.max_fan_out.max(fan_out);
+        self.mean_fan_out = self.deliveries as f64 / self.routed as f64;
+        self.total_delivery_latency_micros += latency_micros;
+        self.mean_delivery_latency_micros =
+            self.total_delivery_latency_micros as f64 / self.routed as f64;
+        self.max_delivery_latency_micros = self.max_delivery_latency_micros.max(latency_micros);
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RouterStatistics {
+    pub total: TopicStatistics,
+    pub topics: BTreeMap<String, TopicStatistics>,
+    pub peak_inbox_depth: usize,
+}
+
+pub fn new_router_statistics() -> RouterStatistics {
+    return RouterStatistics {
+        total: new_topic_statistics(),
+        topics: BTreeMap::new(),
+        peak_inbox_depth: 0,
     };
 }
+
+impl RouterStatistics {
+    pub fn add_routed_event(
+        &mut self,
+        name: &str,
+        fan_out: u64,
+        latency: Duration,
+        inbox_depth: usize,
+    ) {
+        self.total.add_routed_event(fan_out, latency);
+        self.topics
+            .entry(name.to_string())
+            .or_insert_with(new_topic_statistics)
+            .add_routed_event(fan_out, latency);
+        self.peak_inbox_depth = self.peak_inbox_depth.max(inbox_depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::event::{self, BATHROOM_STATE_REPLIED, BATHROOM_STATE_REQUESTED};
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    // The reply of the bathroom the request is addressed to
+    fn reply(request: &Event) -> Event {
+        return event::new_reply_event(
+            BATHROOM_STATE_REPLIED.to_string(),
+            request.destination_id.unwrap(),
+            request,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn requests_return_the_reply_with_their_correlation_id() {
+        let channels = channel::new_channel_registry();
+        let (router_tx, router_rx) = channel::new_channel(&channels, "router", channel::UNBOUNDED);
+        let (replies_tx, replies_rx) =
+            channel::new_channel(&channels, "replies", channel::UNBOUNDED);
+        let request = event::new_request_event(
+            BATHROOM_STATE_REQUESTED.to_string(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+
+        let bathroom = thread::spawn(move || {
+            let request = router_rx.recv().unwrap();
+            // A late reply to an older request is discarded
+            let mut stale_reply = reply(&request);
+            stale_reply.correlation_id = Some(Uuid::new_v4());
+            replies_tx.send(stale_reply).unwrap();
+            replies_tx.send(reply(&request)).unwrap();
+        });
+
+        let correlation_id = request.correlation_id;
+        let reply = super::request(&router_tx, &replies_rx, request, TIMEOUT).unwrap();
+        assert_eq!(reply.correlation_id, correlation_id);
+        bathroom.join().unwrap();
+    }
+
+    #[test]
+    fn requests_without_reply_time_out() {
+        let channels = channel::new_channel_registry();
+        let (router_tx, _router_rx) = channel::new_channel(&channels, "router", channel::UNBOUNDED);
+        let (_replies_tx, replies_rx) =
+            channel::new_channel(&channels, "replies", channel::UNBOUNDED);
+        let request = event::new_request_event(
+            BATHROOM_STATE_REQUESTED.to_string(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+
+        let sent_at = Instant::now();
+        let result = super::request(&router_tx, &replies_rx, request, TIMEOUT);
+        assert_eq!(result.unwrap_err(), RequestError::Timeout);
+        assert!(sent_at.elapsed() >= TIMEOUT);
+    }
+
+    #[test]
+    fn requests_to_a_stopped_router_are_disconnected() {
+        let channels = channel::new_channel_registry();
+        let (router_tx, router_rx) = channel::new_channel(&channels, "router", channel::UNBOUNDED);
+        let (_replies_tx, replies_rx) =
+            channel::new_channel(&channels, "replies", channel::UNBOUNDED);
+        drop(router_rx);
+        let request = event::new_request_event(
+            BATHROOM_STATE_REQUESTED.to_string(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+
+        let result = super::request(&router_tx, &replies_rx, request, TIMEOUT);
+        assert_eq!(result.unwrap_err(), RequestError::Disconnected);
+    }
+}
@@ -0,0 +1,329 @@
+use rand::prelude::*;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::config::{self, Parameters};
+use super::replication::{self, AggregatedMetric, RunArgs};
+use super::report;
+
+// Values a parameter is swept over: `steps` evenly spaced values from `min` to `max` (both
+// included) on a grid, or anywhere between them in a Latin hypercube
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Range {
+    pub min: f64,
+    pub max: f64,
+    pub steps: usize,
+}
+
+impl Range {
+    pub fn grid_values(&self) -> Vec<f64> {
+        if self.steps <= 1 {
+            return vec![self.min];
+        }
+
+        return (0..self.steps)
+            .map(|step| self.value_at(step as f64 / (self.steps - 1) as f64))
+            .collect();
+    }
+
+    // `fraction` goes from 0 (`min`) to 1 (`max`)
+    pub fn value_at(&self, fraction: f64) -> f64 {
+        return self.min + (self.max - self.min) * fraction;
+    }
+}
+
+#[allow(dead_code)] // Variants are picked through `SWEEP_SAMPLING` in `simulation.rs`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sampling {
+    // Every combination of the ranges' values
+    Grid,
+    // This many configurations, each range being split into as many strata, every one of which
+    // is sampled exactly once. Far fewer configurations than a grid still cover each range evenly
+    LatinHypercube(usize),
+}
+
+// A configuration from a value of each swept parameter. Bathroom sizes are rounded, thresholds are
+// in simulated seconds and generation rates are probabilities
+fn new_parameters(values: [f64; 3]) -> Parameters {
+    let [bathroom_size, max_use_time_threshold, person_generation_rate] = values;
+
+    return Parameters {
+        bathroom_size: bathroom_size.round().max(1.0) as usize,
+        max_use_time_threshold: Duration::from_secs_f64(max_use_time_threshold.max(0.0)),
+        person_generation_rate: person_generation_rate.clamp(0.0, 1.0),
+        ..config::default_parameters()
+    };
+}
+
+pub fn configurations<R: Rng>(
+    ranges: [Range; 3],
+    sampling: Sampling,
+    rand: &mut R,
+) -> Vec<Parameters> {
+    return match sampling {
+        Sampling::Grid => {
+            let mut configurations = vec![];
+            for bathroom_size in ranges[0].grid_values() {
+                for max_use_time_threshold in ranges[1].grid_values() {
+                    for person_generation_rate in ranges[2].grid_values() {
+                        configurations.push(new_parameters([
+                            bathroom_size,
+                            max_use_time_threshold,
+                            person_generation_rate,
+                        ]));
+                    }
+                }
+            }
+            configurations
+        }
+        Sampling::LatinHypercube(samples) => {
+            let strata: Vec<Vec<usize>> = ranges
+                .iter()
+                .map(|_| {
+                    let mut strata: Vec<usize> = (0..samples).collect();
+                    strata.shuffle(rand);
+                    strata
+                })
+                .collect();
+
+            (0..samples)
+                .map(|sample| {
+                    let mut values = [0.0; 3];
+                    for (i, range) in ranges.iter().enumerate() {
+                        let fraction =
+                            (strata[i][sample] as f64 + rand.gen::<f64>()) / samples as f64;
+                        values[i] = range.value_at(fraction);
+                    }
+                    new_parameters(values)
+                })
+                .collect()
+        }
+    };
+}
+
+fn csv_header() -> String {
+    let mut header =
+        "configuration,bathroom_size,max_use_time_threshold,person_generation_rate,replications"
+            .to_string();
+    replication::SUMMARIZED_METRICS.iter().for_each(|name| {
+        header += &format!(",{}.mean,{}.ci95_half_width", name, name);
+    });
+
+    return header;
+}
+
+fn csv_row(
+    configuration: usize,
+    parameters: &Parameters,
+    replications: usize,
+    metrics: &BTreeMap<String, AggregatedMetric>,
+) -> String {
+    let mut row = format!(
+        "{},{},{},{},{}",
+        configuration,
+        parameters.bathroom_size,
+        parameters.max_use_time_threshold.as_secs_f64(),
+        parameters.person_generation_rate,
+        replications
+    );
+    replication::SUMMARIZED_METRICS.iter().for_each(|name| {
+        row += &match metrics.get(*name) {
+            Some(metric) => format!(",{},{}", metric.mean, metric.ci95_half_width),
+            None => ",,".to_string(),
+        };
+    });
+
+    return row;
+}
+
+// Entry point of `cargo run -- sweep [--grid | --latin-hypercube <samples>] [--replications <n>]
+// [--duration <simulated minutes>] [--seed <base seed>] [--parallel <n>]`, returns the process
+// exit code
+pub fn run(args: &[String], running: Arc<AtomicBool>) -> i32 {
+    let mut sampling = super::SWEEP_SAMPLING;
+    let RunArgs {
+        replications,
+        duration,
+        base_seed,
+        parallelism,
+    } = match replication::parse_run_args(
+        args,
+        replication::new_run_args(super::SWEEP_REPLICATIONS, super::SWEEP_DURATION),
+        |flag, next_value| match flag {
+            "--grid" => {
+                sampling = Sampling::Grid;
+                true
+            }
+            "--latin-hypercube" => next_value()
+                .filter(|value| *value > 0)
+                .map(|value| sampling = Sampling::LatinHypercube(value as usize))
+                .is_some(),
+            _ => false,
+        },
+    ) {
+        Some(run_args) => run_args,
+        None => {
+            println!("Usage: cargo run -- sweep [--grid | --latin-hypercube <samples>] [--replications <n>] [--duration <simulated minutes>] [--seed <base seed>] [--parallel <n>]");
+            return 2;
+        }
+    };
+    let parallelism = parallelism.unwrap_or(1);
+
+    let mut rand = StdRng::seed_from_u64(base_seed);
+    let configurations = configurations(
+        [
+            super::SWEEP_BATHROOM_SIZE,
+            super::SWEEP_MAX_USE_TIME_THRESHOLD,
+            super::SWEEP_PERSON_GENERATION_RATE,
+        ],
+        sampling,
+        &mut rand,
+    );
+    // Every configuration is run with the same seeds, hence the same random draws, so that
+    // differences between configurations aren't blurred by differences between their arrivals
+    let seeds = replication::derive_seeds(base_seed, replications);
+
+    println!(
+        "🧹 Sweeping {} configurations ({:?}) with {} replications of {:?} of simulated time each, base seed {}",
+        configurations.len(),
+        sampling,
+        replications,
+        duration,
+        base_seed
+    );
+
+    let (filename, mut file) =
+        match report::create_report_file(&report::new_report_path(), "sweep.csv") {
+            Ok(file) => file,
+            Err(e) => {
+                println!("💩 Could not create sweep report file: {}", e);
+                return 1;
+            }
+        };
+    if let Err(e) = writeln!(file, "{}", csv_header()) {
+        println!("💩 Could not write sweep report file: {}", e);
+        return 1;
+    }
+
+    let mut swept = 0;
+    for (configuration, parameters) in configurations.iter().enumerate() {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+
+        println!(
+            "🧹 Configuration {}/{}: {:?}",
+            configuration + 1,
+            configurations.len(),
+            parameters
+        );
+        let runs = replication::run_replications(
+            *parameters,
+            &seeds,
+            duration,
+            parallelism,
+            running.clone(),
+        );
+        if runs.is_empty() {
+            break;
+        }
+
+        // Written as configurations are swept, so an interrupted sweep keeps what it went through
+        let row = csv_row(
+            configuration,
+            parameters,
+            runs.len(),
+            &replication::aggregate_runs(&runs),
+        );
+        if let Err(e) = writeln!(file, "{}", row).and_then(|_| file.flush()) {
+            println!("💩 Could not write sweep report file: {}", e);
+            return 1;
+        }
+        swept += 1;
+    }
+
+    println!(
+        "📄 Sweep CSV file of {} configurations created successfully! File located at {}",
+        swept, filename
+    );
+
+    return if swept == configurations.len() { 0 } else { 1 };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RANGES: [Range; 3] = [
+        Range {
+            min: 0.0,
+            max: 1_000_000.0,
+            steps: 2,
+        },
+        Range {
+            min: 0.0,
+            max: 600.0,
+            steps: 3,
+        },
+        Range {
+            min: 0.0,
+            max: 1.0,
+            steps: 4,
+        },
+    ];
+
+    // Values of each swept parameter across configurations
+    fn swept_values(configurations: &[Parameters]) -> [Vec<f64>; 3] {
+        return [
+            configurations
+                .iter()
+                .map(|parameters| parameters.bathroom_size as f64)
+                .collect(),
+            configurations
+                .iter()
+                .map(|parameters| parameters.max_use_time_threshold.as_secs_f64())
+                .collect(),
+            configurations
+                .iter()
+                .map(|parameters| parameters.person_generation_rate)
+                .collect(),
+        ];
+    }
+
+    #[test]
+    fn latin_hypercube_samples_every_stratum_once() {
+        let samples = 25;
+        let mut rand = StdRng::seed_from_u64(42);
+        let configurations = configurations(RANGES, Sampling::LatinHypercube(samples), &mut rand);
+        assert_eq!(configurations.len(), samples);
+
+        for (range, values) in RANGES.iter().zip(swept_values(&configurations)) {
+            let mut strata: Vec<usize> = values
+                .iter()
+                .map(|value| {
+                    let fraction = (value - range.min) / (range.max - range.min);
+                    ((fraction * samples as f64) as usize).min(samples - 1)
+                })
+                .collect();
+            strata.sort_unstable();
+            assert_eq!(strata, (0..samples).collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn grid_covers_every_combination() {
+        let mut rand = StdRng::seed_from_u64(42);
+        let configurations = configurations(RANGES, Sampling::Grid, &mut rand);
+        assert_eq!(configurations.len(), 2 * 3 * 4);
+
+        let [bathroom_sizes, thresholds, rates] = swept_values(&configurations);
+        assert_eq!(bathroom_sizes[0], 1.0);
+        assert_eq!(bathroom_sizes[23], 1_000_000.0);
+        assert_eq!(thresholds[..3], [0.0, 0.0, 0.0]);
+        assert_eq!(thresholds[4..8], [300.0; 4]);
+        assert_eq!(rates[..4], [0.0, 1.0 / 3.0, 2.0 / 3.0, 1.0]);
+    }
+}
@@ -0,0 +1,207 @@
+use serde::Serialize;
+
+use super::person::Gender;
+
+// Bathroom state over one bucket of simulated time. Lengths and occupancy are time-weighted
+// averages over the bucket, and the allowed gender is the one allowed for most of it
+#[derive(Clone, Debug, Serialize)]
+pub struct TimeSeriesBucket {
+    // Simulated seconds since the start of the simulation
+    pub start: f64,
+    pub male_queue_length: f64,
+    pub female_queue_length: f64,
+    pub occupied_cabins: f64,
+    pub arrivals: u64,
+    pub departures: u64,
+    pub allowed_gender: Gender,
+    #[serde(skip)]
+    covered: f64,
+    #[serde(skip)]
+    male_seconds: f64,
+    #[serde(skip)]
+    female_seconds: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct State {
+    male_queue_length: f64,
+    female_queue_length: f64,
+    occupied_cabins: f64,
+    allowed_gender: Gender,
+}
+
+#[derive(Debug)]
+pub struct TimeSeries {
+    pub bucket_width: f64,
+    pub buckets: Vec<TimeSeriesBucket>,
+    // None until the first bathroom state is recorded
+    state: Option<State>,
+    // Simulated seconds since the start of the simulation of the last state change
+    state_since: f64,
+}
+
+pub fn new_time_series(bucket_width: f64) -> TimeSeries {
+    return TimeSeries {
+        bucket_width,
+        buckets: vec![],
+        state: None,
+        state_since: 0.0,
+    };
+}
+
+impl TimeSeries {
+    fn bucket(&mut self, at: f64) -> &mut TimeSeriesBucket {
+        let index = (at.max(0.0) / self.bucket_width) as usize;
+
+        while self.buckets.len() <= index {
+            let start = self.buckets.len() as f64 * self.bucket_width;
+            self.buckets.push(TimeSeriesBucket {
+                start,
+                male_queue_length: 0.0,
+                female_queue_length: 0.0,
+                occupied_cabins: 0.0,
+                arrivals: 0,
+                departures: 0,
+                allowed_gender: Gender::Male,
+                covered: 0.0,
+                male_seconds: 0.0,
+                female_seconds: 0.0,
+            });
+        }
+
+        return &mut self.buckets[index];
+    }
+
+    // Spreads the current state over every bucket between the last change and `until`
+    fn accumulate(&mut self, until: f64) {
+        let mut from = self.state_since;
+
+        while let Some(state) = self.state.filter(|_| from < until) {
+            let bucket_width = self.bucket_width;
+            let bucket = self.bucket(from);
+            let to = until.min(bucket.start + bucket_width);
+            let duration = to - from;
+
+            bucket.male_queue_length += state.male_queue_length * duration;
+            bucket.female_queue_length += state.female_queue_length * duration;
+            bucket.occupied_cabins += state.occupied_cabins * duration;
+            bucket.covered += duration;
+            match state.allowed_gender {
+                Gender::Male => bucket.male_seconds += duration,
+                Gender::Female => bucket.female_seconds += duration,
+            }
+
+            from = to;
+        }
+
+        self.state_since = self.state_since.max(until);
+    }
+
+    pub fn record_state(
+        &mut self,
+        at: f64,
+        male_queue_length: usize,
+        female_queue_length: usize,
+        occupied_cabins: usize,
+        allowed_gender: Gender,
+    ) {
+        self.accumulate(at);
+        self.state = Some(State {
+            male_queue_length: male_queue_length as f64,
+            female_queue_length: female_queue_length as f64,
+            occupied_cabins: occupied_cabins as f64,
+            allowed_gender,
+        });
+    }
+
+    pub fn add_arrival(&mut self, at: f64) {
+        self.bucket(at).arrivals += 1;
+    }
+
+    pub fn add_departure(&mut self, at: f64) {
+        self.bucket(at).departures += 1;
+    }
+
+    // Turns the accumulated sums into averages, the last bucket may only be partially covered
+    pub fn finish(&mut self, at: f64) {
+        self.accumulate(at);
+
+        self.buckets.iter_mut().for_each(|bucket| {
+            if bucket.covered > 0.0 {
+                bucket.male_queue_length /= bucket.covered;
+                bucket.female_queue_length /= bucket.covered;
+                bucket.occupied_cabins /= bucket.covered;
+            }
+            bucket.allowed_gender = if bucket.male_seconds >= bucket.female_seconds {
+                Gender::Male
+            } else {
+                Gender::Female
+            };
+        });
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = "minute,male_queue_length,female_queue_length,occupied_cabins,arrivals,departures,allowed_gender\n".to_string();
+
+        self.buckets.iter().for_each(|bucket| {
+            csv += &format!(
+                "{},{:.3},{:.3},{:.3},{},{},{}\n",
+                bucket.start / 60.0,
+                bucket.male_queue_length,
+                bucket.female_queue_length,
+                bucket.occupied_cabins,
+                bucket.arrivals,
+                bucket.departures,
+                bucket.allowed_gender
+            );
+        });
+
+        return csv;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_average_the_state_over_the_time_it_held() {
+        let mut time_series = new_time_series(60.0);
+
+        time_series.record_state(0.0, 2, 0, 1, Gender::Male);
+        // Halfway through the first minute, the queue empties and the bathroom switches
+        time_series.record_state(30.0, 0, 4, 0, Gender::Female);
+        time_series.add_arrival(10.0);
+        time_series.add_departure(70.0);
+        time_series.finish(90.0);
+
+        assert_eq!(time_series.buckets.len(), 2);
+        let first = &time_series.buckets[0];
+        assert_eq!(first.male_queue_length, 1.0);
+        assert_eq!(first.female_queue_length, 2.0);
+        assert_eq!(first.occupied_cabins, 0.5);
+        assert_eq!((first.arrivals, first.departures), (1, 0));
+        // Ties go to men
+        assert_eq!(first.allowed_gender, Gender::Male);
+        // The last bucket is only averaged over the 30 seconds it covers
+        let last = &time_series.buckets[1];
+        assert_eq!(last.female_queue_length, 4.0);
+        assert_eq!(last.allowed_gender, Gender::Female);
+        assert_eq!((last.arrivals, last.departures), (0, 1));
+    }
+
+    #[test]
+    fn csv_has_a_row_per_minute() {
+        let mut time_series = new_time_series(60.0);
+        time_series.record_state(0.0, 1, 2, 3, Gender::Female);
+        time_series.add_arrival(0.0);
+        time_series.finish(120.0);
+
+        assert_eq!(
+            time_series.to_csv(),
+            "minute,male_queue_length,female_queue_length,occupied_cabins,arrivals,departures,allowed_gender\n\
+             0,1.000,2.000,3.000,1,0,Female\n\
+             1,1.000,2.000,3.000,0,0,Female\n"
+        );
+    }
+}
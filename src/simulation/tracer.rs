@@ -0,0 +1,218 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Instant;
+use uuid::Uuid;
+
+use super::event::{self, Event};
+
+// What the tracer knows about a single event once it has been routed
+#[derive(Debug)]
+pub struct TraceRecord {
+    pub id: Uuid,
+    pub name: String,
+    pub producer_id: Uuid,
+    pub destination_id: Option<Uuid>,
+    pub trace_id: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
+    pub correlation_id: Option<Uuid>,
+    pub sent_at: Instant,
+    pub routed_at: Option<Instant>,
+    pub received_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct Tracer {
+    pub started_at: Instant,
+    pub records: Vec<TraceRecord>,
+    // Kind of every actor that declared it, e.g. "bathroom", by id
+    pub actors: HashMap<Uuid, String>,
+}
+
+pub fn new_tracer() -> Tracer {
+    return Tracer {
+        started_at: Instant::now(),
+        records: vec![],
+        actors: HashMap::new(),
+    };
+}
+
+impl Tracer {
+    pub fn record(&mut self, msg: &Event) {
+        if let Some(actor) = &msg.actor {
+            self.actors.insert(msg.producer_id, actor.clone());
+        }
+        self.records.push(TraceRecord {
+            id: msg.id,
+            name: msg.name.clone(),
+            producer_id: msg.producer_id,
+            destination_id: msg.destination_id,
+            trace_id: msg.trace_id,
+            parent_id: msg.parent_id,
+            correlation_id: msg.correlation_id,
+            sent_at: msg.sent_at,
+            routed_at: msg.routed_at,
+            received_at: Instant::now(),
+        });
+    }
+
+    // Named after the kind of actor the producer declared when registering with the router (or on
+    // any of its events, for actors that never register)
+    fn track_name(&self, producer_id: Uuid) -> String {
+        let actor = self
+            .actors
+            .get(&producer_id)
+            .map_or("actor", |actor| actor.as_str());
+        return format!("{} {}", actor, producer_id);
+    }
+
+    fn micros_since_start(&self, instant: Instant) -> u64 {
+        return instant
+            .saturating_duration_since(self.started_at)
+            .as_micros() as u64;
+    }
+
+    // Builds a Chrome trace-event JSON document (chrome://tracing, Perfetto, Speedscope...). Each
+    // actor gets its own track, where every event it produced is a slice spanning its hop to the
+    // router. Flow arrows link each event to its parent, and each person's journey is shown as
+    // async "queue" and "bathroom" spans. Timestamps are real microseconds since the tracer started
+    pub fn to_chrome_trace(&self) -> Value {
+        let mut trace_events: Vec<Value> = vec![];
+        let mut tracks: HashMap<Uuid, u64> = HashMap::new();
+        let mut records_by_id: HashMap<Uuid, &TraceRecord> = HashMap::new();
+
+        trace_events.push(json!({
+            "ph": "M", "name": "process_name", "pid": 1, "tid": 0,
+            "args": { "name": "bathroom simulation" }
+        }));
+
+        for record in &self.records {
+            records_by_id.insert(record.id, record);
+
+            if !tracks.contains_key(&record.producer_id) {
+                let tid = tracks.len() as u64 + 1;
+                tracks.insert(record.producer_id, tid);
+                trace_events.push(json!({
+                    "ph": "M", "name": "thread_name", "pid": 1, "tid": tid,
+                    "args": { "name": self.track_name(record.producer_id) }
+                }));
+            }
+
+            let ts = self.micros_since_start(record.sent_at);
+            let routed_at = record.routed_at.unwrap_or(record.received_at);
+            let hop_micros = routed_at
+                .saturating_duration_since(record.sent_at)
+                .as_micros() as u64;
+
+            trace_events.push(json!({
+                "ph": "X",
+                "name": record.name,
+                "cat": "event",
+                "pid": 1,
+                "tid": tracks[&record.producer_id],
+                "ts": ts,
+                "dur": hop_micros.max(1),
+                "args": {
+                    "event_id": record.id.to_string(),
+                    "trace_id": record.trace_id.map(|id| id.to_string()),
+                    "parent_id": record.parent_id.map(|id| id.to_string()),
+                    "correlation_id": record.correlation_id.map(|id| id.to_string()),
+                    "producer_id": record.producer_id.to_string(),
+                    "destination_id": record.destination_id.map(|id| id.to_string()),
+                    "router_hop_micros": hop_micros,
+                    "listener_hop_micros": record.received_at.saturating_duration_since(routed_at).as_micros() as u64,
+                }
+            }));
+        }
+
+        for (flow_id, record) in self.records.iter().enumerate() {
+            let parent = match record.parent_id.and_then(|id| records_by_id.get(&id)) {
+                Some(parent) => parent,
+                None => continue,
+            };
+
+            trace_events.push(json!({
+                "ph": "s", "name": "caused", "cat": "causality", "id": flow_id,
+                "pid": 1, "tid": tracks[&parent.producer_id],
+                "ts": self.micros_since_start(parent.sent_at)
+            }));
+            trace_events.push(json!({
+                "ph": "f", "bp": "e", "name": "caused", "cat": "causality", "id": flow_id,
+                "pid": 1, "tid": tracks[&record.producer_id],
+                "ts": self.micros_since_start(record.sent_at)
+            }));
+        }
+
+        for record in &self.records {
+            let trace_id = match record.trace_id {
+                Some(trace_id) => trace_id.to_string(),
+                None => continue,
+            };
+            let ts = self.micros_since_start(record.sent_at);
+
+            let phases: &[(&str, &str)] = match record.name.as_str() {
+                event::PERSON_JOINED_THE_QUEUE => &[("b", "queue")],
+                event::PERSON_ENTERED_THE_BATHROOM => &[("e", "queue"), ("b", "bathroom")],
+                event::PERSON_LEFT_THE_BATHROOM => &[("e", "bathroom")],
+                _ => &[],
+            };
+
+            phases.iter().for_each(|(ph, name)| {
+                trace_events.push(json!({
+                    "ph": ph, "name": name, "cat": "journey", "id": trace_id,
+                    "pid": 1, "tid": 0, "ts": ts
+                }))
+            });
+        }
+
+        return json!({ "traceEvents": trace_events, "displayTimeUnit": "ms" });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track_names(tracer: &Tracer) -> Vec<String> {
+        return tracer.to_chrome_trace()["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|trace_event| trace_event["name"] == "thread_name")
+            .map(|trace_event| trace_event["args"]["name"].as_str().unwrap().to_string())
+            .collect();
+    }
+
+    #[test]
+    fn tracks_are_named_after_the_actor_producing_their_events() {
+        let mut tracer = new_tracer();
+        let controller_id = Uuid::new_v4();
+        let stranger_id = Uuid::new_v4();
+
+        // The controller's first event is no heartbeat, and isn't about a person either
+        tracer.record(
+            &event::new_event(
+                event::BATHROOM_POLICY_UPDATED.to_string(),
+                controller_id,
+                None,
+                None,
+                None,
+            )
+            .with_actor("controller"),
+        );
+        tracer.record(&event::new_event(
+            event::BATHROOM_SWITCHED_GENDERS.to_string(),
+            stranger_id,
+            None,
+            None,
+            None,
+        ));
+
+        assert_eq!(
+            track_names(&tracer),
+            [
+                format!("controller {}", controller_id),
+                format!("actor {}", stranger_id)
+            ]
+        );
+    }
+}
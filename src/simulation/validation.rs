@@ -0,0 +1,205 @@
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use super::config::{self, Parameters};
+use super::metrics_collector::MetricsCollector;
+use super::queueing::{self, Expectations};
+use super::replication::{self, RunArgs};
+use super::report;
+
+// Compared quantities: how the expectation is read, and how a run's simulated value is
+type Quantity = (
+    &'static str,
+    fn(&Expectations) -> f64,
+    fn(&MetricsCollector) -> f64,
+);
+
+const QUANTITIES: [Quantity; 5] = [
+    (
+        "utilization",
+        |expectations| expectations.utilization,
+        |metrics_collector| metrics_collector.cabin_utilization,
+    ),
+    (
+        "mean_wait",
+        |expectations| expectations.mean_wait,
+        |metrics_collector| metrics_collector.female_personal_total_wait_time.avg,
+    ),
+    (
+        "mean_queue_length",
+        |expectations| expectations.mean_queue_length,
+        |metrics_collector| metrics_collector.female_queue_length.avg,
+    ),
+    (
+        "mean_time_in_system",
+        |expectations| expectations.mean_time_in_system,
+        |metrics_collector| metrics_collector.female_personal_total_time_spent.avg,
+    ),
+    (
+        "mean_number_in_system",
+        |expectations| expectations.mean_number_in_system,
+        |metrics_collector| {
+            metrics_collector.female_queue_length.avg + metrics_collector.occupied_cabins.avg
+        },
+    ),
+];
+
+// Only women arrive, so the bathroom behaves as a plain multi-server queue. It still switches
+// genders: an idle bathroom nobody waits for goes male (BathroomIdle), and back female as soon as a
+// woman arrives (CurrentGenderQueueEmpty). Both switches happen with every cabin free, the second
+// one as her arrival is handled and before she is let in, so nobody ever waits for a switch and no
+// cabin is ever kept free while someone queues, which is all the M/M/c comparison relies on. The
+// usage limits never apply, since they only do while the other gender waits
+fn validation_parameters(bathroom_size: usize) -> Parameters {
+    return Parameters {
+        bathroom_size,
+        female_arrival_share: 1.0,
+        ..config::default_parameters()
+    };
+}
+
+// Entry point of `cargo run -- validate [--cabins <n>] [--replications <n>] [--duration
+// <simulated minutes>] [--seed <base seed>] [--parallel <n>]`, returns the process exit code: 0
+// when every simulated value is consistent with the Allen–Cunneen expectation, 1 otherwise, 2 on
+// usage errors or when the queue is unstable
+pub fn run(args: &[String], running: Arc<AtomicBool>) -> i32 {
+    let mut bathroom_size = super::VALIDATION_BATHROOM_SIZE;
+    let RunArgs {
+        replications,
+        duration,
+        base_seed,
+        parallelism,
+    } = match replication::parse_run_args(
+        args,
+        replication::new_run_args(super::VALIDATION_REPLICATIONS, super::VALIDATION_DURATION),
+        |flag, next_value| match flag {
+            "--cabins" => next_value()
+                .filter(|value| *value > 0)
+                .map(|value| bathroom_size = value as usize)
+                .is_some(),
+            _ => false,
+        },
+    ) {
+        Some(run_args) => run_args,
+        None => {
+            println!("Usage: cargo run -- validate [--cabins <n>] [--replications <n>] [--duration <simulated minutes>] [--seed <base seed>] [--parallel <n>]");
+            return 2;
+        }
+    };
+    let parallelism = parallelism.unwrap_or(1);
+
+    let parameters = validation_parameters(bathroom_size);
+    let (arrival_rate, interarrival_scv) = queueing::arrival_process(&parameters);
+    let (mean_service_time, service_scv) = queueing::service_process();
+
+    println!(
+        "📐 Arrivals: {:.4}/s (ca² {:.3}), service: {:.1}s on average (cs² {:.3}), {} cabins",
+        arrival_rate, interarrival_scv, mean_service_time, service_scv, bathroom_size
+    );
+    let (mmc, allen_cunneen) = match (
+        queueing::mmc(arrival_rate, mean_service_time, bathroom_size),
+        queueing::allen_cunneen(
+            arrival_rate,
+            mean_service_time,
+            bathroom_size,
+            interarrival_scv,
+            service_scv,
+        ),
+    ) {
+        (Some(mmc), Some(allen_cunneen)) => (mmc, allen_cunneen),
+        _ => {
+            println!(
+                "💩 The queue is unstable (utilization {:.2}), there is nothing to expect",
+                arrival_rate * mean_service_time / bathroom_size as f64
+            );
+            return 2;
+        }
+    };
+
+    println!(
+        "📐 Running {} replications of {:?} of simulated time, {} at a time, base seed {}",
+        replications, duration, parallelism, base_seed
+    );
+    let runs = replication::run_replications(
+        parameters,
+        &replication::derive_seeds(base_seed, replications),
+        duration,
+        parallelism,
+        running,
+    );
+    if runs.is_empty() {
+        println!("💩 No replication finished");
+        return 1;
+    }
+
+    let mut rows = vec![
+        "quantity,mm_c,allen_cunneen,simulated_mean,simulated_ci95_half_width,deviation,consistent"
+            .to_string(),
+    ];
+    let mut inconsistencies = 0;
+    println!(
+        "{:<22} {:>12} {:>14} {:>24} {:>10}",
+        "", "M/M/c", "Allen–Cunneen", "simulated (95% CI)", "deviation"
+    );
+    for (name, expected, simulated) in QUANTITIES {
+        let values: Vec<f64> = runs
+            .iter()
+            .map(|(metrics_collector, _)| simulated(metrics_collector))
+            .collect();
+        let simulated = replication::aggregate(&values);
+        let expected_value = expected(&allen_cunneen);
+
+        // Deviation relative to the expectation, which may be nearly 0 for waits of lightly loaded
+        // bathrooms, hence the expectation also counting as met within the confidence interval
+        let deviation = if expected_value != 0.0 {
+            (simulated.mean - expected_value) / expected_value
+        } else {
+            0.0
+        };
+        let consistent = deviation.abs() <= super::VALIDATION_TOLERANCE
+            || (simulated.ci95_low..=simulated.ci95_high).contains(&expected_value);
+        inconsistencies += !consistent as usize;
+
+        println!(
+            "{:<22} {:>12.3} {:>14.3} {:>14.3} ± {:<7.3} {:>+9.1}% {}",
+            name,
+            expected(&mmc),
+            expected_value,
+            simulated.mean,
+            simulated.ci95_half_width,
+            deviation * 100.0,
+            if consistent { "  " } else { "⚠️" }
+        );
+        rows.push(format!(
+            "{},{},{},{},{},{},{}",
+            name,
+            expected(&mmc),
+            expected_value,
+            simulated.mean,
+            simulated.ci95_half_width,
+            deviation,
+            consistent
+        ));
+    }
+
+    match report::create_report_file(&report::new_report_path(), "validation.csv").and_then(
+        |(filename, mut file)| {
+            writeln!(file, "{}", rows.join("\n"))?;
+            return Ok(filename);
+        },
+    ) {
+        Ok(filename) => println!(
+            "📄 Validation CSV file created successfully! File located at {}",
+            filename
+        ),
+        Err(e) => println!("💩 Could not create validation report file: {}", e),
+    };
+
+    println!(
+        "📐 {} quantities deviate from the Allen–Cunneen expectation by more than {:.0}% and beyond their 95% CI",
+        inconsistencies,
+        super::VALIDATION_TOLERANCE * 100.0
+    );
+    return if inconsistencies > 0 { 1 } else { 0 };
+}
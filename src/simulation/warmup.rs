@@ -0,0 +1,206 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// How long the start of a run, biased by the bathroom starting empty, is left out of the statistics
+#[allow(dead_code)] // Variants are picked through `WARM_UP` in `simulation.rs`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WarmUp {
+    // For this long in simulated time
+    SimulatedTime(Duration),
+    // Until this many people left the bathroom
+    ServedPeople(u64),
+    // Until the queue lengths stabilise, see `WarmUpDetector`
+    Automatic,
+}
+
+// The warm-up of a run, as written to the report. Times are in simulated seconds since the start
+#[derive(Debug, Serialize)]
+pub struct WarmUpPeriod {
+    // e.g. "SimulatedTime(600s)", or "None" when nothing is left out
+    pub mode: String,
+    // None if the warm-up never ended, in which case the whole run was counted
+    pub ended_at: Option<f64>,
+    // Counted during the warm-up, then discarded
+    pub excluded_arrivals: u64,
+    pub excluded_departures: u64,
+    #[serde(skip)]
+    warm_up: Option<WarmUp>,
+    #[serde(skip)]
+    detector: Option<WarmUpDetector>,
+}
+
+pub fn new_warm_up_period(warm_up: Option<WarmUp>) -> WarmUpPeriod {
+    return WarmUpPeriod {
+        mode: match warm_up {
+            Some(warm_up) => format!("{:?}", warm_up),
+            None => "None".to_string(),
+        },
+        ended_at: match warm_up {
+            Some(_) => None,
+            None => Some(0.0),
+        },
+        excluded_arrivals: 0,
+        excluded_departures: 0,
+        warm_up,
+        detector: match warm_up {
+            Some(WarmUp::Automatic) => Some(new_warm_up_detector(
+                super::WARM_UP_DETECTOR_WINDOW.as_secs_f64(),
+                super::WARM_UP_DETECTOR_WINDOWS,
+                super::WARM_UP_DETECTOR_TOLERANCE,
+            )),
+            _ => None,
+        },
+    };
+}
+
+impl WarmUpPeriod {
+    pub fn is_over(&self) -> bool {
+        return self.ended_at.is_some();
+    }
+
+    // Feeds the automatic detector with the length of both queues together
+    pub fn record_queue_length(&mut self, at: f64, length: f64) {
+        if let Some(detector) = self.detector.as_mut() {
+            detector.record(at, length);
+        }
+    }
+
+    // Whether the warm-up is over at `at`, once `served` people left the bathroom
+    pub fn should_end(&self, at: f64, served: u64) -> bool {
+        return match self.warm_up {
+            Some(WarmUp::SimulatedTime(duration)) => at >= duration.as_secs_f64(),
+            Some(WarmUp::ServedPeople(people)) => served >= people,
+            Some(WarmUp::Automatic) => self
+                .detector
+                .as_ref()
+                .is_some_and(|detector| detector.is_stable()),
+            None => true,
+        };
+    }
+
+    pub fn end(&mut self, at: f64, arrivals: u64, departures: u64) {
+        self.ended_at = Some(at);
+        self.excluded_arrivals = arrivals;
+        self.excluded_departures = departures;
+    }
+}
+
+// Tells when the queues stopped growing (or shrinking): the time-weighted average queue length is
+// taken over consecutive windows of simulated time, and the last `windows` averages must lie
+// within `tolerance` of their mean. Averages under one person count as one, so that small
+// fluctuations of nearly empty queues don't keep the warm-up going forever
+#[derive(Clone, Debug)]
+pub struct WarmUpDetector {
+    window: f64,
+    windows: usize,
+    tolerance: f64,
+    window_start: f64,
+    // Sum of length * simulated seconds it held for, since the start of the current window
+    integral: f64,
+    length: f64,
+    changed_at: f64,
+    averages: VecDeque<f64>,
+}
+
+pub fn new_warm_up_detector(window: f64, windows: usize, tolerance: f64) -> WarmUpDetector {
+    return WarmUpDetector {
+        window,
+        windows: windows.max(2),
+        tolerance,
+        window_start: 0.0,
+        integral: 0.0,
+        // The bathroom starts empty
+        length: 0.0,
+        changed_at: 0.0,
+        averages: VecDeque::new(),
+    };
+}
+
+impl WarmUpDetector {
+    pub fn record(&mut self, at: f64, length: f64) {
+        let at = at.max(self.changed_at);
+
+        while at >= self.window_start + self.window {
+            let window_end = self.window_start + self.window;
+            self.integral += self.length * (window_end - self.changed_at);
+            self.averages.push_back(self.integral / self.window);
+            if self.averages.len() > self.windows {
+                self.averages.pop_front();
+            }
+
+            self.integral = 0.0;
+            self.changed_at = window_end;
+            self.window_start = window_end;
+        }
+
+        self.integral += self.length * (at - self.changed_at);
+        self.length = length;
+        self.changed_at = at;
+    }
+
+    pub fn is_stable(&self) -> bool {
+        if self.averages.len() < self.windows {
+            return false;
+        }
+
+        let min = self.averages.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = self
+            .averages
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let mean = self.averages.iter().sum::<f64>() / self.averages.len() as f64;
+
+        return max - min <= self.tolerance * mean.max(1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warm_ups_end_after_their_simulated_time() {
+        let warm_up = new_warm_up_period(Some(WarmUp::SimulatedTime(Duration::from_secs(600))));
+
+        assert!(!warm_up.is_over());
+        assert!(!warm_up.should_end(599.0, 100));
+        assert!(warm_up.should_end(600.0, 0));
+    }
+
+    #[test]
+    fn warm_ups_end_once_enough_people_were_served() {
+        let warm_up = new_warm_up_period(Some(WarmUp::ServedPeople(10)));
+
+        assert!(!warm_up.should_end(10_000.0, 9));
+        assert!(warm_up.should_end(0.0, 10));
+    }
+
+    #[test]
+    fn automatic_warm_ups_end_once_the_queues_stabilise() {
+        let mut warm_up = new_warm_up_period(Some(WarmUp::Automatic));
+        let window = crate::simulation::WARM_UP_DETECTOR_WINDOW.as_secs_f64();
+
+        // The queues grow over the first windows...
+        for (i, length) in [0.0, 5.0, 10.0].iter().enumerate() {
+            warm_up.record_queue_length(i as f64 * window, *length);
+        }
+        warm_up.record_queue_length(3.0 * window, 10.0);
+        assert!(!warm_up.should_end(3.0 * window, 0));
+
+        // ...then hold steady for as many windows as the detector compares
+        let windows = crate::simulation::WARM_UP_DETECTOR_WINDOWS as f64;
+        warm_up.record_queue_length((3.0 + windows) * window, 10.0);
+        assert!(warm_up.should_end((3.0 + windows) * window, 0));
+    }
+
+    #[test]
+    fn runs_without_warm_up_count_from_the_start() {
+        let warm_up = new_warm_up_period(None);
+
+        assert!(warm_up.is_over());
+        assert_eq!(warm_up.ended_at, Some(0.0));
+        assert!(warm_up.should_end(0.0, 0));
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use uuid::Uuid;
+
+use super::bathroom::Bathroom;
+use super::channel::Sender;
+use super::event::{self, Event};
+use super::metrics_collector::simulated_seconds;
+use super::person::Gender;
+
+// Sends HEARTBEAT events on behalf of an actor, from its loop, so that the watchdog can tell when
+// it stopped responding
+pub struct Heartbeat {
+    pub actor: String,
+    pub actor_id: Uuid,
+    pub beat_at: Option<Instant>,
+}
+
+pub fn new_heartbeat(actor: &str, actor_id: Uuid) -> Heartbeat {
+    return Heartbeat {
+        actor: actor.to_string(),
+        actor_id,
+        beat_at: None,
+    };
+}
+
+impl Heartbeat {
+    // Whether WATCHDOG_HEARTBEAT_INTERVAL (in simulated time) went by since the last beat, and the
+    // watchdog is enabled
+    pub fn is_due(&self) -> bool {
+        return super::ENABLE_WATCHDOG
+            && !self.beat_at.is_some_and(|beat_at| {
+                beat_at.elapsed().mul_f64(super::TIME_SCALE) < super::WATCHDOG_HEARTBEAT_INTERVAL
+            });
+    }
+
+    // Beats if it is due
+    pub fn beat(&mut self, router_tx: &Sender) {
+        if !self.is_due() {
+            return;
+        }
+
+        let mut heartbeat_event = event::new_event(
+            event::HEARTBEAT.to_string(),
+            self.actor_id,
+            None,
+            None,
+            None,
+        );
+        heartbeat_event.actor = Some(self.actor.clone());
+        let _ = router_tx.send(heartbeat_event);
+        self.beat_at = Some(Instant::now());
+    }
+}
+
+// Liveness properties of the simulation, checked against the latest state of the bathroom and
+// heartbeats of the actors
+pub struct Watchdog {
+    // Latest snapshot of the bathroom carried by an event, and when it was received
+    pub bathroom: Option<Bathroom>,
+    pub bathroom_seen_at: Option<Instant>,
+    // Since when the other gender has been waiting while every cabin is free, without a switch
+    pub stalled_since: Option<Instant>,
+    // Kind and last heartbeat of every actor that ever beat
+    pub heartbeats: HashMap<Uuid, (String, Instant)>,
+    // What was already reported, so each failure is only reported once: people who waited too
+    // long, actors still silent, and whether the current stall was
+    pub reported_waits: HashSet<Uuid>,
+    pub silent_actors: HashSet<Uuid>,
+    pub reported_stall: bool,
+    // Every failure found, in the order it was found
+    pub failures: Vec<String>,
+}
+
+pub fn new_watchdog() -> Watchdog {
+    return Watchdog {
+        bathroom: None,
+        bathroom_seen_at: None,
+        stalled_since: None,
+        heartbeats: HashMap::new(),
+        reported_waits: HashSet::new(),
+        silent_actors: HashSet::new(),
+        reported_stall: false,
+        failures: vec![],
+    };
+}
+
+impl Watchdog {
+    pub fn record(&mut self, msg: &Event) {
+        if msg.name == event::HEARTBEAT {
+            let actor = msg.actor.clone().unwrap_or_default();
+            self.heartbeats
+                .insert(msg.producer_id, (actor, msg.sent_at));
+            self.silent_actors.remove(&msg.producer_id);
+        }
+
+        // Only the bathroom's own events carry its latest state, others may carry copies of it
+        if let Some(bathroom) = msg
+            .bathroom_snapshot
+            .as_ref()
+            .filter(|bathroom| bathroom.id == msg.producer_id)
+        {
+            // Switch events carry the state from before the switch, which is no stall
+            let stalled = msg.name != event::BATHROOM_SWITCHED_GENDERS
+                && bathroom.cabins.iter().all(|cabin| cabin.is_none())
+                && match bathroom.allowed_gender {
+                    Gender::Male => !bathroom.female_queue.is_empty(),
+                    Gender::Female => !bathroom.male_queue.is_empty(),
+                };
+            if !stalled {
+                self.stalled_since = None;
+                self.reported_stall = false;
+            } else if self.stalled_since.is_none() {
+                self.stalled_since = Some(msg.sent_at);
+            }
+
+            self.bathroom = Some(bathroom.clone());
+            self.bathroom_seen_at = Some(msg.sent_at);
+        }
+    }
+
+    // The failures found at `at` that weren't reported before
+    pub fn check(&mut self, at: Instant) -> Vec<String> {
+        let mut failures = vec![];
+
+        if let Some(bathroom) = &self.bathroom {
+            for person in bathroom
+                .male_queue
+                .iter()
+                .chain(bathroom.female_queue.iter())
+            {
+                let wait = match person.joined_queue_at {
+                    Some(joined_queue_at) => simulated_seconds(joined_queue_at, at),
+                    None => continue,
+                };
+                if wait > super::WATCHDOG_MAX_WAIT.as_secs_f64()
+                    && self.reported_waits.insert(person.id)
+                {
+                    failures.push(format!(
+                        "person {} of gender {} has been waiting for {:.0}s, over {:?}",
+                        person.id,
+                        person.gender,
+                        wait,
+                        super::WATCHDOG_MAX_WAIT
+                    ));
+                }
+            }
+
+            if let Some(stalled_since) = self.stalled_since {
+                let stall = simulated_seconds(stalled_since, at);
+                if stall > super::WATCHDOG_SWITCH_DELAY.as_secs_f64() && !self.reported_stall {
+                    self.reported_stall = true;
+                    failures.push(format!(
+                        "bathroom {} hasn't switched from {} for {:.0}s, although the other gender is waiting and every cabin is free",
+                        bathroom.id, bathroom.allowed_gender, stall
+                    ));
+                }
+            }
+        }
+
+        for (actor_id, (actor, beat_at)) in &self.heartbeats {
+            let silence = simulated_seconds(*beat_at, at);
+            if silence > super::WATCHDOG_HEARTBEAT_TIMEOUT.as_secs_f64()
+                && self.silent_actors.insert(*actor_id)
+            {
+                failures.push(format!(
+                    "{} {} stopped responding, its last heartbeat was {:.0}s ago",
+                    actor, actor_id, silence
+                ));
+            }
+        }
+
+        self.failures.extend(failures.iter().cloned());
+        return failures;
+    }
+
+    // Diagnostic dump of the latest known state of the bathroom
+    pub fn dump(&self, at: Instant) -> String {
+        return match (&self.bathroom, self.bathroom_seen_at) {
+            (Some(bathroom), Some(seen_at)) => format!(
+                "Latest state of the bathroom, as of {:.0}s ago (policy: max use time threshold {:?}, max uses per turn {}):\n{}",
+                simulated_seconds(seen_at, at),
+                bathroom.policy.max_use_time_threshold,
+                bathroom.policy.max_uses_per_turn,
+                bathroom
+            ),
+            _ => "The state of the bathroom is unknown, no event carried it yet".to_string(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::bathroom::new_bathroom;
+    use crate::simulation::person::new_person;
+    use crate::simulation::{
+        config, TIME_SCALE, WATCHDOG_HEARTBEAT_TIMEOUT, WATCHDOG_MAX_WAIT, WATCHDOG_SWITCH_DELAY,
+    };
+    use std::time::Duration;
+
+    // The instant `simulated` (in simulated time) after `from`
+    fn after(from: Instant, simulated: Duration) -> Instant {
+        return from + simulated.div_f64(TIME_SCALE);
+    }
+
+    // An event of the bathroom carrying its state, sent at `sent_at`
+    fn bathroom_event(name: &str, bathroom: &Bathroom, sent_at: Instant) -> Event {
+        let mut bathroom_event = event::new_event(
+            name.to_string(),
+            bathroom.id,
+            None,
+            None,
+            Some(bathroom.clone()),
+        );
+        bathroom_event.sent_at = sent_at;
+        return bathroom_event;
+    }
+
+    fn heartbeat_event(actor_id: Uuid, sent_at: Instant) -> Event {
+        let mut heartbeat_event =
+            event::new_event(event::HEARTBEAT.to_string(), actor_id, None, None, None);
+        heartbeat_event.actor = Some("person".to_string());
+        heartbeat_event.sent_at = sent_at;
+        return heartbeat_event;
+    }
+
+    // A male bathroom with a woman who joined the queue at `joined_queue_at`, and a man in a cabin
+    fn busy_bathroom(joined_queue_at: Instant) -> Bathroom {
+        let mut bathroom = new_bathroom(Gender::Male, &config::default_parameters());
+        let mut woman = new_person(Gender::Female);
+        woman.joined_queue_at = Some(joined_queue_at);
+        bathroom.female_queue.push(woman);
+        bathroom.cabins[0] = Some(new_person(Gender::Male));
+        return bathroom;
+    }
+
+    #[test]
+    fn healthy_simulations_pass_every_check() {
+        let started_at = Instant::now();
+        let actor_id = Uuid::new_v4();
+        let mut watchdog = new_watchdog();
+
+        watchdog.record(&heartbeat_event(actor_id, started_at));
+        watchdog.record(&bathroom_event(
+            event::PERSON_ENTERED_THE_BATHROOM,
+            &busy_bathroom(started_at),
+            started_at,
+        ));
+
+        let failures = watchdog.check(after(started_at, WATCHDOG_SWITCH_DELAY * 2));
+        assert!(failures.is_empty(), "{:?}", failures);
+        assert!(watchdog.failures.is_empty());
+    }
+
+    #[test]
+    fn people_waiting_too_long_are_reported_once() {
+        let started_at = Instant::now();
+        let bathroom = busy_bathroom(started_at);
+        let mut watchdog = new_watchdog();
+        watchdog.record(&bathroom_event(
+            event::PERSON_JOINED_THE_QUEUE,
+            &bathroom,
+            started_at,
+        ));
+
+        let late = after(started_at, WATCHDOG_MAX_WAIT + Duration::from_secs(60));
+        let failures = watchdog.check(late);
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].contains(&bathroom.female_queue[0].id.to_string()));
+        assert!(watchdog.check(late).is_empty());
+    }
+
+    #[test]
+    fn bathrooms_not_switching_to_a_waiting_gender_are_stalled() {
+        let started_at = Instant::now();
+        let mut bathroom = busy_bathroom(started_at);
+        bathroom.cabins[0] = None;
+        let mut watchdog = new_watchdog();
+        watchdog.record(&bathroom_event(
+            event::PERSON_LEFT_THE_BATHROOM,
+            &bathroom,
+            started_at,
+        ));
+
+        assert!(watchdog
+            .check(after(started_at, WATCHDOG_SWITCH_DELAY / 2))
+            .is_empty());
+        let failures = watchdog.check(after(started_at, WATCHDOG_SWITCH_DELAY * 2));
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].contains("hasn't switched"));
+
+        // Switching ends the stall
+        watchdog.record(&bathroom_event(
+            event::BATHROOM_SWITCHED_GENDERS,
+            &bathroom,
+            after(started_at, WATCHDOG_SWITCH_DELAY * 2),
+        ));
+        assert_eq!(watchdog.stalled_since, None);
+    }
+
+    #[test]
+    fn actors_without_heartbeat_are_silent_until_they_beat_again() {
+        let started_at = Instant::now();
+        let actor_id = Uuid::new_v4();
+        let mut watchdog = new_watchdog();
+        watchdog.record(&heartbeat_event(actor_id, started_at));
+
+        let late = after(
+            started_at,
+            WATCHDOG_HEARTBEAT_TIMEOUT + Duration::from_secs(60),
+        );
+        let failures = watchdog.check(late);
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].contains(&actor_id.to_string()));
+        assert!(watchdog.check(late).is_empty());
+
+        watchdog.record(&heartbeat_event(actor_id, late));
+        assert!(watchdog.check(late).is_empty());
+        assert!(watchdog.silent_actors.is_empty());
+    }
+}